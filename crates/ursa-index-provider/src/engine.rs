@@ -7,9 +7,13 @@ use crate::{
 use bytes::Bytes;
 use db::Store;
 use libipld_core::ipld::Ipld;
-use tokio::sync::{
-    mpsc::{unbounded_channel, UnboundedReceiver as Receiver, UnboundedSender as Sender},
-    oneshot,
+use tokio::{
+    select,
+    sync::{
+        broadcast::error::RecvError,
+        mpsc::{unbounded_channel, UnboundedReceiver as Receiver, UnboundedSender as Sender},
+        oneshot,
+    },
 };
 use ursa_network::{GossipsubMessage, NetworkCommand};
 
@@ -26,7 +30,6 @@ use tracing::{error, info, warn};
 use ursa_store::UrsaStore;
 
 type CommandOneShotSender<T> = oneshot::Sender<Result<T, Error>>;
-type CommandOneShotReceiver<T> = oneshot::Receiver<Result<T, Error>>;
 
 // handlers
 async fn head<S: Blockstore + Store + Sync + Send + 'static>(
@@ -68,7 +71,7 @@ pub enum ProviderCommand {
     /// remove multihashes from advertisment when evicted by a node
     Remove {
         context_id: Vec<u8>,
-        sender: CommandOneShotReceiver<()>,
+        sender: CommandOneShotSender<()>,
     },
 }
 
@@ -147,64 +150,90 @@ where
     pub async fn start(mut self) -> Result<()> {
         info!("Index provider engine starting up!");
 
+        let mut evictions = self.store.subscribe_evictions();
         loop {
-            if let Some(command) = self.command_receiver.recv().await {
-                match command {
-                    ProviderCommand::Put {
-                        context_id,
-                        sender,
-                        size,
-                    } => {
-                        let cid = Cid::try_from(context_id).unwrap();
-                        if let Err(e) = sender.send(Ok(())) {
-                            error!("Provider Engine: {:?}", e);
+            select! {
+                command = self.command_receiver.recv() => {
+                    match command {
+                        Some(ProviderCommand::Put {
+                            context_id,
+                            sender,
+                            size,
+                        }) => {
+                            let cid = Cid::try_from(context_id).unwrap();
+                            if let Err(e) = sender.send(Ok(())) {
+                                error!("Provider Engine: {:?}", e);
+                            }
+                            if let Err(e) = self.publish_local(cid, size).await {
+                                error!("Error while publishing the advertisement locally: {:?}", e)
+                            } else {
+                                self.announce().await;
+                            }
                         }
-                        let peer_id = PeerId::from(self.provider.keypair().public());
-
-                        if let Err(e) = self.publish_local(cid, size).await {
-                            error!("Error while publishing the advertisement locally: {:?}", e)
-                        } else {
-                            match self
-                                .provider
-                                .create_announce_message(peer_id, self.domain.clone())
-                            {
-                                Ok(announce_message) => {
-                                    if let Err(e) = self
-                                        .gossip_announce(announce_message.clone(), peer_id)
-                                        .await
-                                    {
-                                        warn!("there was an error while gossiping the announcement, will try to announce via http {:?}", e);
-                                        self.http_announce(announce_message).await;
-                                    }
-                                }
-                                Err(e) => warn!(
-                                    "There was a problem parsing announcement message: {:?}",
-                                    e
-                                ),
+                        Some(ProviderCommand::Remove { context_id, sender }) => {
+                            let cid = Cid::try_from(context_id).unwrap();
+                            if let Err(e) = sender.send(Ok(())) {
+                                error!("Provider Engine: {:?}", e);
                             }
+                            self.remove(cid).await;
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                evicted = evictions.recv() => {
+                    match evicted {
+                        Ok(cid) => self.remove(cid).await,
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("Provider Engine: missed {missed} evicted roots, they stay advertised")
                         }
+                        // the store, which holds the sender, outlives the engine
+                        Err(RecvError::Closed) => {}
                     }
-                    // TODO: implement when cache eviction is implemented
-                    ProviderCommand::Remove { .. } => todo!(),
                 }
             }
         }
     }
 
-    pub async fn publish_local(&mut self, root_cid: Cid, file_size: u64) -> Result<()> {
+    /// Advertise the removal of a root no longer cached.
+    async fn remove(&mut self, cid: Cid) {
+        if let Err(e) = self.publish_remove(cid).await {
+            error!(
+                "Error while publishing the removal advertisement locally: {:?}",
+                e
+            )
+        } else {
+            self.announce().await;
+        }
+    }
+
+    /// Announce the current head to the indexer, over gossip or http as a fallback.
+    async fn announce(&mut self) {
+        let peer_id = PeerId::from(self.provider.keypair().public());
+        match self
+            .provider
+            .create_announce_message(peer_id, self.domain.clone())
+        {
+            Ok(announce_message) => {
+                if let Err(e) = self
+                    .gossip_announce(announce_message.clone(), peer_id)
+                    .await
+                {
+                    warn!("there was an error while gossiping the announcement, will try to announce via http {:?}", e);
+                    self.http_announce(announce_message).await;
+                }
+            }
+            Err(e) => warn!("There was a problem parsing announcement message: {:?}", e),
+        }
+    }
+
+    /// Addresses from which the advertised content is retrievable.
+    async fn addresses(&self) -> Result<Vec<String>> {
         let (listener_addresses_sender, listener_addresses_receiver) = oneshot::channel();
         self.network_command_sender
             .send(NetworkCommand::GetListenerAddresses {
                 sender: listener_addresses_sender,
             })?;
 
-        let context_id = root_cid.to_bytes();
-        info!(
-            "Creating advertisement for cids under root cid: {:?}.",
-            root_cid
-        );
-        let peer_id = PeerId::from(self.provider.keypair().public());
-
         let listener_addresses = listener_addresses_receiver.await?;
         let mut addresses = vec![self.server_address.to_string()];
         for la in listener_addresses {
@@ -219,6 +248,18 @@ where
             }
             addresses.push(address.to_string())
         }
+        Ok(addresses)
+    }
+
+    pub async fn publish_local(&mut self, root_cid: Cid, file_size: u64) -> Result<()> {
+        let context_id = root_cid.to_bytes();
+        info!(
+            "Creating advertisement for cids under root cid: {:?}.",
+            root_cid
+        );
+        let peer_id = PeerId::from(self.provider.keypair().public());
+        let addresses = self.addresses().await?;
+
         let advertisement = Advertisement::new(
             context_id.clone(),
             peer_id,
@@ -228,19 +269,18 @@ where
         );
         let provider_id = self.provider.create(advertisement)?;
 
-        let dag = self.store.dag_traversal(&(root_cid))?;
-        let entries = dag
-            .iter()
-            .map(|d| return Ipld::Bytes(d.0.hash().to_bytes()))
-            .collect::<Vec<Ipld>>();
-        let chunks: Vec<&[Ipld]> = entries.chunks(MAX_ENTRIES).collect();
-
         info!("Inserting Index chunks.");
-        for chunk in chunks.iter() {
-            let entries_bytes = fvm_ipld_encoding::to_vec(&chunk)?;
-            self.provider
-                .add_chunk(entries_bytes, provider_id)
-                .expect(" adding chunk to advertisement should not fail!");
+        let mut entries = Vec::with_capacity(MAX_ENTRIES);
+        for block in self.store.dag_iter(&root_cid) {
+            let (cid, _) = block?;
+            entries.push(Ipld::Bytes(cid.hash().to_bytes()));
+            if entries.len() == MAX_ENTRIES {
+                self.add_chunk(&entries, provider_id)?;
+                entries.clear();
+            }
+        }
+        if !entries.is_empty() {
+            self.add_chunk(&entries, provider_id)?;
         }
         info!("Publishing the advertisement now");
         self.provider
@@ -250,6 +290,30 @@ where
         Ok(())
     }
 
+    fn add_chunk(&mut self, entries: &[Ipld], provider_id: usize) -> Result<()> {
+        let entries_bytes = fvm_ipld_encoding::to_vec(&entries)?;
+        self.provider
+            .add_chunk(entries_bytes, provider_id)
+            .expect(" adding chunk to advertisement should not fail!");
+        Ok(())
+    }
+
+    /// Publish an advertisement removing the content under a root cid.
+    pub async fn publish_remove(&mut self, root_cid: Cid) -> Result<()> {
+        info!(
+            "Creating removal advertisement for root cid: {:?}.",
+            root_cid
+        );
+        let peer_id = PeerId::from(self.provider.keypair().public());
+        let addresses = self.addresses().await?;
+
+        let advertisement = Advertisement::new(root_cid.to_bytes(), peer_id, addresses, true, 0);
+        let provider_id = self.provider.create(advertisement)?;
+        self.provider.publish(provider_id)?;
+
+        Ok(())
+    }
+
     pub async fn gossip_announce(&mut self, data: Vec<u8>, peer_id: PeerId) -> Result<()> {
         let (sender, receiver) = oneshot::channel();
         let topic = TopicHash::from_raw("indexer/ingest/mainnet");
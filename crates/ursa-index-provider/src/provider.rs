@@ -28,6 +28,20 @@ use ursa_store::{BlockstoreExt, UrsaStore};
 
 pub const HEAD_KEY: &str = "head";
 
+/// Read the head of the advertisement chain persisted in a provider store.
+pub fn read_head<S: Store>(db: &S) -> Result<Option<Cid>> {
+    match db.read(HEAD_KEY)? {
+        Some(bytes) => Ok(Some(Cid::try_from(bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Persist the head of the advertisement chain in a provider store.
+pub fn write_head<S: Store>(db: &S, head: &Cid) -> Result<()> {
+    db.write(HEAD_KEY, head.to_bytes())?;
+    Ok(())
+}
+
 pub struct Provider<S> {
     head: Arc<RwLock<Option<Cid>>>,
     keypair: Keypair,
@@ -40,11 +54,7 @@ where
     S: Blockstore + Store + Sync + Send + 'static,
 {
     pub fn new(keypair: Keypair, store: Arc<UrsaStore<S>>) -> Self {
-        let head = store
-            .blockstore()
-            .read(HEAD_KEY)
-            .expect("reading from store should not fail")
-            .map(|h| Cid::try_from(h).unwrap());
+        let head = read_head(store.blockstore()).expect("reading from store should not fail");
         Provider {
             keypair,
             store,
@@ -150,7 +160,7 @@ where
                 .store
                 .blockstore()
                 .put_obj(&ipld_ad, Code::Blake2b256)?;
-            self.store.db.write(HEAD_KEY, cid.to_bytes())?;
+            write_head(self.store.blockstore(), &cid)?;
             *head = Some(cid);
             return Ok(ad);
         }
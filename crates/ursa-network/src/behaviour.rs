@@ -12,6 +12,7 @@
 //! - [`RequestResponse`] A `NetworkBehaviour` that implements a generic
 //!   request/response protocol or protocol family, whereby each request is
 //!   sent over a new substream on a connection.
+//! - [`CarCodec`] A request/response protocol streaming dags as car files.
 
 use anyhow::Result;
 use db::Store;
@@ -28,7 +29,11 @@ use libp2p::{
     },
     identify::{Behaviour as Identify, Config as IdentifyConfig},
     identity::Keypair,
-    kad::{store::MemoryStore, Kademlia, KademliaConfig},
+    kad::{
+        record::Key,
+        store::{Error as RecordStoreError, MemoryStore, MemoryStoreConfig},
+        Kademlia, KademliaConfig, QueryId as KadQueryId,
+    },
     mdns::tokio::Behaviour as Mdns,
     multiaddr::Protocol,
     ping::Behaviour as Ping,
@@ -52,12 +57,17 @@ use ursa_store::GraphSyncStorage;
 
 use crate::gossipsub::build_gossipsub;
 use crate::{
-    codec::protocol::{UrsaExchangeCodec, UrsaProtocol},
+    codec::{
+        car::{CarCodec, CarProtocol},
+        protocol::{UrsaExchangeCodec, UrsaProtocol},
+    },
     config::NetworkConfig,
 };
 
 pub const IPFS_PROTOCOL: &str = "ipfs/0.1.0";
 pub const KAD_PROTOCOL: &[u8] = b"/ursa/kad/0.0.1";
+/// Maximum number of cids the node publishes provider records for.
+pub const MAX_PROVIDED_KEYS: usize = 1 << 20;
 
 fn ursa_agent() -> String {
     format!("ursa/{}", env!("CARGO_PKG_VERSION"))
@@ -103,6 +113,9 @@ where
     /// request/response protocol implementation for [`UrsaProtocol`]
     pub(crate) request_response: RequestResponse<UrsaExchangeCodec>,
 
+    /// Direct car transfers of dags between peers.
+    pub(crate) car_exchange: RequestResponse<CarCodec<S>>,
+
     /// Graphsync for efficiently exchanging data between blocks between peers.
     pub(crate) graphsync: GraphSync<GraphSyncStorage<S>>,
 }
@@ -117,6 +130,7 @@ where
         config: &NetworkConfig,
         bitswap_store: B,
         graphsync_store: GraphSyncStorage<S>,
+        car_codec: CarCodec<S>,
         relay_client: Option<libp2p::relay::v2::client::Client>,
         peers: &mut HashSet<PeerId>,
     ) -> Self {
@@ -146,17 +160,30 @@ where
                 .with_agent_version(ursa_agent()),
         );
 
+        let idle_timeout = Duration::from_secs(config.idle_connection_timeout);
+
         let request_response = {
             let mut cfg = RequestResponseConfig::default();
+            cfg.set_request_timeout(Duration::from_secs(config.request_timeout))
+                .set_connection_keep_alive(idle_timeout);
 
-            // todo(botch): calculate an upper limit to allow for large files
-            cfg.set_request_timeout(Duration::from_secs(60));
-
-            let protocols = iter::once((UrsaProtocol, ProtocolSupport::Full));
+            let protocols = UrsaProtocol::SUPPORTED
+                .into_iter()
+                .map(|protocol| (protocol, ProtocolSupport::Full));
 
             RequestResponse::new(UrsaExchangeCodec, protocols, cfg)
         };
 
+        let car_exchange = {
+            let mut cfg = RequestResponseConfig::default();
+            cfg.set_request_timeout(Duration::from_secs(config.car_request_timeout))
+                .set_connection_keep_alive(idle_timeout);
+
+            let protocols = iter::once((CarProtocol, ProtocolSupport::Full));
+
+            RequestResponse::new(car_codec, protocols, cfg)
+        };
+
         let autonat = config
             .autonat
             .then(|| {
@@ -193,12 +220,17 @@ where
 
         // setup the kademlia behaviour
         let mut kad = {
-            let store = MemoryStore::new(local_peer_id);
+            let store_config = MemoryStoreConfig {
+                max_provided_keys: MAX_PROVIDED_KEYS,
+                ..Default::default()
+            };
+            let store = MemoryStore::with_config(local_peer_id, store_config);
             let replication_factor = NonZeroUsize::new(config.kad_replication_factor).unwrap();
             let mut kad_config = KademliaConfig::default();
             kad_config
                 .set_protocol_names(vec![Cow::from(KAD_PROTOCOL)])
-                .set_replication_factor(replication_factor);
+                .set_replication_factor(replication_factor)
+                .set_connection_idle_timeout(idle_timeout);
 
             Kademlia::with_config(local_peer_id, store, kad_config.clone())
         };
@@ -240,6 +272,7 @@ where
             kad,
             mdns,
             request_response,
+            car_exchange,
             graphsync,
         }
     }
@@ -248,6 +281,7 @@ where
         self.bitswap.add_address(peer_id, addr.clone());
         self.kad.add_address(peer_id, addr.clone());
         self.request_response.add_address(peer_id, addr.clone());
+        self.car_exchange.add_address(peer_id, addr.clone());
         self.graphsync.add_address(peer_id, addr);
     }
 
@@ -286,4 +320,19 @@ where
     ) -> Result<libp2p_bitswap::QueryId> {
         Ok(self.bitswap.sync(cid, providers, iter::once(cid)))
     }
+
+    /// Publish a provider record for `cid` to the closest peers of its key.
+    pub fn start_providing(&mut self, cid: &Cid) -> Result<KadQueryId, RecordStoreError> {
+        self.kad.start_providing(Key::new(&cid.to_bytes()))
+    }
+
+    /// Stop publishing the provider record for `cid`.
+    pub fn stop_providing(&mut self, cid: &Cid) {
+        self.kad.stop_providing(&Key::new(&cid.to_bytes()));
+    }
+
+    /// Look up the peers providing `cid`.
+    pub fn get_providers(&mut self, cid: &Cid) -> KadQueryId {
+        self.kad.get_providers(Key::new(&cid.to_bytes()))
+    }
 }
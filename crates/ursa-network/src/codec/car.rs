@@ -0,0 +1,413 @@
+//! Direct CAR transfer between peers.
+//!
+//! A request names a root cid and how deep to walk its dag. The responder
+//! answers with a status byte and, if it has the root, streams the dag as a
+//! CARv1 file straight from its store. The requester writes the blocks into
+//! its own store as they are read, so a transfer is not bounded by the size
+//! of a single response, only by [`MAX_CAR_SIZE`]. Each section is refused
+//! before being read if it is larger than a block the store accepts, and the
+//! blocks are written in batches on the blocking thread pool.
+//!
+//! Only a depth limit is supported, not a full ipld selector: the whole dag,
+//! or the blocks up to a number of links below the root. The requester only
+//! accepts a car with the requested root as its single root, and answers once
+//! every requested block is in its store.
+
+use anyhow::bail;
+use async_trait::async_trait;
+use bincode::Options;
+use db::Store;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::Cid;
+use libp2p::{
+    core::{
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        ProtocolName,
+    },
+    request_response::RequestResponseCodec,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, sync::Arc};
+use tokio::task;
+use tracing::warn;
+use ursa_store::{ContentSource, UrsaStore};
+
+/// Max request size in bytes
+const MAX_REQUEST_SIZE: usize = 1024;
+/// Max size in bytes of a car stream.
+pub const MAX_CAR_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// Bytes of blocks read before they are written to the store.
+const WRITE_BATCH_SIZE: usize = 4 * 1024 * 1024;
+
+pub const CAR_PROTOCOL_NAME: &[u8] = b"/ursa/car/0.1.0";
+
+/// Status byte sent before the car stream.
+const STATUS_NOT_FOUND: u8 = 0;
+const STATUS_CAR: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct CarProtocol;
+
+impl ProtocolName for CarProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        CAR_PROTOCOL_NAME
+    }
+}
+
+/// The dag to transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CarRequest {
+    pub root: Cid,
+    /// How many links below the root to walk, the whole dag if unset.
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CarResponse {
+    /// Stream the requested dag, sent by the responder.
+    Dag(CarRequest),
+    /// The responder does not have the root.
+    NotFound,
+    /// The roots of the car stream written to the store, and the bytes read
+    /// from the peer, read by the requester.
+    Stored { roots: Vec<Cid>, bytes: u64 },
+}
+
+/// Codec reading car streams into, and writing them from, the store.
+pub struct CarCodec<S> {
+    store: Arc<UrsaStore<S>>,
+    /// The request written on the outbound stream of this codec, which the
+    /// response is checked against.
+    request: Option<CarRequest>,
+}
+
+impl<S> CarCodec<S> {
+    pub fn new(store: Arc<UrsaStore<S>>) -> Self {
+        Self {
+            store,
+            request: None,
+        }
+    }
+}
+
+impl<S> Clone for CarCodec<S> {
+    fn clone(&self) -> Self {
+        Self::new(Arc::clone(&self.store))
+    }
+}
+
+impl<S> fmt::Debug for CarCodec<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CarCodec").finish()
+    }
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl<S> CarCodec<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Write the blocks of a CARv1 stream answering `request` into the store,
+    /// and record its root once every requested block is present.
+    /// Returns the bytes read from the stream.
+    async fn store_car<T>(&self, request: &CarRequest, io: &mut T) -> anyhow::Result<u64>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        // garbage collection waits until the root is recorded
+        let _ingest = self.store.ingest();
+        let mut limited = io.take(MAX_CAR_SIZE);
+        // fails on any other version than CARv1
+        let mut car = self.store.car_reader(&mut limited).await?;
+        if car.header.roots != [request.root] {
+            bail!(
+                "Expected the car of {} but got the roots {:?}",
+                request.root,
+                car.header.roots
+            );
+        }
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        while let Some(block) = car.next_block().await? {
+            batch_size += block.1.len();
+            batch.push(block);
+            if batch_size >= WRITE_BATCH_SIZE {
+                self.put_blocks(std::mem::take(&mut batch)).await?;
+                batch_size = 0;
+            }
+        }
+        self.put_blocks(batch).await?;
+        drop(car);
+        if limited.limit() == 0 {
+            bail!("The car stream exceeds {MAX_CAR_SIZE} bytes");
+        }
+        let bytes = MAX_CAR_SIZE - limited.limit();
+
+        let store = Arc::clone(&self.store);
+        let request = *request;
+        task::spawn_blocking(move || {
+            let complete = match request.max_depth {
+                None => store.dag_status(&request.root)?.is_complete(),
+                Some(_) => store
+                    .dag_iter(&request.root)
+                    .with_max_depth(request.max_depth)
+                    .all(|block| block.is_ok()),
+            };
+            if !complete {
+                bail!("The car stream of {} is missing blocks", request.root);
+            }
+            // enforces the storage quota
+            store.add_root(&request.root, ContentSource::Car)
+        })
+        .await??;
+        Ok(bytes)
+    }
+
+    /// Write blocks read from a car stream into the store.
+    async fn put_blocks(&self, blocks: Vec<(Cid, Vec<u8>)>) -> anyhow::Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let store = Arc::clone(&self.store);
+        task::spawn_blocking(move || {
+            blocks
+                .iter()
+                .try_for_each(|(cid, data)| store.put_block(cid, data, "car"))
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl<S> RequestResponseCodec for CarCodec<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    type Protocol = CarProtocol;
+
+    type Request = CarRequest;
+
+    type Response = CarResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let vec = read_length_prefixed(io, MAX_REQUEST_SIZE).await?;
+
+        if vec.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        bincode::options()
+            .with_limit(MAX_REQUEST_SIZE as u64)
+            .deserialize(&vec)
+            .map_err(invalid_data)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut status = [0];
+        io.read_exact(&mut status).await?;
+        match status[0] {
+            STATUS_NOT_FOUND => Ok(CarResponse::NotFound),
+            STATUS_CAR => {
+                let request = self
+                    .request
+                    .take()
+                    .ok_or_else(|| invalid_data("car response without a request"))?;
+                let bytes = self
+                    .store_car(&request, io)
+                    .await
+                    .map_err(|e| invalid_data(format!("Failed to store the car stream: {e:?}")))?;
+                Ok(CarResponse::Stored {
+                    roots: vec![request.root],
+                    bytes,
+                })
+            }
+            status => Err(invalid_data(format!(
+                "unknown car response status {status}"
+            ))),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = bincode::options().serialize(&req).map_err(invalid_data)?;
+        write_length_prefixed(io, &data).await?;
+        io.close().await?;
+        // the response is read with this codec on the same stream
+        self.request = Some(req);
+
+        Ok(())
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        match res {
+            CarResponse::Dag(request) => {
+                io.write_all(&[STATUS_CAR]).await?;
+                // a missing block ends the stream early, the requester keeps
+                // the blocks it received
+                if let Err(e) = self
+                    .store
+                    .write_car(&request.root, request.max_depth, io)
+                    .await
+                {
+                    warn!("The car stream of {} ended early: {e:?}", request.root);
+                }
+            }
+            CarResponse::NotFound => io.write_all(&[STATUS_NOT_FOUND]).await?,
+            CarResponse::Stored { .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "stored roots are not sent to peers",
+                ))
+            }
+        }
+        io.close().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::MemoryDB;
+    use futures::io::Cursor;
+    use libipld::{cbor::DagCborCodec, ipld, multihash::Code, Block, DefaultParams};
+
+    fn get_store() -> Arc<UrsaStore<MemoryDB>> {
+        Arc::new(UrsaStore::new(Arc::new(MemoryDB::default())))
+    }
+
+    async fn car_response(store: &UrsaStore<MemoryDB>, root: &Cid) -> Vec<u8> {
+        let mut data = vec![STATUS_CAR];
+        store.write_car(root, None, &mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_read_response_oversized_section() {
+        let store = Arc::new(UrsaStore::new(Arc::new(MemoryDB::default())).with_max_block_size(64));
+        let block =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!(vec![0u8; 256]))
+                .unwrap();
+        store.put_block(block.cid(), block.data(), "test").unwrap();
+        let data = car_response(&store, block.cid()).await;
+
+        let local = Arc::new(UrsaStore::new(Arc::new(MemoryDB::default())).with_max_block_size(64));
+        let mut codec = CarCodec::new(Arc::clone(&local));
+        let request = CarRequest {
+            root: *block.cid(),
+            max_depth: None,
+        };
+        codec
+            .write_request(&CarProtocol, &mut Cursor::new(Vec::new()), request)
+            .await
+            .unwrap();
+        let error = codec
+            .read_response(&CarProtocol, &mut Cursor::new(data))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Car section"));
+        assert!(!local.blockstore().has(block.cid()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_response() {
+        let remote = get_store();
+        let child = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("child"))
+            .unwrap();
+        let root = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Blake3_256,
+            &ipld!({ "child": *child.cid() }),
+        )
+        .unwrap();
+        for block in [&child, &root] {
+            remote.put_block(block.cid(), block.data(), "test").unwrap();
+        }
+
+        let store = get_store();
+        let request = CarRequest {
+            root: *root.cid(),
+            max_depth: None,
+        };
+
+        // a response is only read for a request
+        let mut codec = CarCodec::new(Arc::clone(&store));
+        let mut io = Cursor::new(car_response(&remote, root.cid()).await);
+        assert!(codec.read_response(&CarProtocol, &mut io).await.is_err());
+
+        // the car of another root is rejected
+        let mut codec = CarCodec::new(Arc::clone(&store));
+        codec
+            .write_request(&CarProtocol, &mut Cursor::new(Vec::new()), request)
+            .await
+            .unwrap();
+        let mut io = Cursor::new(car_response(&remote, child.cid()).await);
+        assert!(codec.read_response(&CarProtocol, &mut io).await.is_err());
+
+        // a truncated dag is rejected
+        let mut data = car_response(&remote, root.cid()).await;
+        data.truncate(data.len() - child.data().len() - child.cid().to_bytes().len() - 1);
+        let mut codec = CarCodec::new(Arc::clone(&store));
+        codec
+            .write_request(&CarProtocol, &mut Cursor::new(Vec::new()), request)
+            .await
+            .unwrap();
+        assert!(codec
+            .read_response(&CarProtocol, &mut Cursor::new(data))
+            .await
+            .is_err());
+        assert!(!store.roots().contains(root.cid()));
+
+        let mut codec = CarCodec::new(Arc::clone(&store));
+        codec
+            .write_request(&CarProtocol, &mut Cursor::new(Vec::new()), request)
+            .await
+            .unwrap();
+        let data = car_response(&remote, root.cid()).await;
+        // the status byte is not part of the car stream
+        let bytes = data.len() as u64 - 1;
+        let mut io = Cursor::new(data);
+        assert_eq!(
+            codec.read_response(&CarProtocol, &mut io).await.unwrap(),
+            CarResponse::Stored {
+                roots: vec![*root.cid()],
+                bytes
+            }
+        );
+        assert!(store.dag_status(root.cid()).unwrap().is_complete());
+        assert!(store.roots().contains(root.cid()));
+    }
+}
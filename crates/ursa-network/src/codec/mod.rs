@@ -1 +1,2 @@
+pub mod car;
 pub mod protocol;
@@ -1,5 +1,6 @@
-use crate::utils::cache_summary::CacheSummary;
+use crate::utils::cache_summary::{CacheSummary, SummaryDelta};
 use async_trait::async_trait;
+use bincode::Options;
 use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use libipld::Cid;
 use libp2p::{
@@ -9,7 +10,7 @@ use libp2p::{
     },
     request_response::RequestResponseCodec,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::io;
 
 /// Max request size in bytes
@@ -17,27 +18,95 @@ const MAX_REQUEST_SIZE: usize = 4 * 1024 * 1024; // 1 << 22
 /// Max response size in bytes
 const MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
 
-pub const PROTOCOL_NAME: &[u8] = b"/ursa/txrx/0.0.1";
+/// Legacy protocol, messages are JSON encoded.
+pub const PROTOCOL_NAME_V0: &[u8] = b"/ursa/txrx/0.0.1";
+pub const PROTOCOL_NAME: &[u8] = b"/ursa/txrx/0.1.0";
 
-#[derive(Debug, Clone)]
-pub struct UrsaProtocol;
+/// Version byte leading every bincode message.
+pub const WIRE_VERSION: u8 = 1;
+
+/// The versions of the exchange protocol, negotiated when a substream is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrsaProtocol {
+    /// JSON messages, spoken by older nodes.
+    V0,
+    /// Bincode messages prefixed with [`WIRE_VERSION`].
+    V1,
+}
+
+impl UrsaProtocol {
+    /// Supported protocols, the preferred one first.
+    pub const SUPPORTED: [UrsaProtocol; 2] = [UrsaProtocol::V1, UrsaProtocol::V0];
+
+    fn encode<M: Serialize>(&self, message: &M, max_size: usize) -> io::Result<Vec<u8>> {
+        let data = match self {
+            UrsaProtocol::V0 => serde_json::to_vec(message).map_err(invalid_data)?,
+            UrsaProtocol::V1 => {
+                let mut data = vec![WIRE_VERSION];
+                bincode::options()
+                    .serialize_into(&mut data, message)
+                    .map_err(invalid_data)?;
+                data
+            }
+        };
+        if data.len() > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("message of {} bytes exceeds {max_size} bytes", data.len()),
+            ));
+        }
+        Ok(data)
+    }
+
+    fn decode<M: DeserializeOwned>(&self, data: &[u8], max_size: usize) -> io::Result<M> {
+        match self {
+            UrsaProtocol::V0 => serde_json::from_slice(data).map_err(invalid_data),
+            UrsaProtocol::V1 => match data.split_first() {
+                Some((&WIRE_VERSION, message)) => bincode::options()
+                    .with_limit(max_size as u64)
+                    .deserialize(message)
+                    .map_err(invalid_data),
+                Some((version, _)) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported message version {version}"),
+                )),
+                None => Err(io::ErrorKind::UnexpectedEof.into()),
+            },
+        }
+    }
+}
 
 impl ProtocolName for UrsaProtocol {
     fn protocol_name(&self) -> &[u8] {
-        PROTOCOL_NAME
+        match self {
+            UrsaProtocol::V0 => PROTOCOL_NAME_V0,
+            UrsaProtocol::V1 => PROTOCOL_NAME,
+        }
     }
 }
 
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
 #[derive(Debug, Clone)]
 pub struct UrsaExchangeCodec;
 
 // todo(botch): think of a proper structure for a request
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestType {
-    // change this to the final cid version
-    CarRequest(String),
-    CacheRequest(Cid),
+    /// Ask to replicate a root, with the size of its dag in bytes.
+    CacheRequest(Cid, u64),
     StoreSummary(Box<CacheSummary>),
+    /// Changes to the cache summary last sent.
+    SummaryDelta(SummaryDelta),
+    /// The dag of a `CacheRequest` was stored.
+    ReplicaStored(Cid),
+    /// Free bytes in the storage quota, `None` if unbounded.
+    StoreCapacity(Option<u64>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -55,6 +124,10 @@ pub enum ResponseType {
     CarResponse(CarResponse),
     CacheResponse,
     StoreSummaryRequest,
+    /// A summary delta did not apply, the full summary is needed.
+    SummaryResync,
+    /// A `CacheRequest` was declined, the peer has no room for the content.
+    CacheDeclined,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -68,7 +141,11 @@ impl RequestResponseCodec for UrsaExchangeCodec {
 
     type Response = UrsaExchangeResponse;
 
-    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    async fn read_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
     where
         T: AsyncRead + Unpin + Send,
     {
@@ -78,15 +155,12 @@ impl RequestResponseCodec for UrsaExchangeCodec {
             return Err(io::ErrorKind::UnexpectedEof.into());
         }
 
-        let request: UrsaExchangeRequest =
-            serde_json::from_str(&String::from_utf8(vec).unwrap()).unwrap();
-
-        Ok(request)
+        protocol.decode(&vec, MAX_REQUEST_SIZE)
     }
 
     async fn read_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
@@ -98,22 +172,19 @@ impl RequestResponseCodec for UrsaExchangeCodec {
             return Err(io::ErrorKind::UnexpectedEof.into());
         }
 
-        let response: UrsaExchangeResponse =
-            serde_json::from_str(&String::from_utf8(vec).unwrap()).unwrap();
-
-        Ok(response)
+        protocol.decode(&vec, MAX_RESPONSE_SIZE)
     }
 
     async fn write_request<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         req: Self::Request,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = serde_json::to_vec(&req).unwrap();
+        let data = protocol.encode(&req, MAX_REQUEST_SIZE)?;
         write_length_prefixed(io, &data).await?;
         io.close().await?;
 
@@ -122,14 +193,14 @@ impl RequestResponseCodec for UrsaExchangeCodec {
 
     async fn write_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         res: Self::Response,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = serde_json::to_vec(&res).unwrap();
+        let data = protocol.encode(&res, MAX_RESPONSE_SIZE)?;
         write_length_prefixed(io, &data).await?;
         io.close().await?;
 
@@ -139,27 +210,168 @@ impl RequestResponseCodec for UrsaExchangeCodec {
 
 #[cfg(test)]
 mod tests {
-    #[ignore = "todo"]
+    use super::*;
+    use futures::io::Cursor;
+
+    fn requests() -> Vec<UrsaExchangeRequest> {
+        let mut cache_summary = CacheSummary::default();
+        cache_summary.insert(Cid::default().to_bytes());
+        vec![
+            UrsaExchangeRequest(RequestType::CacheRequest(Cid::default(), 1024)),
+            UrsaExchangeRequest(RequestType::StoreSummary(Box::new(cache_summary))),
+            UrsaExchangeRequest(RequestType::SummaryDelta(SummaryDelta {
+                base_version: 1,
+                inserted: vec![Cid::default().to_bytes()],
+                removed: vec![],
+            })),
+            UrsaExchangeRequest(RequestType::ReplicaStored(Cid::default())),
+            UrsaExchangeRequest(RequestType::StoreCapacity(Some(1 << 30))),
+        ]
+    }
+
+    fn responses() -> Vec<UrsaExchangeResponse> {
+        vec![
+            UrsaExchangeResponse(ResponseType::CarResponse(CarResponse {
+                cid: "Qm".to_string(),
+                data: vec![1, 2, 3],
+            })),
+            UrsaExchangeResponse(ResponseType::CacheResponse),
+            UrsaExchangeResponse(ResponseType::StoreSummaryRequest),
+            UrsaExchangeResponse(ResponseType::SummaryResync),
+            UrsaExchangeResponse(ResponseType::CacheDeclined),
+        ]
+    }
+
+    async fn framed(data: &[u8]) -> Cursor<Vec<u8>> {
+        let mut io = Cursor::new(Vec::new());
+        write_length_prefixed(&mut io, data).await.unwrap();
+        io.set_position(0);
+        io
+    }
+
     #[tokio::test]
     async fn test_read_request() {
-        todo!()
+        for protocol in UrsaProtocol::SUPPORTED {
+            for request in requests() {
+                let data = protocol.encode(&request, MAX_REQUEST_SIZE).unwrap();
+                let mut io = framed(&data).await;
+                let read = UrsaExchangeCodec
+                    .read_request(&protocol, &mut io)
+                    .await
+                    .unwrap();
+                assert_eq!(read, request);
+            }
+        }
+
+        // malformed requests are errors, not panics
+        for data in [
+            &b""[..],
+            b"{not json",
+            &[WIRE_VERSION, 0xff, 0xff],
+            &[42, 0],
+        ] {
+            for protocol in UrsaProtocol::SUPPORTED {
+                let mut io = framed(data).await;
+                assert!(UrsaExchangeCodec
+                    .read_request(&protocol, &mut io)
+                    .await
+                    .is_err());
+            }
+        }
     }
 
-    #[ignore = "todo"]
     #[tokio::test]
     async fn test_read_response() {
-        todo!()
+        for protocol in UrsaProtocol::SUPPORTED {
+            for response in responses() {
+                let data = protocol.encode(&response, MAX_RESPONSE_SIZE).unwrap();
+                let mut io = framed(&data).await;
+                let read = UrsaExchangeCodec
+                    .read_response(&protocol, &mut io)
+                    .await
+                    .unwrap();
+                assert_eq!(read, response);
+            }
+        }
+
+        // a truncated message and a request read as a response
+        let data = UrsaProtocol::V1
+            .encode(&responses()[0], MAX_RESPONSE_SIZE)
+            .unwrap();
+        let mut io = framed(&data[..data.len() - 1]).await;
+        assert!(UrsaExchangeCodec
+            .read_response(&UrsaProtocol::V1, &mut io)
+            .await
+            .is_err());
+        let data = UrsaProtocol::V0
+            .encode(&requests()[0], MAX_REQUEST_SIZE)
+            .unwrap();
+        let mut io = framed(&data).await;
+        assert!(UrsaExchangeCodec
+            .read_response(&UrsaProtocol::V0, &mut io)
+            .await
+            .is_err());
     }
 
-    #[ignore = "todo"]
     #[tokio::test]
     async fn test_write_request() {
-        todo!()
+        for protocol in UrsaProtocol::SUPPORTED {
+            for request in requests() {
+                let mut io = Cursor::new(Vec::new());
+                UrsaExchangeCodec
+                    .write_request(&protocol, &mut io, request.clone())
+                    .await
+                    .unwrap();
+
+                io.set_position(0);
+                let data = read_length_prefixed(&mut io, MAX_REQUEST_SIZE)
+                    .await
+                    .unwrap();
+                if protocol == UrsaProtocol::V1 {
+                    assert_eq!(data[0], WIRE_VERSION);
+                }
+                let decoded: UrsaExchangeRequest =
+                    protocol.decode(&data, MAX_REQUEST_SIZE).unwrap();
+                assert_eq!(decoded, request);
+            }
+        }
+
+        // the bincode cache summary is smaller than the json one
+        let summary = requests()[2].clone();
+        let json = UrsaProtocol::V0.encode(&summary, MAX_REQUEST_SIZE).unwrap();
+        let bincode = UrsaProtocol::V1.encode(&summary, MAX_REQUEST_SIZE).unwrap();
+        assert!(bincode.len() < json.len());
     }
 
-    #[ignore = "todo"]
     #[tokio::test]
     async fn test_write_response() {
-        todo!()
+        for protocol in UrsaProtocol::SUPPORTED {
+            for response in responses() {
+                let mut io = Cursor::new(Vec::new());
+                UrsaExchangeCodec
+                    .write_response(&protocol, &mut io, response.clone())
+                    .await
+                    .unwrap();
+
+                io.set_position(0);
+                let read = UrsaExchangeCodec
+                    .read_response(&protocol, &mut io)
+                    .await
+                    .unwrap();
+                assert_eq!(read, response);
+            }
+        }
+
+        // responses over the size limit are not sent
+        let response = UrsaExchangeResponse(ResponseType::CarResponse(CarResponse {
+            cid: "Qm".to_string(),
+            data: vec![0; MAX_RESPONSE_SIZE],
+        }));
+        let mut io = Cursor::new(Vec::new());
+        let result = UrsaExchangeCodec
+            .write_response(&UrsaProtocol::V1, &mut io, response)
+            .await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(io.into_inner().is_empty());
     }
 }
@@ -1,9 +1,13 @@
-use libp2p::Multiaddr;
+use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use ursa_store::{BackendKind, EvictionPolicy, DEFAULT_MAX_BLOCK_SIZE};
+
+use crate::transport::{MuxerKind, TransportKind};
+use crate::utils::replication::ReplicationStrategy;
 
 /// Ursa Configuration
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct NetworkConfig {
     /// Optional mdns local discovery.
     #[serde(default = "NetworkConfig::default_mdns")]
@@ -30,6 +34,18 @@ pub struct NetworkConfig {
     /// Database path.
     #[serde(default = "NetworkConfig::default_database_path")]
     pub database_path: PathBuf,
+    /// Database backend of the blockstore (rocksdb, flatfs or memory). Defaults to rocksdb.
+    #[serde(default = "NetworkConfig::default_database_backend")]
+    pub database_backend: BackendKind,
+    /// Maximum bytes of content kept in the blockstore. Defaults to 0, unbounded.
+    #[serde(default = "NetworkConfig::default_max_storage_bytes")]
+    pub max_storage_bytes: u64,
+    /// Policy used to evict cached roots once `max_storage_bytes` is exceeded (lru or lfu).
+    #[serde(default = "NetworkConfig::default_eviction_policy")]
+    pub eviction_policy: EvictionPolicy,
+    /// Maximum size of a block accepted from uploads, the origin and peers. Defaults to 1MiB.
+    #[serde(default = "NetworkConfig::default_max_block_size")]
+    pub max_block_size: usize,
     /// user identity name
     #[serde(default = "NetworkConfig::default_identity")]
     pub identity: String,
@@ -46,6 +62,111 @@ pub struct NetworkConfig {
     /// Interval to run random kademlia walks to refresh the routing table. Defaults to 5 minutes
     #[serde(default = "NetworkConfig::default_kad_walk_interval")]
     pub kad_walk_interval: u64,
+    /// Initial number of cids the cache summary shared with peers holds before growing.
+    #[serde(default = "NetworkConfig::default_cache_summary_capacity")]
+    pub cache_summary_capacity: usize,
+    /// False positive rate of the cache summary. Defaults to 0.1
+    #[serde(default = "NetworkConfig::default_cache_summary_fp_rate")]
+    pub cache_summary_fp_rate: f64,
+    /// Interval to resend the full cache summary to peers, in seconds. Defaults to 5 minutes
+    #[serde(default = "NetworkConfig::default_cache_summary_refresh_interval")]
+    pub cache_summary_refresh_interval: u64,
+    /// Time after which the cache summary of a peer that was not updated is dropped,
+    /// in seconds. Defaults to 15 minutes
+    #[serde(default = "NetworkConfig::default_cache_summary_ttl")]
+    pub cache_summary_ttl: u64,
+    /// Number of the best scored peers asked for content or to replicate it. Defaults to 8
+    #[serde(default = "NetworkConfig::default_max_peers_per_request")]
+    pub max_peers_per_request: usize,
+    /// Time to wait for a peer to complete a graphsync request before asking the next one,
+    /// in seconds. Defaults to 2 minutes
+    #[serde(default = "NetworkConfig::default_graphsync_timeout")]
+    pub graphsync_timeout: u64,
+    /// Number of peers that must confirm they store a replica of uploaded content. Defaults to 3
+    #[serde(default = "NetworkConfig::default_replication_factor")]
+    pub replication_factor: usize,
+    /// Strategy used to pick the peers replicating uploaded content
+    /// (closest, random, capacity or diverse). Defaults to closest
+    #[serde(default = "NetworkConfig::default_replication_strategy")]
+    pub replication_strategy: ReplicationStrategy,
+    /// Time to wait for a peer to confirm a replica before asking another one, in seconds.
+    /// Defaults to 10 minutes
+    #[serde(default = "NetworkConfig::default_replication_timeout")]
+    pub replication_timeout: u64,
+    /// Accept to replicate the content of other peers while there is room in the storage quota.
+    #[serde(default = "NetworkConfig::default_accept_replicas")]
+    pub accept_replicas: bool,
+    /// Fetch content announced as popular by other peers while there is room for it.
+    #[serde(default = "NetworkConfig::default_auto_cache")]
+    pub auto_cache: bool,
+    /// Number of requests after which content is announced as popular. Defaults to 10
+    #[serde(default = "NetworkConfig::default_popularity_threshold")]
+    pub popularity_threshold: u64,
+    /// Least time between two fetches of content announced as popular by the same peer,
+    /// in seconds. Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_auto_cache_interval")]
+    pub auto_cache_interval: u64,
+    /// Interval to announce the popular content, in seconds. Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_announce_interval")]
+    pub announce_interval: u64,
+    /// Transports to listen and dial on (tcp, quic or both). Defaults to both
+    #[serde(default = "NetworkConfig::default_transport")]
+    pub transport: TransportKind,
+    /// Stream muxer preferred on tcp connections (yamux or mplex). Defaults to yamux
+    #[serde(default = "NetworkConfig::default_muxer")]
+    pub muxer: MuxerKind,
+    /// Maximum number of incoming connections being established.
+    #[serde(default = "NetworkConfig::default_max_connections")]
+    pub max_pending_incoming: u32,
+    /// Maximum number of outgoing connections being established.
+    #[serde(default = "NetworkConfig::default_max_connections")]
+    pub max_pending_outgoing: u32,
+    /// Maximum number of established incoming connections.
+    #[serde(default = "NetworkConfig::default_max_connections")]
+    pub max_established_incoming: u32,
+    /// Maximum number of established outgoing connections.
+    #[serde(default = "NetworkConfig::default_max_connections")]
+    pub max_established_outgoing: u32,
+    /// Maximum number of established connections with a single peer.
+    #[serde(default = "NetworkConfig::default_max_established_per_peer")]
+    pub max_established_per_peer: u32,
+    /// Number of addresses of a peer dialed concurrently.
+    #[serde(default = "NetworkConfig::default_dial_concurrency_factor")]
+    pub dial_concurrency_factor: u8,
+    /// Time a connection with no request in flight is kept open, in seconds. Defaults to 10 seconds
+    #[serde(default = "NetworkConfig::default_idle_connection_timeout")]
+    pub idle_connection_timeout: u64,
+    /// Timeout of the requests exchanged with peers, in seconds. Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_request_timeout")]
+    pub request_timeout: u64,
+    /// Timeout of car transfers, in seconds. Defaults to 10 minutes
+    #[serde(default = "NetworkConfig::default_car_request_timeout")]
+    pub car_request_timeout: u64,
+    /// Pre-shared key of a private network, as 64 hex characters or a swarm.key file.
+    /// Only peers with the same key can connect, requires the tcp transport.
+    #[serde(default)]
+    pub psk: Option<String>,
+    /// Peers allowed to connect, any peer if empty. Bootstrap nodes must be allowed.
+    #[serde(default)]
+    pub allow_peers: Vec<PeerId>,
+    /// Peers never allowed to connect.
+    #[serde(default)]
+    pub deny_peers: Vec<PeerId>,
+    /// Path of the database keeping the known peers across restarts.
+    /// Defaults to ~/.ursa/data/peer_store
+    #[serde(default = "NetworkConfig::default_peer_store_path")]
+    pub peer_store_path: PathBuf,
+    /// Interval to save the known peers, in seconds.
+    /// Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_peer_store_interval")]
+    pub peer_store_interval: u64,
+    /// Interval to persist the content accesses kept in memory by the store, in seconds.
+    /// Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_access_flush_interval")]
+    pub access_flush_interval: u64,
+    /// Number of the best scored known peers dialed on startup.
+    #[serde(default = "NetworkConfig::default_reconnect_peers")]
+    pub reconnect_peers: usize,
 }
 
 impl NetworkConfig {
@@ -82,6 +203,18 @@ impl NetworkConfig {
     fn default_database_path() -> PathBuf {
         "~/.ursa/data/ursa_db".into()
     }
+    fn default_database_backend() -> BackendKind {
+        BackendKind::RocksDb
+    }
+    fn default_max_storage_bytes() -> u64 {
+        0
+    }
+    fn default_eviction_policy() -> EvictionPolicy {
+        EvictionPolicy::Lru
+    }
+    fn default_max_block_size() -> usize {
+        DEFAULT_MAX_BLOCK_SIZE
+    }
     fn default_keystore_path() -> PathBuf {
         "~/.ursa/keystore".into()
     }
@@ -94,6 +227,84 @@ impl NetworkConfig {
     fn default_kad_walk_interval() -> u64 {
         300
     }
+    fn default_cache_summary_capacity() -> usize {
+        10_000
+    }
+    fn default_cache_summary_fp_rate() -> f64 {
+        0.1
+    }
+    fn default_cache_summary_refresh_interval() -> u64 {
+        300
+    }
+    fn default_cache_summary_ttl() -> u64 {
+        900
+    }
+    fn default_graphsync_timeout() -> u64 {
+        120
+    }
+    fn default_max_peers_per_request() -> usize {
+        8
+    }
+    fn default_replication_factor() -> usize {
+        3
+    }
+    fn default_replication_strategy() -> ReplicationStrategy {
+        ReplicationStrategy::Closest
+    }
+    fn default_replication_timeout() -> u64 {
+        600
+    }
+    fn default_accept_replicas() -> bool {
+        true
+    }
+    fn default_auto_cache() -> bool {
+        true
+    }
+    fn default_popularity_threshold() -> u64 {
+        10
+    }
+    fn default_auto_cache_interval() -> u64 {
+        60
+    }
+    fn default_announce_interval() -> u64 {
+        60
+    }
+    fn default_transport() -> TransportKind {
+        TransportKind::Both
+    }
+    fn default_muxer() -> MuxerKind {
+        MuxerKind::Yamux
+    }
+    fn default_max_connections() -> u32 {
+        1024
+    }
+    fn default_max_established_per_peer() -> u32 {
+        8
+    }
+    fn default_dial_concurrency_factor() -> u8 {
+        8
+    }
+    fn default_idle_connection_timeout() -> u64 {
+        10
+    }
+    fn default_request_timeout() -> u64 {
+        60
+    }
+    fn default_car_request_timeout() -> u64 {
+        600
+    }
+    fn default_peer_store_path() -> PathBuf {
+        "~/.ursa/data/peer_store".into()
+    }
+    fn default_peer_store_interval() -> u64 {
+        60
+    }
+    fn default_access_flush_interval() -> u64 {
+        60
+    }
+    fn default_reconnect_peers() -> usize {
+        8
+    }
 }
 
 impl Default for NetworkConfig {
@@ -107,11 +318,47 @@ impl Default for NetworkConfig {
             bootstrap_nodes: Self::default_bootstrap_nodes(),
             swarm_addrs: Self::default_swarm_addrs(),
             database_path: Self::default_database_path(),
+            database_backend: Self::default_database_backend(),
+            max_storage_bytes: Self::default_max_storage_bytes(),
+            eviction_policy: Self::default_eviction_policy(),
+            max_block_size: Self::default_max_block_size(),
             identity: Self::default_identity(),
             tracker: Self::default_tracker(),
             keystore_path: Self::default_keystore_path(),
             kad_replication_factor: Self::default_kad_replication_factor(),
             kad_walk_interval: Self::default_kad_walk_interval(),
+            cache_summary_capacity: Self::default_cache_summary_capacity(),
+            cache_summary_fp_rate: Self::default_cache_summary_fp_rate(),
+            cache_summary_refresh_interval: Self::default_cache_summary_refresh_interval(),
+            cache_summary_ttl: Self::default_cache_summary_ttl(),
+            max_peers_per_request: Self::default_max_peers_per_request(),
+            graphsync_timeout: Self::default_graphsync_timeout(),
+            replication_factor: Self::default_replication_factor(),
+            replication_strategy: Self::default_replication_strategy(),
+            replication_timeout: Self::default_replication_timeout(),
+            accept_replicas: Self::default_accept_replicas(),
+            auto_cache: Self::default_auto_cache(),
+            popularity_threshold: Self::default_popularity_threshold(),
+            auto_cache_interval: Self::default_auto_cache_interval(),
+            announce_interval: Self::default_announce_interval(),
+            transport: Self::default_transport(),
+            muxer: Self::default_muxer(),
+            max_pending_incoming: Self::default_max_connections(),
+            max_pending_outgoing: Self::default_max_connections(),
+            max_established_incoming: Self::default_max_connections(),
+            max_established_outgoing: Self::default_max_connections(),
+            max_established_per_peer: Self::default_max_established_per_peer(),
+            dial_concurrency_factor: Self::default_dial_concurrency_factor(),
+            idle_connection_timeout: Self::default_idle_connection_timeout(),
+            request_timeout: Self::default_request_timeout(),
+            car_request_timeout: Self::default_car_request_timeout(),
+            psk: None,
+            allow_peers: Vec::new(),
+            deny_peers: Vec::new(),
+            peer_store_path: Self::default_peer_store_path(),
+            peer_store_interval: Self::default_peer_store_interval(),
+            access_flush_interval: Self::default_access_flush_interval(),
+            reconnect_peers: Self::default_reconnect_peers(),
         }
     }
 }
@@ -1,5 +1,8 @@
 use crate::config::NetworkConfig;
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Result};
+use bincode::Options;
+use libipld::Cid;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
@@ -15,6 +18,40 @@ use libp2p::{
 
 const URSA_GOSSIP_PROTOCOL: &str = "ursa/gossipsub/0.0.1";
 
+/// Version byte leading every announcement.
+pub const ANNOUNCEMENT_VERSION: u8 = 1;
+/// Max announcement size in bytes
+const MAX_ANNOUNCEMENT_SIZE: u64 = 1024;
+
+/// Content announcements published on the global topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Announcement {
+    /// The publisher stored new content.
+    NewContent { cid: Cid, size: u64 },
+    /// Content was requested `count` times from the publisher.
+    Requested { cid: Cid, size: u64, count: u64 },
+    /// The publisher evicted content.
+    Evicted(Cid),
+}
+
+impl Announcement {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = vec![ANNOUNCEMENT_VERSION];
+        bincode::options().serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        match data.split_first() {
+            Some((&ANNOUNCEMENT_VERSION, announcement)) => Ok(bincode::options()
+                .with_limit(MAX_ANNOUNCEMENT_SIZE)
+                .deserialize(announcement)?),
+            Some((version, _)) => bail!("unsupported announcement version {version}"),
+            None => bail!("empty announcement"),
+        }
+    }
+}
+
 pub(crate) fn build_gossipsub(keypair: &Keypair, config: &NetworkConfig) -> Gossipsub {
     let is_bootstrapper = config.bootstrapper;
     let mesh_n = if is_bootstrapper { 0 } else { 8 };
@@ -48,3 +85,32 @@ pub(crate) fn build_gossipsub(keypair: &Keypair, config: &NetworkConfig) -> Goss
         .map_err(|err| anyhow!("{}", err))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_encoding() {
+        let announcements = [
+            Announcement::NewContent {
+                cid: Cid::default(),
+                size: 1024,
+            },
+            Announcement::Requested {
+                cid: Cid::default(),
+                size: 1024,
+                count: 10,
+            },
+            Announcement::Evicted(Cid::default()),
+        ];
+        for announcement in announcements {
+            let data = announcement.encode().unwrap();
+            assert_eq!(data[0], ANNOUNCEMENT_VERSION);
+            assert_eq!(Announcement::decode(&data).unwrap(), announcement);
+        }
+
+        assert!(Announcement::decode(&[]).is_err());
+        assert!(Announcement::decode(b"hello world!").is_err());
+    }
+}
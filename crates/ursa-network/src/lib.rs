@@ -6,5 +6,13 @@ pub mod service;
 mod transport;
 mod utils;
 
+pub use ipld_traversal::Selector;
+
 pub use self::config::*;
 pub use self::service::*;
+pub use self::transport::{MuxerKind, TransportKind};
+pub use self::utils::{
+    peer_store::{PeerRecord, PeerStore},
+    replication::ReplicationStrategy,
+    reputation::{PeerScore, PeerStats},
+};
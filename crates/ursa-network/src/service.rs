@@ -17,7 +17,7 @@ use db::Store;
 use fnv::FnvHashMap;
 use futures_util::stream::StreamExt;
 use fvm_ipld_blockstore::Blockstore;
-use graphsync::{GraphSyncEvent, Request};
+use graphsync::{GraphSyncEvent, Request, RequestId as GraphSyncRequestId};
 use ipld_traversal::{selector::RecursionLimit, Selector};
 use libipld::{Cid, DefaultParams};
 use libp2p::{
@@ -28,20 +28,27 @@ use libp2p::{
     },
     identify::Event as IdentifyEvent,
     identity::Keypair,
-    kad::{BootstrapOk, KademliaEvent, QueryResult},
+    kad::{
+        AddProviderOk, BootstrapOk, GetProvidersOk, GetProvidersResult, KademliaEvent,
+        QueryId as KadQueryId, QueryResult,
+    },
     mdns::Event as MdnsEvent,
     multiaddr::Protocol,
     ping::Event as PingEvent,
     relay::v2::client::Client as RelayClient,
     request_response::{RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel},
-    swarm::{ConnectionHandler, IntoConnectionHandler, NetworkBehaviour},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionHandler, IntoConnectionHandler, NetworkBehaviour,
+    },
     swarm::{ConnectionLimits, SwarmBuilder, SwarmEvent},
     Multiaddr, PeerId, Swarm,
 };
 use libp2p_bitswap::{BitswapEvent, QueryId};
+use metrics::histogram;
 use rand::prelude::SliceRandom;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
     num::{NonZeroU8, NonZeroUsize},
     sync::Arc,
@@ -50,19 +57,33 @@ use std::{
 use tokio::{
     select,
     sync::{
+        broadcast::error::RecvError,
         mpsc::{unbounded_channel, UnboundedReceiver as Receiver, UnboundedSender as Sender},
         oneshot,
     },
+    task,
     time::{sleep, Instant},
 };
 use tracing::{debug, error, info, trace, warn};
 use ursa_metrics::Recorder;
-use ursa_store::{BitswapStorage, GraphSyncStorage, UrsaStore};
+use ursa_store::{
+    BitswapStorage, ContentSource, DagLimitGuard, GraphSyncStorage, IngestGuard, UrsaStore,
+};
 
 use crate::behaviour::KAD_PROTOCOL;
-use crate::codec::protocol::{RequestType, ResponseType};
+use crate::codec::{
+    car::{CarCodec, CarRequest, CarResponse},
+    protocol::{RequestType, ResponseType},
+};
+use crate::gossipsub::Announcement;
 use crate::transport::build_transport;
-use crate::utils::cache_summary::CacheSummary;
+use crate::utils::{
+    cache_summary::{CacheSummary, SummaryDelta},
+    peer_filter::PeerFilter,
+    peer_store::{prune_peers, unix_time, PeerRecord, PeerStore},
+    replication::{PeerPlacement, ReplicationStrategy},
+    reputation::{PeerScore, PeerStats},
+};
 use crate::{
     behaviour::{Behaviour, BehaviourEvent},
     codec::protocol::{UrsaExchangeRequest, UrsaExchangeResponse},
@@ -71,6 +92,8 @@ use crate::{
 
 pub const URSA_GLOBAL: &str = "/ursa/global";
 pub const MESSAGE_PROTOCOL: &[u8] = b"/ursa/message/0.0.1";
+/// Most deltas queued for a peer, past which it gets the full summary instead.
+const MAX_QUEUED_DELTAS: usize = 16;
 
 type BlockOneShotSender<T> = oneshot::Sender<Result<T, Error>>;
 type SwarmEventType<S> = SwarmEvent<
@@ -83,6 +106,91 @@ type SwarmEventType<S> = SwarmEvent<
     >::Error
 >;
 
+/// Selector matching every block of a dag.
+pub fn dag_selector() -> Selector {
+    Selector::ExploreRecursive {
+        limit: RecursionLimit::None,
+        sequence: Box::new(Selector::ExploreAll {
+            next: Box::new(Selector::ExploreRecursiveEdge),
+        }),
+        current: None,
+    }
+}
+
+/// Selector matching the root block of a dag alone.
+pub fn block_selector() -> Selector {
+    Selector::Matcher
+}
+
+/// A graphsync request waiting for its traversal to complete.
+struct GraphsyncQuery {
+    root: Cid,
+    selector: Selector,
+    /// Peers to ask next if the current one does not have the dag.
+    peers: Vec<PeerId>,
+    /// `None` for the content we cache for the network.
+    sender: Option<BlockOneShotSender<usize>>,
+    /// The peer that asked us to cache the content.
+    replica_of: Option<PeerId>,
+    /// Most bytes the dag of content we cache may hold, checked again once the dag is here.
+    max_size: Option<u64>,
+    /// Keeps garbage collection away until the dag is recorded.
+    _ingest: IngestGuard,
+    /// Refuses the blocks past `max_size` while the dag is fetched.
+    _limit: Option<DagLimitGuard>,
+}
+
+/// A dag fetched for a graphsync query, measured and recorded in the store.
+struct FetchedDag {
+    query: GraphsyncQuery,
+    /// The peer that sent the dag.
+    peer_id: PeerId,
+    /// Number of blocks received.
+    received: usize,
+    /// Bytes of the dag present in the store.
+    bytes: u64,
+    /// Whether the content we cache was recorded as a root.
+    recorded: bool,
+}
+
+/// What a kademlia provider lookup was started for.
+enum ProviderLookup {
+    Bitswap(Cid),
+    Graphsync(GraphsyncQuery),
+}
+
+/// The peers asked to replicate a root.
+#[derive(Default)]
+struct Replication {
+    /// Bytes of the dag to replicate.
+    size: u64,
+    /// Peers to ask next if one declines or fails.
+    candidates: Vec<PeerId>,
+    /// Peers that were asked and did not confirm yet, with when they were asked.
+    pending: HashMap<PeerId, Instant>,
+}
+
+/// Our summary updates on their way to a peer.
+///
+/// One update is sent at a time, so the peer applies the deltas in order.
+/// The deltas made meanwhile are queued, merged when they can be.
+#[derive(Default)]
+struct SummaryUpdates {
+    /// Summary or delta the peer did not answer yet.
+    in_flight: Option<RequestId>,
+    /// Deltas to send next.
+    queued: VecDeque<SummaryDelta>,
+    /// Send the full summary next, in place of the queued deltas.
+    resync: bool,
+}
+
+/// The cache summary of a peer.
+struct PeerSummary {
+    summary: CacheSummary,
+    /// When the summary was last received or updated.
+    updated_at: Instant,
+}
+
 #[derive(Debug)]
 pub enum GossipsubMessage {
     /// A subscribe message.
@@ -158,15 +266,52 @@ pub enum NetworkCommand {
         sender: BlockOneShotSender<()>,
     },
 
+    /// Fetch the blocks selected from `root` over graphsync, answering with the
+    /// number of blocks received. The `peers` are asked in order, if empty the
+    /// connected peers with the root in their cache summary or its providers are used.
+    GetGraphsync {
+        root: Cid,
+        selector: Selector,
+        peers: Vec<PeerId>,
+        sender: BlockOneShotSender<usize>,
+    },
+
+    /// Stream the dag under `root` from a peer as a car file, down to `max_depth`
+    /// links below the root if set, answering with the roots written to the store.
+    /// Without a peer, the best scored connected peer with the root in its cache
+    /// summary is asked.
+    GetCar {
+        peer_id: Option<PeerId>,
+        root: Cid,
+        max_depth: Option<usize>,
+        sender: oneshot::Sender<Result<Vec<Cid>>>,
+    },
+
     Put {
         cid: Cid,
         sender: oneshot::Sender<Result<()>>,
     },
 
+    Remove {
+        cid: Cid,
+        sender: oneshot::Sender<Result<()>>,
+    },
+
+    /// Peers that confirmed they store a replica of content we put.
+    GetReplicas {
+        cid: Cid,
+        sender: oneshot::Sender<HashSet<PeerId>>,
+    },
+
     GetPeers {
         sender: oneshot::Sender<HashSet<PeerId>>,
     },
 
+    /// Reputation of every peer we exchanged with.
+    GetPeerScores {
+        sender: oneshot::Sender<HashMap<PeerId, PeerScore>>,
+    },
+
     GetListenerAddresses {
         sender: oneshot::Sender<Vec<Multiaddr>>,
     },
@@ -182,6 +327,9 @@ pub enum NetworkCommand {
         message: GossipsubMessage,
     },
 
+    /// Save the known peers, then stop the service loop.
+    Shutdown { sender: oneshot::Sender<()> },
+
     #[cfg(test)]
     GetPeerContent {
         sender: oneshot::Sender<HashMap<PeerId, CacheSummary>>,
@@ -204,14 +352,26 @@ where
     event_sender: Sender<NetworkEvent>,
     /// Handles events received by the ursa network.
     _event_receiver: Receiver<NetworkEvent>,
-    /// Bitswap pending queries.
-    bitswap_queries: FnvHashMap<QueryId, Cid>,
+    /// Bitswap pending queries with the peers asked.
+    bitswap_queries: FnvHashMap<QueryId, (Cid, Vec<PeerId>)>,
+    /// Kademlia provider lookups for content missing from every connected peer.
+    provider_queries: FnvHashMap<KadQueryId, ProviderLookup>,
+    /// Graphsync pending requests, with the peer asked and when.
+    graphsync_queries: FnvHashMap<GraphSyncRequestId, (PeerId, Instant, GraphsyncQuery)>,
+    /// Time after which the next peer of a graphsync query is asked.
+    graphsync_timeout: Duration,
+    /// Reports the dags of completed graphsync queries once they are stored.
+    fetched_sender: Sender<FetchedDag>,
+    /// Receives the stored dags of completed graphsync queries.
+    fetched_receiver: Receiver<FetchedDag>,
     /// hashmap for keeping track of rpc response channels.
     response_channels: FnvHashMap<Cid, Vec<BlockOneShotSender<()>>>,
     /// Pending requests.
     _pending_requests: HashMap<RequestId, ResponseChannel<UrsaExchangeResponse>>,
     /// Pending responses.
     pending_responses: HashMap<RequestId, oneshot::Sender<Result<UrsaExchangeResponse>>>,
+    /// Pending car transfers.
+    car_requests: HashMap<RequestId, oneshot::Sender<Result<Vec<Cid>>>>,
     /// Connected peers.
     peers: HashSet<PeerId>,
     /// Bootstrap multiaddrs.
@@ -219,9 +379,61 @@ where
     /// Summarizes the cached content.
     cached_content: CacheSummary,
     /// Content summaries from other nodes.
-    peer_cached_content: HashMap<PeerId, CacheSummary>,
+    peer_cached_content: HashMap<PeerId, PeerSummary>,
+    /// Our summary updates to send to the connected peers.
+    summary_updates: HashMap<PeerId, SummaryUpdates>,
     /// Interval for random Kademlia walks.
     kad_walk_interval: u64,
+    /// Interval to resend the full cache summary.
+    summary_refresh_interval: Duration,
+    /// Time after which a peer summary that was not updated is dropped.
+    summary_ttl: Duration,
+    /// Statistics of the peers we exchanged with, used to rank them.
+    peer_stats: HashMap<PeerId, PeerStats>,
+    /// Number of the best scored peers asked for content.
+    max_peers_per_request: usize,
+    /// Replication of the content we put, until enough peers confirmed it.
+    replications: HashMap<Cid, Replication>,
+    /// Peers that confirmed they store a replica of the content we put.
+    replicas: HashMap<Cid, HashSet<PeerId>>,
+    /// Time after which another peer is asked in place of one that did not confirm a replica.
+    replication_timeout: Duration,
+    /// Pending cache requests, with the root they replicate.
+    replica_requests: HashMap<RequestId, Cid>,
+    /// Number of peers that must confirm a replica.
+    replication_factor: usize,
+    /// Strategy used to pick the replicating peers.
+    replication_strategy: ReplicationStrategy,
+    /// Accept the cache requests of other peers.
+    accept_replicas: bool,
+    /// Free storage advertised by peers, `None` if unbounded.
+    peer_capacity: HashMap<PeerId, Option<u64>>,
+    /// Addresses connected peers were last connected on.
+    peer_addresses: HashMap<PeerId, Multiaddr>,
+    /// Peers allowed to connect.
+    peer_filter: PeerFilter,
+    /// Fetch the content announced as popular.
+    auto_cache: bool,
+    /// Number of requests after which content is popular.
+    popularity_threshold: u64,
+    /// Least time between two fetches of popular content announced by a peer.
+    auto_cache_interval: Duration,
+    /// When the content announced by a peer was last fetched.
+    auto_cache_sources: HashMap<PeerId, Instant>,
+    /// Interval to announce the popular content.
+    announce_interval: Duration,
+    /// Hits of the roots when they were last announced as popular.
+    announced_hits: HashMap<Cid, u64>,
+    /// Database of the known peers.
+    peer_store: PeerStore,
+    /// Peers identified on the network, saved to the peer store.
+    known_peers: HashMap<PeerId, PeerRecord>,
+    /// Interval to save the known peers.
+    peer_store_interval: Duration,
+    /// Number of the best scored known peers dialed on startup.
+    reconnect_peers: usize,
+    /// Set once the state is saved by a [`NetworkCommand::Shutdown`].
+    stopped: bool,
 }
 
 impl<S> UrsaService<S>
@@ -258,28 +470,32 @@ where
 
         let bitswap_store = BitswapStorage(store.clone());
         let graphsync_store = GraphSyncStorage(store.clone());
-        let transport = build_transport(&keypair, config, relay_transport);
+        let peer_filter = PeerFilter::new(&config.allow_peers, &config.deny_peers);
+        let transport = build_transport(&keypair, config, relay_transport, peer_filter.clone())?;
         let mut peers = HashSet::new();
         let behaviour = Behaviour::new(
             &keypair,
             config,
             bitswap_store,
             graphsync_store,
+            CarCodec::new(store.clone()),
             relay_client,
             &mut peers,
         );
 
         let limits = ConnectionLimits::default()
-            .with_max_pending_incoming(Some(2 << 9))
-            .with_max_pending_outgoing(Some(2 << 9))
-            .with_max_established_incoming(Some(2 << 9))
-            .with_max_established_outgoing(Some(2 << 9))
-            .with_max_established_per_peer(Some(8));
+            .with_max_pending_incoming(Some(config.max_pending_incoming))
+            .with_max_pending_outgoing(Some(config.max_pending_outgoing))
+            .with_max_established_incoming(Some(config.max_established_incoming))
+            .with_max_established_outgoing(Some(config.max_established_outgoing))
+            .with_max_established_per_peer(Some(config.max_established_per_peer));
+        let dial_concurrency_factor = NonZeroU8::new(config.dial_concurrency_factor)
+            .ok_or_else(|| anyhow!("The dial concurrency factor must be at least 1"))?;
 
         let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id)
             .notify_handler_buffer_size(NonZeroUsize::new(2 << 7).unwrap())
             .connection_event_buffer_size(2 << 7)
-            .dial_concurrency_factor(NonZeroU8::new(8).unwrap())
+            .dial_concurrency_factor(dial_concurrency_factor)
             .connection_limits(limits)
             .build();
 
@@ -288,11 +504,28 @@ where
         }
 
         for addr in &config.swarm_addrs {
+            if !config.transport.supports(addr) {
+                warn!(
+                    "Not listening on {addr}, the {:?} transport does not support it",
+                    config.transport
+                );
+                continue;
+            }
             Swarm::listen_on(&mut swarm, addr.clone())
                 .map_err(|err| anyhow!("{}", err))
                 .unwrap();
         }
 
+        // announce the content already in the store
+        let mut cached_content =
+            CacheSummary::try_new(config.cache_summary_capacity, config.cache_summary_fp_rate)?;
+        for root_cid in store.roots() {
+            cached_content.insert(root_cid.to_bytes());
+            if let Err(e) = swarm.behaviour_mut().start_providing(&root_cid) {
+                warn!("Failed to provide {root_cid}: {e:?}");
+            }
+        }
+
         // subscribe to topic
         let topic = Topic::new(URSA_GLOBAL);
         if let Err(error) = swarm.behaviour_mut().subscribe(&topic) {
@@ -301,6 +534,7 @@ where
 
         let (event_sender, _event_receiver) = unbounded_channel();
         let (command_sender, command_receiver) = unbounded_channel();
+        let (fetched_sender, fetched_receiver) = unbounded_channel();
 
         Ok(UrsaService {
             swarm,
@@ -311,16 +545,54 @@ where
             _event_receiver,
             response_channels: Default::default(),
             bitswap_queries: Default::default(),
+            provider_queries: Default::default(),
+            graphsync_queries: Default::default(),
+            graphsync_timeout: Duration::from_secs(config.graphsync_timeout.max(1)),
+            fetched_sender,
+            fetched_receiver,
             _pending_requests: HashMap::default(),
             pending_responses: HashMap::default(),
+            car_requests: HashMap::default(),
             peers,
             bootstraps: config.bootstrap_nodes.clone(),
-            cached_content: CacheSummary::default(),
+            cached_content,
             peer_cached_content: HashMap::default(),
+            summary_updates: HashMap::default(),
             kad_walk_interval: config.kad_walk_interval,
+            summary_refresh_interval: Duration::from_secs(config.cache_summary_refresh_interval),
+            summary_ttl: Duration::from_secs(config.cache_summary_ttl),
+            peer_stats: HashMap::default(),
+            max_peers_per_request: config.max_peers_per_request,
+            replications: HashMap::default(),
+            replicas: HashMap::default(),
+            replication_timeout: Duration::from_secs(config.replication_timeout.max(1)),
+            replica_requests: HashMap::default(),
+            replication_factor: config.replication_factor,
+            replication_strategy: config.replication_strategy,
+            accept_replicas: config.accept_replicas,
+            peer_capacity: HashMap::default(),
+            peer_addresses: HashMap::default(),
+            peer_filter,
+            auto_cache: config.auto_cache,
+            popularity_threshold: config.popularity_threshold.max(1),
+            auto_cache_interval: Duration::from_secs(config.auto_cache_interval),
+            auto_cache_sources: HashMap::default(),
+            announce_interval: Duration::from_secs(config.announce_interval),
+            announced_hits: HashMap::default(),
+            peer_store: PeerStore::memory(),
+            known_peers: HashMap::default(),
+            peer_store_interval: Duration::from_secs(config.peer_store_interval),
+            reconnect_peers: config.reconnect_peers,
+            stopped: false,
         })
     }
 
+    /// Keep the known peers in `peer_store` across restarts, instead of in memory.
+    pub fn with_peer_store(mut self, peer_store: PeerStore) -> Self {
+        self.peer_store = peer_store;
+        self
+    }
+
     pub fn close_command_receiver(&mut self) {
         self.command_receiver.close();
     }
@@ -346,6 +618,7 @@ where
                     rtt.as_millis(),
                     ping_event.peer.to_base58(),
                 );
+                self.record_peer(ping_event.peer, |stats| stats.record_rtt(rtt));
             }
             Ok(libp2p::ping::Success::Pong) => {
                 trace!(
@@ -359,12 +632,14 @@ where
                     ping_event.peer.to_base58(),
                     error
                 );
+                self.record_peer(ping_event.peer, |stats| stats.ping_failures += 1);
             }
             Err(libp2p::ping::Failure::Timeout) => {
                 warn!(
                     "[PingFailure::Timeout] - no response was received from {}",
                     ping_event.peer.to_base58()
                 );
+                self.record_peer(ping_event.peer, |stats| stats.ping_failures += 1);
             }
             Err(libp2p::ping::Failure::Unsupported) => {
                 debug!(
@@ -392,12 +667,18 @@ where
                     );
                 }
 
-                // check if received identify is from a peer on the same network
-                if info
-                    .protocols
-                    .iter()
-                    .any(|name| name.as_bytes() == KAD_PROTOCOL)
+                // check if received identify is from an allowed peer on the same network
+                if self.peer_filter.is_allowed(&peer_id)
+                    && info
+                        .protocols
+                        .iter()
+                        .any(|name| name.as_bytes() == KAD_PROTOCOL)
                 {
+                    let record = self.known_peers.entry(peer_id).or_default();
+                    record.addresses = info.listen_addrs.clone();
+                    record.protocols = info.protocols.clone();
+                    record.last_seen = unix_time();
+
                     let behaviour = self.swarm.behaviour_mut();
 
                     behaviour.gossipsub.add_explicit_peer(&peer_id);
@@ -458,7 +739,18 @@ where
                 );
             }
             BitswapEvent::Complete(query_id, result) => {
-                if let Some(cid) = self.bitswap_queries.remove(&query_id) {
+                if let Some((cid, peers)) = self.bitswap_queries.remove(&query_id) {
+                    // bitswap does not tell which peer sent the block, every peer asked is credited
+                    let succeeded = result.is_ok();
+                    for peer in peers {
+                        self.record_peer(peer, |stats| {
+                            if succeeded {
+                                stats.bitswap_successes += 1;
+                            } else {
+                                stats.bitswap_failures += 1;
+                            }
+                        });
+                    }
                     if let Some(chans) = self.response_channels.remove(&cid) {
                         for chan in chans.into_iter() {
                             match result {
@@ -492,6 +784,10 @@ where
                 message_id,
                 message,
             } => {
+                if message.topic == Topic::new(URSA_GLOBAL).hash() {
+                    let source = message.source.unwrap_or(propagation_source);
+                    self.handle_announcement(source, &message.data);
+                }
                 self.emit_event(NetworkEvent::Gossipsub(GossipsubEvent::Message {
                     peer_id: propagation_source,
                     message_id,
@@ -535,6 +831,15 @@ where
                         warn!("[KademliaEvent::Bootstrap] - Bootstrap failed: {e:?}");
                     }
                 },
+                QueryResult::GetProviders(result) => self.handle_get_providers(id, result),
+                QueryResult::StartProviding(result) => match result {
+                    Ok(AddProviderOk { key }) => {
+                        debug!("[KademliaEvent::StartProviding] - Published provider record for {key:?}");
+                    }
+                    Err(e) => {
+                        warn!("[KademliaEvent::StartProviding] - Failed to publish provider record: {e:?}");
+                    }
+                },
                 other => debug!("[KademliaEvent::OutboundQueryProgressed] - {id:?}: {other:?}"),
             },
             _ => debug!("[KademliaEvent] - {event:?}"),
@@ -542,10 +847,62 @@ where
         Ok(())
     }
 
+    /// Fetch content from the providers found by a kademlia lookup.
+    ///
+    /// Only the first providers found are used, the rest of the query is cancelled.
+    fn handle_get_providers(&mut self, id: KadQueryId, result: GetProvidersResult) {
+        let providers = match result {
+            Ok(GetProvidersOk::FoundProviders { providers, .. }) => providers,
+            Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => HashSet::new(),
+            Err(e) => {
+                warn!("[KademliaEvent::GetProviders] - Provider lookup failed: {e:?}");
+                HashSet::new()
+            }
+        };
+        let lookup = match self.provider_queries.remove(&id) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        if let Some(mut query) = self.swarm.behaviour_mut().kad.query_mut(&id) {
+            query.finish();
+        }
+
+        let local_peer_id = *self.swarm.local_peer_id();
+        let mut peers: Vec<PeerId> = providers
+            .into_iter()
+            .filter(|peer| *peer != local_peer_id && self.peer_filter.is_allowed(peer))
+            .collect();
+        debug!("[KademliaEvent::GetProviders] - Found providers: {peers:?}");
+
+        for peer in &peers {
+            if !self.swarm.is_connected(peer) {
+                if let Err(e) = self.swarm.dial(*peer) {
+                    warn!("[KademliaEvent::GetProviders] - Failed to dial provider {peer}: {e:?}");
+                }
+            }
+        }
+        // connected peers that did not share a cache summary may still have the content
+        for peer in &self.peers {
+            if !self.peer_cached_content.contains_key(peer) && !peers.contains(peer) {
+                peers.push(*peer);
+            }
+        }
+        match lookup {
+            ProviderLookup::Bitswap(cid) => self.sync_block(cid, peers),
+            ProviderLookup::Graphsync(mut query) => {
+                query.peers = self.rank_peers(peers);
+                self.request_graphsync(query);
+            }
+        }
+    }
+
     pub fn handle_mdns(&mut self, event: MdnsEvent) -> Result<()> {
         match event {
             MdnsEvent::Discovered(discovered_peers) => {
                 for (peer_id, address) in discovered_peers {
+                    if !self.peer_filter.is_allowed(&peer_id) {
+                        continue;
+                    }
                     self.swarm
                         .behaviour_mut()
                         .add_address(&peer_id, address.clone());
@@ -575,38 +932,43 @@ where
                     channel,
                 } => {
                     match request.0 {
-                        RequestType::CarRequest(_) => (),
-                        RequestType::CacheRequest(cid) => {
-                            info!("[BehaviourEvent::RequestMessage] cache request from {peer} for {cid}");
-
-                            let selector = Selector::ExploreRecursive {
-                                limit: RecursionLimit::None,
-                                sequence: Box::new(Selector::ExploreAll {
-                                    next: Box::new(Selector::ExploreRecursiveEdge),
-                                }),
-                                current: None,
+                        RequestType::CacheRequest(cid, size) => {
+                            info!("[BehaviourEvent::RequestMessage] cache request from {peer} for {cid} ({size} bytes)");
+
+                            let fits = self.store.free_bytes().map_or(true, |free| free >= size);
+                            let response = if self.accept_replicas && fits {
+                                self.request_graphsync(GraphsyncQuery {
+                                    root: cid,
+                                    selector: dag_selector(),
+                                    peers: vec![peer],
+                                    sender: None,
+                                    replica_of: Some(peer),
+                                    max_size: Some(size),
+                                    _ingest: self.store.ingest(),
+                                    _limit: Some(self.store.limit_dag(cid, size)),
+                                });
+                                ResponseType::CacheResponse
+                            } else {
+                                debug!("[BehaviourEvent::RequestMessage] declining the cache request for {cid}");
+                                ResponseType::CacheDeclined
                             };
-
-                            let req = Request::builder()
-                                .root(cid.to_bytes())
-                                .selector(selector)
-                                .build()
-                                .unwrap();
                             let swarm = self.swarm.behaviour_mut();
-                            swarm.graphsync.request(peer, req);
                             if swarm
                                 .request_response
-                                .send_response(
-                                    channel,
-                                    UrsaExchangeResponse(ResponseType::CacheResponse),
-                                )
+                                .send_response(channel, UrsaExchangeResponse(response))
                                 .is_err()
                             {
                                 error!("[BehaviourEvent::RequestMessage] failed to send response")
                             }
                         }
                         RequestType::StoreSummary(cache_summary) => {
-                            self.peer_cached_content.insert(peer, *cache_summary);
+                            self.peer_cached_content.insert(
+                                peer,
+                                PeerSummary {
+                                    summary: *cache_summary,
+                                    updated_at: Instant::now(),
+                                },
+                            );
                             if self
                                 .swarm
                                 .behaviour_mut()
@@ -622,6 +984,50 @@ where
                                     )
                             }
                         }
+                        RequestType::SummaryDelta(delta) => {
+                            let applied = match self.peer_cached_content.get_mut(&peer) {
+                                Some(peer_summary) if peer_summary.summary.apply(&delta) => {
+                                    peer_summary.updated_at = Instant::now();
+                                    true
+                                }
+                                _ => false,
+                            };
+                            // ask for the full summary if we missed a delta
+                            let response = if applied {
+                                ResponseType::StoreSummaryRequest
+                            } else {
+                                debug!("[BehaviourEvent::RequestMessage] summary delta from {peer} does not apply, asking for a resync");
+                                ResponseType::SummaryResync
+                            };
+                            if self
+                                .swarm
+                                .behaviour_mut()
+                                .request_response
+                                .send_response(channel, UrsaExchangeResponse(response))
+                                .is_err()
+                            {
+                                error!("[BehaviourEvent::RequestMessage] failed to send SummaryDelta response")
+                            }
+                        }
+                        RequestType::ReplicaStored(cid) => {
+                            let confirmed = self
+                                .replications
+                                .get_mut(&cid)
+                                .map_or(false, |replication| {
+                                    replication.pending.remove(&peer).is_some()
+                                });
+                            if confirmed {
+                                info!("[BehaviourEvent::RequestMessage] {peer} stored a replica of {cid}");
+                                self.replicas.entry(cid).or_default().insert(peer);
+                                // the replication is dropped once every peer asked answered
+                                self.request_replicas(cid);
+                            }
+                            self.send_ack(channel);
+                        }
+                        RequestType::StoreCapacity(free_bytes) => {
+                            self.peer_capacity.insert(peer, free_bytes);
+                            self.send_ack(channel);
+                        }
                     }
                     trace!("[BehaviourEvent::RequestMessage] {} ", peer);
                     self.emit_event(NetworkEvent::RequestMessage { request_id });
@@ -637,6 +1043,24 @@ where
                         response
                     );
 
+                    let resync = matches!(response.0, ResponseType::SummaryResync);
+                    self.summary_update_answered(&peer, request_id, resync);
+
+                    match response.0 {
+                        ResponseType::SummaryResync => {
+                            debug!("[RequestResponseMessage::Response] - {peer} asked for our full summary");
+                        }
+                        ResponseType::CacheDeclined => {
+                            if let Some(cid) = self.replica_requests.remove(&request_id) {
+                                info!("[RequestResponseMessage::Response] - {peer} declined to replicate {cid}");
+                                self.replica_failed(cid, peer);
+                            }
+                        }
+                        _ => {
+                            self.replica_requests.remove(&request_id);
+                        }
+                    }
+
                     if let Some(request) = self.pending_responses.remove(&request_id) {
                         if request.send(Ok(response)).is_err() {
                             warn!("[RequestResponseMessage::Response] - failed to send request: {request_id:?}");
@@ -646,13 +1070,101 @@ where
                     debug!("[RequestResponseMessage::Response] - failed to remove channel for: {request_id:?}");
                 }
             },
-            RequestResponseEvent::OutboundFailure { .. }
-            | RequestResponseEvent::InboundFailure { .. }
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                debug!(
+                    "[RequestResponseEvent::OutboundFailure] - request to {peer} failed: {error:?}"
+                );
+                self.record_peer(peer, |stats| stats.request_failures += 1);
+                if let Some(cid) = self.replica_requests.remove(&request_id) {
+                    self.replica_failed(cid, peer);
+                }
+                // the peer may have missed the update
+                self.summary_update_answered(&peer, request_id, true);
+            }
+            RequestResponseEvent::InboundFailure { .. }
             | RequestResponseEvent::ResponseSent { .. } => (),
         }
         Ok(())
     }
 
+    fn handle_car_exchange(
+        &mut self,
+        event: RequestResponseEvent<CarRequest, CarResponse>,
+    ) -> Result<()> {
+        match event {
+            RequestResponseEvent::Message { peer, message } => match message {
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                } => {
+                    info!("[CarExchange] car request from {peer} for {}", request.root);
+                    let response = if self.store.blockstore().has(&request.root)? {
+                        CarResponse::Dag(request)
+                    } else {
+                        CarResponse::NotFound
+                    };
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .car_exchange
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        error!("[CarExchange] failed to send response to {peer}");
+                    }
+                }
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                } => {
+                    let result = match response {
+                        CarResponse::Stored { roots, bytes } => {
+                            self.record_peer(peer, |stats| {
+                                stats.transfer_successes += 1;
+                                stats.bytes_served += bytes;
+                            });
+                            Ok(roots)
+                        }
+                        CarResponse::NotFound => {
+                            self.record_peer(peer, |stats| stats.request_failures += 1);
+                            Err(anyhow!("The peer {peer} does not have the dag"))
+                        }
+                        CarResponse::Dag(_) => Err(anyhow!("Unexpected car response from {peer}")),
+                    };
+                    if let Some(sender) = self.car_requests.remove(&request_id) {
+                        if sender.send(result).is_err() {
+                            warn!("[CarExchange] - failed to send car response: {request_id:?}");
+                        }
+                    }
+                }
+            },
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                warn!("[CarExchange] - car request to {peer} failed: {error:?}");
+                self.record_peer(peer, |stats| stats.request_failures += 1);
+                if let Some(sender) = self.car_requests.remove(&request_id) {
+                    if sender
+                        .send(Err(anyhow!("Car request to {peer} failed: {error:?}")))
+                        .is_err()
+                    {
+                        warn!("[CarExchange] - failed to send car response: {request_id:?}");
+                    }
+                }
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                warn!("[CarExchange] - car transfer to {peer} failed: {error:?}");
+            }
+            RequestResponseEvent::ResponseSent { .. } => (),
+        }
+        Ok(())
+    }
+
     fn handle_graphsync(&mut self, event: GraphSyncEvent) -> Result<()> {
         match event {
             GraphSyncEvent::Completed {
@@ -661,6 +1173,9 @@ where
                 received,
             } => {
                 info!("[GraphSyncEvent::Completed]: {id} {peer_id} {received}");
+                if let Some((_, _, query)) = self.graphsync_queries.remove(&id) {
+                    self.complete_graphsync(query, peer_id, received);
+                }
                 Ok(())
             }
             event => {
@@ -703,6 +1218,10 @@ where
                     req_res_event.record();
                     self.handle_req_res(req_res_event)
                 }
+                BehaviourEvent::CarExchange(car_event) => {
+                    car_event.record();
+                    self.handle_car_exchange(car_event)
+                }
                 BehaviourEvent::RelayServer(relay_event) => {
                     relay_event.record();
                     Ok(())
@@ -711,7 +1230,19 @@ where
                 BehaviourEvent::Dcutr(_) => Ok(()),
                 BehaviourEvent::Graphsync(event) => self.handle_graphsync(event),
             },
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.peer_addresses
+                    .insert(peer_id, endpoint.get_remote_address().clone());
+                self.peer_seen(&peer_id);
+                // a new peer gets our summary right away
+                if num_established.get() == 1 {
+                    self.send_cache_summary(&peer_id);
+                }
                 if self.peers.insert(peer_id) {
                     debug!("Peer connected: {peer_id}");
                     self.emit_event(NetworkEvent::PeerConnected(peer_id));
@@ -724,7 +1255,22 @@ where
                 ..
             } => {
                 if num_established == 0 && self.peers.remove(&peer_id) {
+                    self.peer_seen(&peer_id);
                     self.peer_cached_content.remove(&peer_id);
+                    self.summary_updates.remove(&peer_id);
+                    self.peer_capacity.remove(&peer_id);
+                    self.peer_addresses.remove(&peer_id);
+                    // replicas the peer did not confirm are asked to another peer
+                    let pending: Vec<Cid> = self
+                        .replications
+                        .iter()
+                        .filter(|(_, replication)| replication.pending.contains_key(&peer_id))
+                        .map(|(cid, _)| *cid)
+                        .collect();
+                    for cid in pending {
+                        self.replica_failed(cid, peer_id);
+                    }
+                    self.expire_graphsync(Some(peer_id));
                     debug!("Peer disconnected: {peer_id}");
                     self.emit_event(NetworkEvent::PeerDisconnected(peer_id));
                 }
@@ -740,76 +1286,161 @@ where
             NetworkCommand::GetBitswap { cid, sender } => {
                 info!("Getting cid {cid} via bitswap");
 
-                let peers = self.peers.clone();
+                if let Some(chans) = self.response_channels.get_mut(&cid) {
+                    chans.push(sender);
+                } else {
+                    self.response_channels.insert(cid, vec![sender]);
+                }
+
+                // a lookup for this cid is already running and will answer every channel
+                if self.provider_queries.values().any(
+                    |lookup| matches!(lookup, ProviderLookup::Bitswap(pending) if *pending == cid),
+                ) {
+                    return Ok(());
+                }
 
+                let peers = self.summarized_peers(&cid);
                 if peers.is_empty() {
-                    error!(
-                        "There were no peers provided and the block does not exist in local store"
-                    );
-                    return sender
-                        .send(Err(anyhow!(
-                        "There were no peers provided and the block does not exist in local store"
-                    )))
-                        .map_err(|_| anyhow!("Failed to get a bitswap block!"));
+                    debug!("[NetworkCommand::GetBitswap] - no connected peer has {cid}, looking up providers");
+                    let query_id = self.swarm.behaviour_mut().get_providers(&cid);
+                    self.provider_queries
+                        .insert(query_id, ProviderLookup::Bitswap(cid));
                 } else {
-                    if let Some(chans) = self.response_channels.get_mut(&cid) {
-                        chans.push(sender);
-                    } else {
-                        self.response_channels.insert(cid, vec![sender]);
-                    }
+                    self.sync_block(cid, peers);
+                }
+            }
+            NetworkCommand::GetGraphsync {
+                root,
+                selector,
+                peers,
+                sender,
+            } => {
+                info!("Getting the dag of {root} via graphsync");
 
-                    let peers = peers
-                        .iter()
-                        .filter(|peer| {
-                            if let Some(cache_summary) = self.peer_cached_content.get(*peer) {
-                                return cache_summary.contains(cid.to_bytes());
-                            }
-                            true
-                        })
+                let peers = if peers.is_empty() {
+                    self.rank_peers(self.summarized_peers(&root))
+                } else {
+                    peers
+                };
+                let query = GraphsyncQuery {
+                    root,
+                    selector,
+                    peers,
+                    sender: Some(sender),
+                    replica_of: None,
+                    max_size: None,
+                    _ingest: self.store.ingest(),
+                    _limit: None,
+                };
+                if query.peers.is_empty() {
+                    debug!("[NetworkCommand::GetGraphsync] - no connected peer has {root}, looking up providers");
+                    let query_id = self.swarm.behaviour_mut().get_providers(&root);
+                    self.provider_queries
+                        .insert(query_id, ProviderLookup::Graphsync(query));
+                } else {
+                    self.request_graphsync(query);
+                }
+            }
+            NetworkCommand::GetCar {
+                peer_id,
+                root,
+                max_depth,
+                sender,
+            } => {
+                let peer_id = match peer_id.or_else(|| {
+                    self.rank_peers(self.summarized_peers(&root))
+                        .first()
                         .copied()
-                        .collect();
-
-                    let query = self.swarm.behaviour_mut().sync_block(cid, peers);
-
-                    if let Ok(query_id) = query {
-                        self.bitswap_queries.insert(query_id, cid);
-                        self.emit_event(NetworkEvent::BitswapWant { cid, query_id });
-                    } else {
-                        error!(
-                            "[NetworkCommand::BitswapWant] - no block found for cid {:?}.",
-                            cid
-                        )
+                }) {
+                    Some(peer_id) => peer_id,
+                    None => {
+                        if sender
+                            .send(Err(anyhow!("No connected peer has {root}")))
+                            .is_err()
+                        {
+                            warn!("[NetworkCommand::GetCar] - failed to send response");
+                        }
+                        return Ok(());
                     }
-                }
+                };
+                info!("Getting the dag of {root} as a car file from {peer_id}");
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .car_exchange
+                    .send_request(&peer_id, CarRequest { root, max_depth });
+                self.car_requests.insert(request_id, sender);
             }
             NetworkCommand::Put { cid, sender } => {
                 // replicate content
-                let swarm = self.swarm.behaviour_mut();
-                for peer in &self.peers {
-                    info!("[NetworkCommand::Put] - sending cache request to peer {peer} for {cid}");
-                    swarm
-                        .request_response
-                        .send_request(peer, UrsaExchangeRequest(RequestType::CacheRequest(cid)));
-                }
-                // update cache summary and share it with the connected peers
-                self.cached_content.insert(&cid.to_bytes());
-                let swarm = self.swarm.behaviour_mut();
-                for peer in &self.peers {
-                    let request = UrsaExchangeRequest(RequestType::StoreSummary(Box::new(
-                        self.cached_content.clone(),
-                    )));
-                    swarm.request_response.send_request(peer, request);
+                let mut peers: Vec<PeerId> = self.peers.iter().copied().collect();
+                self.sort_by_score(&mut peers);
+                let candidates = self.replication_strategy.order(
+                    &cid,
+                    peers,
+                    &PeerPlacement {
+                        capacity: &self.peer_capacity,
+                        addresses: &self.peer_addresses,
+                    },
+                );
+                let size = self
+                    .store
+                    .root_meta(&cid)
+                    .map(|meta| meta.size)
+                    .unwrap_or_default();
+                self.replications.insert(
+                    cid,
+                    Replication {
+                        size,
+                        candidates,
+                        ..Default::default()
+                    },
+                );
+                self.request_replicas(cid);
+                // update cache summary and share the change with the connected peers
+                self.update_cache_summary(SummaryDelta {
+                    inserted: vec![cid.to_bytes()],
+                    ..Default::default()
+                });
+                // announce the content to the rest of the network
+                if let Err(e) = self.swarm.behaviour_mut().start_providing(&cid) {
+                    warn!("[NetworkCommand::Put] - failed to provide {cid}: {e:?}");
                 }
+                self.announce(Announcement::NewContent { cid, size });
 
                 sender
                     .send(Ok(()))
                     .map_err(|e| anyhow!("PUT failed: {e:?}."))?;
             }
+            NetworkCommand::Remove { cid, sender } => {
+                info!("[NetworkCommand::Remove] - removing {cid} from the cache summary");
+                self.remove_content(cid);
+
+                sender
+                    .send(Ok(()))
+                    .map_err(|e| anyhow!("REMOVE failed: {e:?}."))?;
+            }
             NetworkCommand::GetPeers { sender } => {
                 sender
                     .send(self.peers.clone())
                     .map_err(|_| anyhow!("Failed to get Libp2p peers!"))?;
             }
+            NetworkCommand::GetReplicas { cid, sender } => {
+                let confirmed = self.replicas.get(&cid).cloned().unwrap_or_default();
+                sender
+                    .send(confirmed)
+                    .map_err(|_| anyhow!("Failed to get replicas!"))?;
+            }
+            NetworkCommand::GetPeerScores { sender } => {
+                sender
+                    .send(
+                        self.peer_stats
+                            .iter()
+                            .map(|(peer, stats)| (*peer, PeerScore::from(*stats)))
+                            .collect(),
+                    )
+                    .map_err(|_| anyhow!("Failed to get peer scores!"))?;
+            }
             NetworkCommand::GetListenerAddresses { sender } => {
                 let mut addresses: Vec<&Multiaddr> = self.swarm.listeners().collect();
                 if let Some(value) = self.swarm.behaviour().public_address() {
@@ -886,16 +1517,655 @@ where
                         .map_err(|_| anyhow!("Failed to publish message!"))?;
                 }
             },
+            NetworkCommand::Shutdown { sender } => {
+                info!("Saving the known peers before shutdown");
+                self.save_peers();
+                self.stopped = true;
+                if sender.send(()).is_err() {
+                    debug!("The shutdown was not awaited");
+                }
+            }
             #[cfg(test)]
             NetworkCommand::GetPeerContent { sender } => {
                 sender
-                    .send(self.peer_cached_content.clone())
+                    .send(
+                        self.peer_cached_content
+                            .iter()
+                            .map(|(peer, peer_summary)| (*peer, peer_summary.summary.clone()))
+                            .collect(),
+                    )
                     .map_err(|_| anyhow!("Failed to send peer content."))?;
             }
         }
         Ok(())
     }
 
+    /// Connected peers whose cache summary contains `cid`.
+    fn summarized_peers(&self, cid: &Cid) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|peer| {
+                self.peer_cached_content
+                    .get(*peer)
+                    .map(|peer_summary| peer_summary.summary.contains(cid.to_bytes()))
+                    .unwrap_or(false)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Send a graphsync request for the query to the next of its peers,
+    /// failing the query if every peer was asked.
+    fn request_graphsync(&mut self, mut query: GraphsyncQuery) {
+        if query.peers.is_empty() {
+            warn!("[GraphSync] - no peer could send the dag of {}", query.root);
+            let error = anyhow!("The dag of {} is not found with any peers", query.root);
+            return Self::fail_graphsync(query, error);
+        }
+
+        let request = match Request::builder()
+            .root(query.root.to_bytes())
+            .selector(query.selector.clone())
+            .build()
+        {
+            Ok(request) => request,
+            Err(e) => {
+                error!("[GraphSync] - invalid request for {}: {e:?}", query.root);
+                let error = anyhow!("Invalid graphsync request for {}: {e:?}", query.root);
+                return Self::fail_graphsync(query, error);
+            }
+        };
+        let peer = query.peers.remove(0);
+        let id = request.id;
+        self.swarm.behaviour_mut().graphsync.request(peer, request);
+        self.graphsync_queries
+            .insert(id, (peer, Instant::now(), query));
+    }
+
+    /// Answer a graphsync query with `error`, if it has a sender.
+    fn fail_graphsync(query: GraphsyncQuery, error: Error) {
+        if let Some(sender) = query.sender {
+            if sender.send(Err(error)).is_err() {
+                error!("[GraphSync] - Graphsync response channel send failed");
+            }
+        }
+    }
+
+    /// Answer a completed graphsync query, or ask the next peer if nothing was received.
+    ///
+    /// The dag is measured, and the content we cache recorded, on a blocking
+    /// task, which reports back to the service loop with a [`FetchedDag`].
+    fn complete_graphsync(&mut self, query: GraphsyncQuery, peer_id: PeerId, received: usize) {
+        if received == 0 {
+            debug!(
+                "[GraphSyncEvent::Completed] - {peer_id} sent no blocks of {}",
+                query.root
+            );
+            self.record_peer(peer_id, |stats| stats.request_failures += 1);
+            return self.request_graphsync(query);
+        }
+
+        let store = Arc::clone(&self.store);
+        let fetched_sender = self.fetched_sender.clone();
+        task::spawn_blocking(move || {
+            let (bytes, complete) = store
+                .dag_status(&query.root)
+                .map(|status| (status.present_bytes, status.is_complete()))
+                .unwrap_or_default();
+            let recorded =
+                query.sender.is_none() && Self::record_fetched(&store, &query, bytes, complete);
+            let fetched = FetchedDag {
+                query,
+                peer_id,
+                received,
+                bytes,
+                recorded,
+            };
+            if fetched_sender.send(fetched).is_err() {
+                debug!("[GraphSyncEvent::Completed] - the service stopped");
+            }
+        });
+    }
+
+    /// Record the content we cache once its dag is here, unless it is incomplete,
+    /// larger than announced or than the room left for it, in which case it is removed.
+    fn record_fetched(
+        store: &UrsaStore<S>,
+        query: &GraphsyncQuery,
+        bytes: u64,
+        complete: bool,
+    ) -> bool {
+        let fits = if !complete {
+            warn!(
+                "[GraphSyncEvent::Completed] - the dag of {} is missing blocks",
+                query.root
+            );
+            false
+        } else if query.max_size.map_or(false, |max_size| bytes > max_size) {
+            warn!(
+                "[GraphSyncEvent::Completed] - the dag of {} is {bytes} bytes, larger than announced",
+                query.root
+            );
+            false
+        } else if store.free_bytes().map_or(false, |free| bytes > free) {
+            debug!(
+                "[GraphSyncEvent::Completed] - no room left for the {bytes} bytes of {}",
+                query.root
+            );
+            false
+        } else {
+            true
+        };
+        if !fits {
+            if let Err(e) = store.remove(&query.root) {
+                error!(
+                    "[GraphSyncEvent::Completed] failed to remove {}: {e:?}",
+                    query.root
+                );
+            }
+            return false;
+        }
+
+        match store.add_root(&query.root, ContentSource::Graphsync) {
+            Ok(()) => true,
+            Err(e) => {
+                error!(
+                    "[GraphSyncEvent::Completed] failed to record root {}: {e:?}",
+                    query.root
+                );
+                false
+            }
+        }
+    }
+
+    /// Answer a graphsync query once its dag is stored, and provide the content we cache.
+    fn finish_graphsync(&mut self, fetched: FetchedDag) {
+        let FetchedDag {
+            query,
+            peer_id,
+            received,
+            bytes,
+            recorded,
+        } = fetched;
+        self.record_peer(peer_id, |stats| {
+            stats.transfer_successes += 1;
+            stats.bytes_served += bytes;
+        });
+
+        if let Some(sender) = query.sender {
+            if sender.send(Ok(received)).is_err() {
+                error!("[GraphSyncEvent::Completed] - Graphsync response channel send failed");
+            }
+        } else if recorded {
+            if let Some(peer) = query.replica_of {
+                self.swarm.behaviour_mut().request_response.send_request(
+                    &peer,
+                    UrsaExchangeRequest(RequestType::ReplicaStored(query.root)),
+                );
+            }
+            self.update_cache_summary(SummaryDelta {
+                inserted: vec![query.root.to_bytes()],
+                ..Default::default()
+            });
+            if let Err(e) = self.swarm.behaviour_mut().start_providing(&query.root) {
+                warn!(
+                    "[GraphSyncEvent::Completed] failed to provide {}: {e:?}",
+                    query.root
+                );
+            }
+        }
+    }
+
+    /// Ask the next peer for the graphsync queries of a disconnected peer,
+    /// or whose peer did not complete them in time.
+    fn expire_graphsync(&mut self, disconnected: Option<PeerId>) {
+        let timeout = self.graphsync_timeout;
+        let expired: Vec<GraphSyncRequestId> = self
+            .graphsync_queries
+            .iter()
+            .filter(|(_, (peer, started, _))| {
+                Some(*peer) == disconnected || started.elapsed() >= timeout
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some((peer, _, query)) = self.graphsync_queries.remove(&id) {
+                debug!(
+                    "[GraphSync] - {peer} did not complete the request {id} for {}",
+                    query.root
+                );
+                self.record_peer(peer, |stats| stats.request_failures += 1);
+                self.request_graphsync(query);
+            }
+        }
+    }
+
+    /// Start a bitswap sync of `cid` from `peers`, failing the pending
+    /// response channels of the cid if there is no one to ask.
+    fn sync_block(&mut self, cid: Cid, peers: Vec<PeerId>) {
+        if peers.is_empty() {
+            warn!("[NetworkCommand::GetBitswap] - no providers found for {cid}");
+            if let Some(chans) = self.response_channels.remove(&cid) {
+                for chan in chans {
+                    if chan
+                        .send(Err(anyhow!(
+                            "The requested block with cid {cid:?} is not found with any peers"
+                        )))
+                        .is_err()
+                    {
+                        error!(
+                            "[NetworkCommand::GetBitswap] - Bitswap response channel send failed"
+                        );
+                    }
+                }
+            }
+            return;
+        }
+
+        let peers = self.rank_peers(peers);
+        match self.swarm.behaviour_mut().sync_block(cid, peers.clone()) {
+            Ok(query_id) => {
+                self.bitswap_queries.insert(query_id, (cid, peers));
+                self.emit_event(NetworkEvent::BitswapWant { cid, query_id });
+            }
+            Err(e) => error!("[NetworkCommand::GetBitswap] - failed to sync {cid}: {e:?}"),
+        }
+    }
+
+    /// Update the time a known peer was last seen.
+    fn peer_seen(&mut self, peer: &PeerId) {
+        if let Some(record) = self.known_peers.get_mut(peer) {
+            record.last_seen = unix_time();
+        }
+    }
+
+    /// Forget the peers not seen for long and the statistics of the peers
+    /// neither known nor connected, then save the known peers with their
+    /// latest statistics.
+    fn save_peers(&mut self) {
+        prune_peers(&mut self.known_peers);
+        // the statistics of the other peers are dropped with them
+        self.peer_stats
+            .retain(|peer, _| self.known_peers.contains_key(peer) || self.peers.contains(peer));
+        for (peer, record) in self.known_peers.iter_mut() {
+            if let Some(stats) = self.peer_stats.get(peer) {
+                record.stats = *stats;
+            }
+        }
+        if let Err(e) = self.peer_store.save(&self.known_peers) {
+            warn!("Failed to save the known peers: {e:?}");
+        }
+    }
+
+    /// Seed kademlia with the saved peers and dial the best scored ones.
+    fn restore_peers(&mut self) {
+        let saved = match self.peer_store.load() {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("Failed to load the known peers: {e:?}");
+                return;
+            }
+        };
+
+        let local_peer_id = *self.swarm.local_peer_id();
+        let mut peers = Vec::new();
+        for (peer_id, record) in saved {
+            if peer_id == local_peer_id
+                || record.addresses.is_empty()
+                || !self.peer_filter.is_allowed(&peer_id)
+            {
+                continue;
+            }
+            for address in &record.addresses {
+                self.swarm
+                    .behaviour_mut()
+                    .add_address(&peer_id, address.clone());
+            }
+            self.peer_stats.entry(peer_id).or_insert(record.stats);
+            self.known_peers.entry(peer_id).or_insert(record);
+            peers.push(peer_id);
+        }
+        if peers.is_empty() {
+            return;
+        }
+        info!("Restored {} known peers", peers.len());
+
+        self.sort_by_score(&mut peers);
+        for peer_id in peers.into_iter().take(self.reconnect_peers) {
+            let opts = DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::Disconnected)
+                .addresses(self.known_peers[&peer_id].addresses.clone())
+                .build();
+            if let Err(e) = self.swarm.dial(opts) {
+                debug!("Failed to dial the known peer {peer_id}: {e:?}");
+            }
+        }
+        if let Err(e) = self.swarm.behaviour_mut().kad.bootstrap() {
+            debug!("Failed to bootstrap kademlia from the known peers: {e:?}");
+        }
+    }
+
+    /// Update the statistics of a peer and its reputation metric.
+    fn record_peer(&mut self, peer: PeerId, update: impl FnOnce(&mut PeerStats)) {
+        let stats = self.peer_stats.entry(peer).or_default();
+        update(stats);
+        // a label per peer would grow without bound, the scores are only
+        // exported as a distribution
+        histogram!("peer_reputation", stats.score());
+    }
+
+    /// Sort peers from the best to the worst score. Peers we never
+    /// exchanged with are scored as an unknown peer.
+    fn sort_by_score(&self, peers: &mut [PeerId]) {
+        let score = |peer: &PeerId| {
+            self.peer_stats
+                .get(peer)
+                .copied()
+                .unwrap_or_default()
+                .score()
+        };
+        peers.sort_by(|a, b| score(b).total_cmp(&score(a)));
+    }
+
+    /// The `max_peers_per_request` best scored peers, best first.
+    fn rank_peers(&self, mut peers: Vec<PeerId>) -> Vec<PeerId> {
+        self.sort_by_score(&mut peers);
+        peers.truncate(self.max_peers_per_request);
+        peers
+    }
+
+    /// Send cache requests to the next candidates of a replication until
+    /// enough peers are asked. The replication is dropped once no peer is
+    /// left to confirm, whether enough peers did or not.
+    fn request_replicas(&mut self, cid: Cid) {
+        let confirmed = self.replicas.get(&cid).map_or(0, |peers| peers.len());
+        let replication = match self.replications.get_mut(&cid) {
+            Some(replication) => replication,
+            None => return,
+        };
+        let swarm = self.swarm.behaviour_mut();
+        while replication.pending.len() + confirmed < self.replication_factor
+            && !replication.candidates.is_empty()
+        {
+            let peer = replication.candidates.remove(0);
+            info!("[NetworkCommand::Put] - sending cache request to peer {peer} for {cid}");
+            let request_id = swarm.request_response.send_request(
+                &peer,
+                UrsaExchangeRequest(RequestType::CacheRequest(cid, replication.size)),
+            );
+            self.replica_requests.insert(request_id, cid);
+            replication.pending.insert(peer, Instant::now());
+        }
+        if replication.pending.len() + confirmed < self.replication_factor {
+            warn!(
+                "[NetworkCommand::Put] - only {} peers can replicate {cid}, {} wanted",
+                replication.pending.len() + confirmed,
+                self.replication_factor
+            );
+        }
+        if replication.pending.is_empty() {
+            self.replications.remove(&cid);
+        }
+    }
+
+    /// Ask another peer to replicate `cid` in place of `peer`.
+    fn replica_failed(&mut self, cid: Cid, peer: PeerId) {
+        if let Some(replication) = self.replications.get_mut(&cid) {
+            if replication.pending.remove(&peer).is_some() {
+                self.request_replicas(cid);
+            }
+        }
+    }
+
+    /// Replace the peers that did not confirm a replica in time.
+    fn expire_replicas(&mut self) {
+        let timeout = self.replication_timeout;
+        let expired: Vec<(Cid, PeerId)> = self
+            .replications
+            .iter()
+            .flat_map(|(cid, replication)| {
+                replication
+                    .pending
+                    .iter()
+                    .filter(|(_, asked)| asked.elapsed() >= timeout)
+                    .map(move |(peer, _)| (*cid, *peer))
+            })
+            .collect();
+        for (cid, peer) in expired {
+            debug!("[Replication] - {peer} did not confirm the replica of {cid} in time");
+            self.record_peer(peer, |stats| stats.request_failures += 1);
+            self.replica_failed(cid, peer);
+        }
+    }
+
+    /// Acknowledge a request that needs no answer.
+    fn send_ack(&mut self, channel: ResponseChannel<UrsaExchangeResponse>) {
+        if self
+            .swarm
+            .behaviour_mut()
+            .request_response
+            .send_response(channel, UrsaExchangeResponse(ResponseType::CacheResponse))
+            .is_err()
+        {
+            error!("[BehaviourEvent::RequestMessage] failed to send response");
+        }
+    }
+
+    /// Publish an announcement on the global topic.
+    fn announce(&mut self, announcement: Announcement) {
+        let data = match announcement.encode() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("[Announcement] - failed to encode {announcement:?}: {e:?}");
+                return;
+            }
+        };
+        if let Err(e) = self
+            .swarm
+            .behaviour_mut()
+            .publish(Topic::new(URSA_GLOBAL), data)
+        {
+            debug!("[Announcement] - failed to publish {announcement:?}: {e:?}");
+        }
+    }
+
+    /// Act on an announcement published by `source`, fetching the content
+    /// announced as popular if we have room for it.
+    fn handle_announcement(&mut self, source: PeerId, data: &[u8]) {
+        let announcement = match Announcement::decode(data) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                debug!("[Announcement] - invalid announcement from {source}: {e:?}");
+                return;
+            }
+        };
+        match announcement {
+            Announcement::NewContent { cid, size } => {
+                debug!("[Announcement] - {source} stored {cid} ({size} bytes)");
+            }
+            Announcement::Evicted(cid) => {
+                debug!("[Announcement] - {source} evicted {cid}");
+            }
+            Announcement::Requested { cid, size, count } => {
+                if !self.auto_cache || count < self.popularity_threshold {
+                    return;
+                }
+                if self.store.blockstore().has(&cid).unwrap_or(false)
+                    || self
+                        .graphsync_queries
+                        .values()
+                        .any(|(_, _, query)| query.root == cid)
+                {
+                    return;
+                }
+                if self.store.free_bytes().map_or(false, |free| free < size) {
+                    debug!("[Announcement] - no room to cache the popular content {cid}");
+                    return;
+                }
+                let interval = self.auto_cache_interval;
+                self.auto_cache_sources
+                    .retain(|_, fetched| fetched.elapsed() < interval);
+                if self.auto_cache_sources.contains_key(&source) {
+                    debug!(
+                        "[Announcement] - already fetched content announced by {source} recently"
+                    );
+                    return;
+                }
+                self.auto_cache_sources.insert(source, Instant::now());
+
+                info!("[Announcement] - caching {cid}, requested {count} times from {source}");
+                if !self.swarm.is_connected(&source) {
+                    if let Err(e) = self.swarm.dial(source) {
+                        warn!("[Announcement] - failed to dial {source}: {e:?}");
+                    }
+                }
+                let mut peers = vec![source];
+                peers.extend(
+                    self.rank_peers(self.summarized_peers(&cid))
+                        .into_iter()
+                        .filter(|peer| *peer != source),
+                );
+                self.request_graphsync(GraphsyncQuery {
+                    root: cid,
+                    selector: dag_selector(),
+                    peers,
+                    sender: None,
+                    replica_of: None,
+                    // the dag is bounded by the announced size
+                    max_size: Some(size),
+                    _ingest: self.store.ingest(),
+                    _limit: Some(self.store.limit_dag(cid, size)),
+                });
+            }
+        }
+    }
+
+    /// Announce the roots whose hits crossed a multiple of the popularity threshold.
+    fn announce_popular_content(&mut self) {
+        for (cid, meta) in self.store.roots_meta() {
+            let announced = self.announced_hits.get(&cid).copied().unwrap_or_default();
+            if meta.hits / self.popularity_threshold > announced / self.popularity_threshold {
+                self.announce(Announcement::Requested {
+                    cid,
+                    size: meta.size,
+                    count: meta.hits,
+                });
+                self.announced_hits.insert(cid, meta.hits);
+            }
+        }
+    }
+
+    /// Send our full cache summary to all the connected peers
+    fn share_cache_summary(&mut self) {
+        let peers: Vec<PeerId> = self.peers.iter().copied().collect();
+        for peer in &peers {
+            self.send_cache_summary(peer);
+        }
+    }
+
+    /// Send our full cache summary and free storage to a peer,
+    /// once it answered the update in flight.
+    fn send_cache_summary(&mut self, peer: &PeerId) {
+        let updates = self.summary_updates.entry(*peer).or_default();
+        // the summary holds the queued deltas
+        updates.queued.clear();
+        if updates.in_flight.is_some() {
+            updates.resync = true;
+            return;
+        }
+        updates.resync = false;
+
+        let request = UrsaExchangeRequest(RequestType::StoreSummary(Box::new(
+            self.cached_content.clone(),
+        )));
+        let capacity = UrsaExchangeRequest(RequestType::StoreCapacity(self.store.free_bytes()));
+        let swarm = self.swarm.behaviour_mut();
+        updates.in_flight = Some(swarm.request_response.send_request(peer, request));
+        swarm.request_response.send_request(peer, capacity);
+    }
+
+    /// Send the next update queued for a peer, if it answered the previous one.
+    fn send_summary_update(&mut self, peer: &PeerId) {
+        let updates = match self.summary_updates.get_mut(peer) {
+            Some(updates) if updates.in_flight.is_none() => updates,
+            _ => return,
+        };
+        if updates.resync {
+            return self.send_cache_summary(peer);
+        }
+        if let Some(delta) = updates.queued.pop_front() {
+            let request = UrsaExchangeRequest(RequestType::SummaryDelta(delta));
+            let request_id = self
+                .swarm
+                .behaviour_mut()
+                .request_response
+                .send_request(peer, request);
+            updates.in_flight = Some(request_id);
+        }
+    }
+
+    /// A peer answered the summary update in flight, or failed to. On `resync`
+    /// it gets the full summary next.
+    fn summary_update_answered(&mut self, peer: &PeerId, request_id: RequestId, resync: bool) {
+        if !self.peers.contains(peer) {
+            self.summary_updates.remove(peer);
+            return;
+        }
+        match self.summary_updates.get_mut(peer) {
+            Some(updates) if updates.in_flight == Some(request_id) => {
+                updates.in_flight = None;
+                if resync {
+                    updates.queued.clear();
+                    updates.resync = true;
+                }
+            }
+            _ => return,
+        }
+        self.send_summary_update(peer);
+    }
+
+    /// Apply a change to our cache summary and queue it for all the connected peers.
+    /// Peers that missed a previous change answer with a resync and get the full summary.
+    fn update_cache_summary(&mut self, mut delta: SummaryDelta) {
+        delta.base_version = self.cached_content.version();
+        self.cached_content.apply(&delta);
+
+        let peers: Vec<PeerId> = self.peers.iter().copied().collect();
+        for peer in &peers {
+            let updates = self.summary_updates.entry(*peer).or_default();
+            if updates.resync {
+                // the full summary to send holds the delta
+                continue;
+            }
+            let merged = updates
+                .queued
+                .back_mut()
+                .map_or(false, |last| last.merge(&delta));
+            if !merged {
+                if updates.queued.len() >= MAX_QUEUED_DELTAS {
+                    updates.queued.clear();
+                    updates.resync = true;
+                } else {
+                    updates.queued.push_back(delta.clone());
+                }
+            }
+            self.send_summary_update(peer);
+        }
+    }
+
+    /// Drop the peer summaries that were not updated in time and resend our full summary.
+    fn refresh_cache_summaries(&mut self) {
+        let ttl = self.summary_ttl;
+        self.peer_cached_content.retain(|peer, peer_summary| {
+            let fresh = peer_summary.updated_at.elapsed() < ttl;
+            if !fresh {
+                debug!("Dropping the stale cache summary of {peer}");
+            }
+            fresh
+        });
+        self.share_cache_summary();
+    }
+
     /// Dial remote peer `peer_id` at `address`
     pub fn dial(
         &mut self,
@@ -921,6 +2191,19 @@ where
         }
     }
 
+    /// Stop providing a root removed from the store.
+    fn remove_content(&mut self, cid: Cid) {
+        self.update_cache_summary(SummaryDelta {
+            removed: vec![cid.to_bytes()],
+            ..Default::default()
+        });
+        self.swarm.behaviour_mut().stop_providing(&cid);
+        self.replications.remove(&cid);
+        self.replicas.remove(&cid);
+        self.announced_hits.remove(&cid);
+        self.announce(Announcement::Evicted(cid));
+    }
+
     /// Start the ursa network service loop.
     ///
     /// Poll `swarm` and `command_receiver` from [`UrsaService`].
@@ -934,7 +2217,21 @@ where
 
         let kad_walk_delay = sleep(Duration::from_secs(self.kad_walk_interval));
         tokio::pin!(kad_walk_delay);
-
+        let summary_refresh_delay = sleep(self.summary_refresh_interval);
+        tokio::pin!(summary_refresh_delay);
+        let announce_delay = sleep(self.announce_interval);
+        tokio::pin!(announce_delay);
+        let peer_store_delay = sleep(self.peer_store_interval);
+        tokio::pin!(peer_store_delay);
+        // queries expire between one and one and a half timeouts
+        let graphsync_expiry_delay = sleep(self.graphsync_timeout / 2);
+        tokio::pin!(graphsync_expiry_delay);
+        let replication_expiry_delay = sleep(self.replication_timeout / 2);
+        tokio::pin!(replication_expiry_delay);
+
+        self.restore_peers();
+
+        let mut evictions = self.store.subscribe_evictions();
         loop {
             select! {
                 event = self.swarm.next() => {
@@ -944,17 +2241,74 @@ where
                 command = self.command_receiver.recv() => {
                     let command = command.ok_or_else(|| anyhow!("Command invalid!"))?;
                     self.handle_command(command).expect("Handle rpc command.");
+                    if self.stopped {
+                        return Ok(());
+                    }
+                },
+                fetched = self.fetched_receiver.recv() => {
+                    // the service holds a sender, the channel is never closed
+                    if let Some(fetched) = fetched {
+                        self.finish_graphsync(fetched);
+                    }
+                },
+                evicted = evictions.recv() => {
+                    match evicted {
+                        Ok(cid) => {
+                            info!("Removing evicted {cid} from the cache summary");
+                            self.remove_content(cid);
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("Missed {missed} evicted roots, they stay in the cache summary");
+                        }
+                        // the store, which holds the sender, outlives the service
+                        Err(RecvError::Closed) => {}
+                    }
                 },
                 _ = &mut kad_walk_delay => {
                     info!("Starting random kademlia walk");
                     self.swarm.behaviour_mut().kad.get_closest_peers(PeerId::random());
                     kad_walk_delay.as_mut().reset(Instant::now() + Duration::from_secs(self.kad_walk_interval));
                 }
+                _ = &mut summary_refresh_delay => {
+                    debug!("Refreshing cache summaries");
+                    self.refresh_cache_summaries();
+                    summary_refresh_delay.as_mut().reset(Instant::now() + self.summary_refresh_interval);
+                }
+                _ = &mut announce_delay => {
+                    self.announce_popular_content();
+                    announce_delay.as_mut().reset(Instant::now() + self.announce_interval);
+                }
+                _ = &mut graphsync_expiry_delay => {
+                    self.expire_graphsync(None);
+                    graphsync_expiry_delay.as_mut().reset(Instant::now() + self.graphsync_timeout / 2);
+                }
+                _ = &mut replication_expiry_delay => {
+                    self.expire_replicas();
+                    replication_expiry_delay.as_mut().reset(Instant::now() + self.replication_timeout / 2);
+                }
+                _ = &mut peer_store_delay => {
+                    debug!("Saving the known peers");
+                    self.save_peers();
+                    peer_store_delay.as_mut().reset(Instant::now() + self.peer_store_interval);
+                }
             }
         }
     }
 }
 
+impl<S> Drop for UrsaService<S>
+where
+    S: Blockstore + Clone + Store + Send + Sync + 'static,
+{
+    /// Best effort to save the known peers when the service stops without
+    /// a [`NetworkCommand::Shutdown`], e.g. aborted.
+    fn drop(&mut self) {
+        if !self.stopped {
+            self.save_peers();
+        }
+    }
+}
+
 #[cfg(test)]
 #[path = "tests/service_tests.rs"]
 mod service_tests;
@@ -1,8 +1,15 @@
 use crate::behaviour::BehaviourEvent;
-use crate::utils::cache_summary::CacheSummary;
+use crate::utils::{
+    cache_summary::{CacheSummary, SummaryDelta},
+    peer_store::{unix_time, PeerRecord, PeerStore},
+    reputation::PeerStats,
+};
 use crate::{
     codec::protocol::{RequestType, UrsaExchangeRequest},
-    NetworkCommand, NetworkConfig, UrsaService, URSA_GLOBAL,
+    dag_selector,
+    gossipsub::Announcement,
+    service::Replication,
+    MuxerKind, NetworkCommand, NetworkConfig, TransportKind, UrsaService, URSA_GLOBAL,
 };
 use anyhow::Result;
 use async_fs::File;
@@ -13,7 +20,7 @@ use futures::StreamExt;
 use fvm_ipld_car::{load_car, CarReader};
 use ipld_traversal::blockstore::Blockstore;
 use libipld::{cbor::DagCborCodec, ipld, multihash::Code, Block, Cid, DefaultParams, Ipld};
-use libp2p::kad::{BootstrapOk, KademliaEvent, QueryResult};
+use libp2p::kad::{AddProviderOk, BootstrapOk, KademliaEvent, QueryResult};
 use libp2p::request_response::RequestResponseEvent;
 use libp2p::{
     gossipsub::IdentTopic as Topic, identity::Keypair, multiaddr::Protocol, swarm::SwarmEvent,
@@ -26,7 +33,7 @@ use std::{sync::Arc, time::Duration, vec};
 use tokio::{select, sync::oneshot, time::timeout};
 use tracing::warn;
 use tracing::{error, info, log::LevelFilter};
-use ursa_store::{BitswapStorage, GraphSyncStorage, UrsaStore};
+use ursa_store::{BitswapStorage, ContentSource, GraphSyncStorage, UrsaStore};
 
 fn create_block(ipld: Ipld) -> Block<DefaultParams> {
     Block::encode(DagCborCodec, Code::Blake3_256, &ipld).unwrap()
@@ -273,7 +280,7 @@ async fn test_network_req_res() -> Result<()> {
     tokio::task::spawn(async move { node_1.start().await.unwrap() });
 
     let (sender, _) = oneshot::channel();
-    let request = UrsaExchangeRequest(RequestType::CarRequest("Qm".to_string()));
+    let request = UrsaExchangeRequest(RequestType::StoreCapacity(Some(1024)));
     let msg = NetworkCommand::SendRequest {
         peer_id: peer_id_2,
         request: Box::new(request),
@@ -432,6 +439,111 @@ async fn test_bitswap_sync() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_graphsync_get() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, peer_id_1, store_1) =
+        network_init(&mut config, None, None).await?;
+    let (node_2, _, _, store_2) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    // Wait for at least one connection
+    loop {
+        if let SwarmEvent::ConnectionEstablished { .. } = node_1.swarm.select_next_some().await {
+            break;
+        }
+    }
+
+    let node_2_sender = node_2.command_sender();
+
+    // Start nodes
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // put the car file in store 1
+    let file = File::open(Path::new("../../test_files/test.car")).await?;
+    let cids = load_car(store_1.blockstore(), BufReader::new(file)).await?;
+    assert!(!store_2.dag_status(&cids[0])?.is_complete());
+
+    let (sender, receiver) = oneshot::channel();
+    let msg = NetworkCommand::GetGraphsync {
+        root: cids[0],
+        selector: dag_selector(),
+        peers: vec![peer_id_1],
+        sender,
+    };
+    assert!(node_2_sender.send(msg).is_ok());
+
+    let received = timeout(Duration::from_secs(10), receiver)
+        .await
+        .expect("graphsync request to complete")??;
+    assert!(received > 0);
+    assert!(store_2.dag_status(&cids[0])?.is_complete());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_car_transfer() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, peer_id_1, store_1) =
+        network_init(&mut config, None, None).await?;
+    let (node_2, _, _, store_2) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    // Wait for at least one connection
+    loop {
+        if let SwarmEvent::ConnectionEstablished { .. } = node_1.swarm.select_next_some().await {
+            break;
+        }
+    }
+
+    let node_2_sender = node_2.command_sender();
+
+    // Start nodes
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // put the car file in store 1
+    let file = File::open(Path::new("../../test_files/test.car")).await?;
+    let root = load_car(store_1.blockstore(), BufReader::new(file)).await?[0];
+
+    // the root block alone, then the whole dag
+    for max_depth in [Some(0), None] {
+        let (sender, receiver) = oneshot::channel();
+        let msg = NetworkCommand::GetCar {
+            peer_id: Some(peer_id_1),
+            root,
+            max_depth,
+            sender,
+        };
+        assert!(node_2_sender.send(msg).is_ok());
+
+        let roots = timeout(Duration::from_secs(10), receiver)
+            .await
+            .expect("car transfer to complete")??;
+        assert_eq!(roots, vec![root]);
+        let status = store_2.dag_status(&root)?;
+        assert_eq!(status.is_complete(), max_depth.is_none());
+    }
+    assert_eq!(store_2.dag_traversal(&root)?, store_1.dag_traversal(&root)?);
+
+    // a root the peer does not have
+    let (sender, receiver) = oneshot::channel();
+    let msg = NetworkCommand::GetCar {
+        peer_id: Some(peer_id_1),
+        root: *get_block(&b"missing"[..]).cid(),
+        max_depth: None,
+        sender,
+    };
+    assert!(node_2_sender.send(msg).is_ok());
+    assert!(receiver.await?.is_err());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_put_command() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -535,6 +647,102 @@ async fn test_put_command() -> Result<()> {
     panic!("Failed to replicate content")
 }
 
+#[tokio::test]
+async fn test_bitswap_get_from_provider() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+
+    // Set up bootstrap.
+    let (bootstrap, bootstrap_addr, bootstrap_id) =
+        run_bootstrap(&mut NetworkConfig::default()).await?;
+    tokio::task::spawn(async move { bootstrap.start().await.unwrap() });
+
+    // Set up node 1 with a block in its store.
+    let (mut node_1, .., store_1) = network_init(
+        &mut NetworkConfig::default(),
+        Some(bootstrap_addr.clone()),
+        None,
+    )
+    .await?;
+    let block = get_block(&b"provided block"[..]);
+    insert_block(BitswapStorage(store_1.clone()), &block);
+
+    // Wait for node 1 to identify with bootstrap.
+    loop {
+        if let SwarmEvent::Behaviour(BehaviourEvent::Identify(libp2p::identify::Event::Sent {
+            peer_id,
+            ..
+        })) = node_1.swarm.select_next_some().await
+        {
+            if peer_id == bootstrap_id {
+                break;
+            }
+        }
+    }
+
+    // Put the block and wait for the provider record to be published.
+    let (sender, receiver) = oneshot::channel();
+    node_1.handle_command(NetworkCommand::Put {
+        cid: *block.cid(),
+        sender,
+    })?;
+    assert!(receiver.await?.is_ok());
+    loop {
+        if let SwarmEvent::Behaviour(BehaviourEvent::Kad(
+            KademliaEvent::OutboundQueryProgressed {
+                result: QueryResult::StartProviding(result),
+                ..
+            },
+        )) = timeout(Duration::from_secs(10), node_1.swarm.select_next_some())
+            .await
+            .expect("provider record to be published")
+        {
+            let AddProviderOk { key } = result?;
+            assert_eq!(key.to_vec(), block.cid().to_bytes());
+            break;
+        }
+    }
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+
+    // Set up node 2, which only knows the bootstrap node.
+    let (mut node_2, .., store_2) =
+        network_init(&mut NetworkConfig::default(), Some(bootstrap_addr), None).await?;
+    loop {
+        if let SwarmEvent::Behaviour(BehaviourEvent::Kad(
+            KademliaEvent::OutboundQueryProgressed {
+                result: QueryResult::Bootstrap(Ok(BootstrapOk { num_remaining, .. })),
+                ..
+            },
+        )) = node_2.swarm.select_next_some().await
+        {
+            if num_remaining == 0 {
+                break;
+            }
+        }
+    }
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // Node 2 has no cache summary for the block and resolves its providers through kademlia.
+    let (sender, receiver) = oneshot::channel();
+    assert!(node_2_sender
+        .send(NetworkCommand::GetBitswap {
+            cid: *block.cid(),
+            sender,
+        })
+        .is_ok());
+    timeout(Duration::from_secs(10), receiver)
+        .await
+        .expect("block to be fetched")??;
+
+    let mut bitswap_store_2 = BitswapStorage(store_2);
+    assert_eq!(
+        bitswap_store_2.get(block.cid())?,
+        Some(block.data().to_vec())
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_send_cache_summary() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -572,10 +780,11 @@ async fn test_send_cache_summary() -> Result<()> {
     }
 
     // check if cid exists
-    let cached_content = node_2
+    let cached_content = &node_2
         .peer_cached_content
         .get(&peer_id_1)
-        .expect("Peer id not contained in peer content.");
+        .expect("Peer id not contained in peer content.")
+        .summary;
     assert!(
         cached_content.contains(Cid::default().to_bytes()),
         "CID not contained in cache summary."
@@ -583,3 +792,575 @@ async fn test_send_cache_summary() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_cache_summary_delta() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node_1, node_1_addrs, peer_id_1, ..) = network_init(&mut config, None, None).await?;
+    let (node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    let node_1_sender = node_1.command_sender();
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // poll the summary node 2 holds for node 1 until it matches
+    let wait_for_summary = |expected: bool| {
+        let sender = node_2_sender.clone();
+        async move {
+            for _ in 0..50 {
+                let (tx, rx) = oneshot::channel();
+                sender.send(NetworkCommand::GetPeerContent { sender: tx })?;
+                if let Some(summary) = rx.await?.get(&peer_id_1) {
+                    if summary.contains(Cid::default().to_bytes()) == expected {
+                        return Ok::<_, anyhow::Error>(summary.version());
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            panic!("the summary of node 1 was not updated")
+        }
+    };
+
+    // the full summary is sent on connection
+    assert_eq!(wait_for_summary(false).await?, 0);
+
+    let (sender, receiver) = oneshot::channel();
+    node_1_sender.send(NetworkCommand::Put {
+        cid: Cid::default(),
+        sender,
+    })?;
+    receiver.await??;
+    assert_eq!(wait_for_summary(true).await?, 1);
+
+    let (sender, receiver) = oneshot::channel();
+    node_1_sender.send(NetworkCommand::Remove {
+        cid: Cid::default(),
+        sender,
+    })?;
+    receiver.await??;
+    assert_eq!(wait_for_summary(false).await?, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_scores() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    // node 2 must fetch the block over bitswap, not as a replica
+    let mut config = NetworkConfig {
+        accept_replicas: false,
+        ..Default::default()
+    };
+
+    let (node_1, node_1_addrs, peer_id_1, store_1) = network_init(&mut config, None, None).await?;
+    let (node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    let block = get_block(&b"hello reputation"[..]);
+    insert_block(BitswapStorage(store_1.clone()), &block);
+
+    let node_1_sender = node_1.command_sender();
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // announce the block and wait for node 2 to know node 1 has it
+    let (sender, receiver) = oneshot::channel();
+    node_1_sender.send(NetworkCommand::Put {
+        cid: *block.cid(),
+        sender,
+    })?;
+    receiver.await??;
+    for _ in 0..50 {
+        let (tx, rx) = oneshot::channel();
+        node_2_sender.send(NetworkCommand::GetPeerContent { sender: tx })?;
+        if let Some(summary) = rx.await?.get(&peer_id_1) {
+            if summary.contains(block.cid().to_bytes()) {
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::GetBitswap {
+        cid: *block.cid(),
+        sender,
+    })?;
+    receiver.await??;
+
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::GetPeerScores { sender })?;
+    let scores = receiver.await?;
+    let score = scores.get(&peer_id_1).expect("node 1 to be scored");
+    assert_eq!(score.stats.bitswap_successes, 1);
+    assert_eq!(score.stats.bitswap_failures, 0);
+    assert!(score.score > PeerStats::default().score());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_confirmed() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        replication_factor: 1,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (node_1, node_1_addrs, _, store_1) = network_init(&mut config, None, None).await?;
+    // node 2 declines every cache request, node 3 has to replicate
+    let (node_2, _, peer_id_2, store_2) = network_init(
+        &mut NetworkConfig {
+            accept_replicas: false,
+            ..Default::default()
+        },
+        Some(node_1_addrs.clone()),
+        None,
+    )
+    .await?;
+    let (node_3, _, peer_id_3, store_3) =
+        network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    let block = get_block(&b"hello replicas"[..]);
+    GraphSyncStorage(store_1.clone()).insert(&block).unwrap();
+    store_1.add_root(block.cid(), ContentSource::Upload)?;
+
+    let node_1_sender = node_1.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+    tokio::task::spawn(async move { node_3.start().await.unwrap() });
+
+    // wait for both nodes to connect
+    for _ in 0..50 {
+        let (sender, receiver) = oneshot::channel();
+        node_1_sender.send(NetworkCommand::GetPeers { sender })?;
+        let peers = receiver.await?;
+        if peers.contains(&peer_id_2) && peers.contains(&peer_id_3) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let (sender, receiver) = oneshot::channel();
+    node_1_sender.send(NetworkCommand::Put {
+        cid: *block.cid(),
+        sender,
+    })?;
+    receiver.await??;
+
+    for _ in 0..50 {
+        let (sender, receiver) = oneshot::channel();
+        node_1_sender.send(NetworkCommand::GetReplicas {
+            cid: *block.cid(),
+            sender,
+        })?;
+        let replicas = receiver.await?;
+        if !replicas.is_empty() {
+            assert_eq!(replicas.into_iter().collect::<Vec<_>>(), vec![peer_id_3]);
+            assert!(GraphSyncStorage(store_3).has(block.cid()).unwrap());
+            assert!(!GraphSyncStorage(store_2).has(block.cid()).unwrap());
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("No peer confirmed the replica")
+}
+
+#[tokio::test]
+async fn test_auto_cache_popular_content() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        popularity_threshold: 2,
+        announce_interval: 1,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (node_1, node_1_addrs, _, store_1) = network_init(&mut config, None, None).await?;
+    let (node_2, _, peer_id_2, store_2) =
+        network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    let block = get_block(&b"hello popular content"[..]);
+    GraphSyncStorage(store_1.clone()).insert(&block).unwrap();
+    store_1.add_root(block.cid(), ContentSource::Upload)?;
+
+    let node_1_sender = node_1.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    for _ in 0..50 {
+        let (sender, receiver) = oneshot::channel();
+        node_1_sender.send(NetworkCommand::GetPeers { sender })?;
+        if receiver.await?.contains(&peer_id_2) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    // let node 2 subscribe to the global topic before the content gets popular
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    store_1.touch(block.cid());
+    store_1.touch(block.cid());
+
+    for _ in 0..100 {
+        if GraphSyncStorage(store_2.clone()).has(block.cid()).unwrap() {
+            assert!(store_2.root_meta(block.cid()).is_some());
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("The popular content was not cached")
+}
+
+#[tokio::test]
+async fn test_auto_cache_rate_limit() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let popular = |content: &[u8]| Announcement::Requested {
+        cid: *get_block(content).cid(),
+        size: 64,
+        count: config.popularity_threshold,
+    };
+    let source = PeerId::random();
+    node.handle_announcement(source, &popular(&b"first"[..]).encode()?);
+    node.handle_announcement(source, &popular(&b"second"[..]).encode()?);
+    assert_eq!(node.graphsync_queries.len(), 1);
+
+    // other sources are limited on their own
+    node.handle_announcement(PeerId::random(), &popular(&b"third"[..]).encode()?);
+    assert_eq!(node.graphsync_queries.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graphsync_query_expiry() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        graphsync_timeout: 1,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let (peer_1, peer_2) = (PeerId::random(), PeerId::random());
+    let (sender, mut receiver) = oneshot::channel();
+    node.handle_command(NetworkCommand::GetGraphsync {
+        root: *get_block(&b"slow"[..]).cid(),
+        selector: dag_selector(),
+        peers: vec![peer_1, peer_2],
+        sender,
+    })?;
+    let asked = |node: &UrsaService<MemoryDB>| {
+        node.graphsync_queries
+            .values()
+            .map(|(peer, ..)| *peer)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(asked(&node), vec![peer_1]);
+
+    // a query is not expired before its timeout
+    node.expire_graphsync(None);
+    assert_eq!(asked(&node), vec![peer_1]);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    node.expire_graphsync(None);
+    assert_eq!(asked(&node), vec![peer_2]);
+
+    // the query fails once every peer was asked
+    node.expire_graphsync(Some(peer_2));
+    assert!(node.graphsync_queries.is_empty());
+    assert!(receiver.try_recv()?.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_save_peers_prunes_stats() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let (known, stranger) = (PeerId::random(), PeerId::random());
+    node.known_peers.insert(
+        known,
+        PeerRecord {
+            last_seen: unix_time(),
+            ..Default::default()
+        },
+    );
+    for peer in [known, stranger] {
+        node.record_peer(peer, |stats| stats.transfer_successes += 1);
+    }
+    node.save_peers();
+
+    assert!(node.peer_stats.contains_key(&known));
+    assert!(!node.peer_stats.contains_key(&stranger));
+    assert_eq!(node.known_peers[&known].stats.transfer_successes, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_saves_peers() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let peer_store = PeerStore::memory();
+    let (node, ..) = network_init(&mut config, None, None).await?;
+    let mut node = node.with_peer_store(peer_store.clone());
+
+    let known = PeerId::random();
+    node.known_peers.insert(
+        known,
+        PeerRecord {
+            last_seen: unix_time(),
+            ..Default::default()
+        },
+    );
+    let command_sender = node.command_sender();
+    let node_task = tokio::task::spawn(async move { node.start().await });
+
+    // the peers are saved before the service loop ends
+    let (sender, receiver) = oneshot::channel();
+    command_sender.send(NetworkCommand::Shutdown { sender })?;
+    timeout(Duration::from_secs(5), receiver).await??;
+    timeout(Duration::from_secs(5), node_task).await???;
+    assert!(peer_store.load()?.contains_key(&known));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_expiry() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        replication_factor: 1,
+        replication_timeout: 1,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let cid = *get_block(&b"silent replicas"[..]).cid();
+    let (peer_1, peer_2) = (PeerId::random(), PeerId::random());
+    node.replications.insert(
+        cid,
+        Replication {
+            size: 64,
+            candidates: vec![peer_1, peer_2],
+            ..Default::default()
+        },
+    );
+    node.request_replicas(cid);
+    let asked = |node: &UrsaService<MemoryDB>| {
+        node.replications[&cid]
+            .pending
+            .keys()
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(asked(&node), vec![peer_1]);
+
+    // a peer is not replaced before the timeout
+    node.expire_replicas();
+    assert_eq!(asked(&node), vec![peer_1]);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    node.expire_replicas();
+    assert_eq!(asked(&node), vec![peer_2]);
+
+    // the replication is dropped once no peer is left to ask
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    node.expire_replicas();
+    assert!(node.replications.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_summary_updates_in_order() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let peer = PeerId::random();
+    node.peers.insert(peer);
+    node.send_cache_summary(&peer);
+    let summary_request = node.summary_updates[&peer].in_flight.unwrap();
+
+    // the deltas made before the peer answered are merged
+    for content in [&b"first"[..], &b"second"[..]] {
+        node.update_cache_summary(SummaryDelta {
+            inserted: vec![get_block(content).cid().to_bytes()],
+            ..Default::default()
+        });
+    }
+    assert_eq!(node.summary_updates[&peer].queued.len(), 1);
+    assert_eq!(node.summary_updates[&peer].queued[0].inserted.len(), 2);
+    assert_eq!(node.summary_updates[&peer].in_flight, Some(summary_request));
+
+    // and sent once it did
+    node.summary_update_answered(&peer, summary_request, false);
+    let delta_request = node.summary_updates[&peer].in_flight.unwrap();
+    assert_ne!(delta_request, summary_request);
+    assert!(node.summary_updates[&peer].queued.is_empty());
+
+    // a peer asking for a resync gets the full summary in place of the queued deltas
+    node.update_cache_summary(SummaryDelta {
+        removed: vec![get_block(&b"first"[..]).cid().to_bytes()],
+        ..Default::default()
+    });
+    node.summary_update_answered(&peer, delta_request, true);
+    let updates = &node.summary_updates[&peer];
+    assert!(updates.queued.is_empty() && !updates.resync);
+    assert_ne!(updates.in_flight, Some(delta_request));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_cache_summary_config() {
+    let mut config = NetworkConfig {
+        cache_summary_fp_rate: 1.5,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    assert!(network_init(&mut config, None, None).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tcp_only_transport() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        transport: TransportKind::Tcp,
+        muxer: MuxerKind::Mplex,
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (mut node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    let (node_2, _, peer_id_2, _) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    loop {
+        if let SwarmEvent::ConnectionEstablished { peer_id, .. } =
+            timeout(Duration::from_secs(5), node_1.swarm.select_next_some())
+                .await
+                .expect("a connection to be established")
+        {
+            if peer_id == peer_id_2 {
+                break;
+            }
+        }
+    }
+    assert!(node_1
+        .swarm
+        .listeners()
+        .all(|addr| TransportKind::Tcp.supports(addr)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_denied_peer_disconnected() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let keypair_2 = Keypair::generate_ed25519();
+    let peer_id_2 = PeerId::from(keypair_2.public());
+    let mut config = NetworkConfig {
+        deny_peers: vec![peer_id_2],
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (mut node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    let (node_2, ..) = network_init(&mut config, Some(node_1_addrs), Some(keypair_2)).await?;
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // the connection fails once node 2 is authenticated
+    loop {
+        let event = timeout(Duration::from_secs(5), node_1.swarm.select_next_some())
+            .await
+            .expect("the connection to be rejected");
+        if let SwarmEvent::ConnectionEstablished { peer_id, .. } = &event {
+            assert_ne!(*peer_id, peer_id_2);
+        }
+        let rejected = matches!(event, SwarmEvent::IncomingConnectionError { .. });
+        node_1.handle_swarm_event(event)?;
+        if rejected {
+            break;
+        }
+    }
+    assert!(!node_1.swarm.is_connected(&peer_id_2));
+
+    let (sender, receiver) = oneshot::channel();
+    node_1.handle_command(NetworkCommand::GetPeers { sender })?;
+    assert!(!receiver.await?.contains(&peer_id_2));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_store_reconnect() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    let peer_store = PeerStore::memory();
+
+    let (node_1, node_1_addrs, peer_id_1, _) = network_init(&mut config, None, None).await?;
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+
+    // node 2 learns of node 1 from its bootstrap nodes
+    let (node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    let mut node_2 = node_2.with_peer_store(peer_store.clone());
+    while !node_2.known_peers.contains_key(&peer_id_1) {
+        let event = timeout(Duration::from_secs(5), node_2.swarm.select_next_some())
+            .await
+            .expect("node 1 to be identified");
+        node_2.handle_swarm_event(event)?;
+    }
+    node_2.save_peers();
+    drop(node_2);
+
+    // node 3 has no bootstrap nodes and reconnects from the saved peers
+    config.bootstrap_nodes = vec![];
+    let (node_3, ..) = network_init(&mut config, None, None).await?;
+    let mut node_3 = node_3.with_peer_store(peer_store);
+    node_3.restore_peers();
+    loop {
+        let event = timeout(Duration::from_secs(5), node_3.swarm.select_next_some())
+            .await
+            .expect("node 1 to be reconnected");
+        let connected = matches!(
+            event,
+            SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == peer_id_1
+        );
+        node_3.handle_swarm_event(event)?;
+        if connected {
+            break;
+        }
+    }
+    assert!(node_3.known_peers.contains_key(&peer_id_1));
+
+    Ok(())
+}
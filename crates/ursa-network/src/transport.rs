@@ -1,79 +1,257 @@
 //! Ursa Transport implementation.
+use anyhow::{anyhow, bail, Result};
+use futures::{future, AsyncRead, AsyncWrite};
 use libp2p::{
     core::{
         muxing::StreamMuxerBox,
-        transport::{upgrade, Boxed, OrTransport},
+        transport::{upgrade, Boxed},
         upgrade::SelectUpgrade,
     },
     identity::Keypair,
-    mplex, noise, quic,
+    mplex,
+    multiaddr::Protocol,
+    noise,
+    pnet::{PnetConfig, PreSharedKey},
+    quic,
     relay::v2::client::transport::ClientTransport,
     swarm::derive_prelude::EitherOutput,
-    tcp, yamux, PeerId, Transport,
+    tcp, yamux, Multiaddr, PeerId, Transport,
 };
+use metrics::increment_counter;
+use serde::{Deserialize, Serialize};
+use std::{io, sync::Arc};
+use tracing::warn;
 
-use crate::config::NetworkConfig;
+use crate::{config::NetworkConfig, utils::peer_filter::PeerFilter};
+
+/// Transports the node listens and dials on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Tcp,
+    Quic,
+    /// QUIC, failing over to TCP.
+    #[default]
+    Both,
+}
+
+impl TransportKind {
+    /// Check if an address can be listened on with the transport.
+    pub fn supports(&self, addr: &Multiaddr) -> bool {
+        let quic = addr
+            .iter()
+            .any(|protocol| matches!(protocol, Protocol::Quic | Protocol::QuicV1));
+        match self {
+            TransportKind::Tcp => !quic,
+            TransportKind::Quic => quic,
+            TransportKind::Both => true,
+        }
+    }
+}
+
+/// Stream muxer preferred on TCP and relayed connections, the other one is
+/// still offered to peers that do not support it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MuxerKind {
+    #[default]
+    Yamux,
+    Mplex,
+}
+
+/// Parse a pre-shared key, either the 64 hex characters of the key or a whole swarm.key file.
+pub fn parse_psk(psk: &str) -> Result<PreSharedKey> {
+    let psk = psk.trim();
+    let psk = if psk.starts_with("/key/") {
+        psk.parse()
+    } else {
+        format!("/key/swarm/psk/1.0.0/\n/base16/\n{psk}").parse()
+    };
+    psk.map_err(|e| anyhow!("Invalid pre-shared key: {e}"))
+}
 
 /// Creates a new [`UrsaTransport`].
 ///
 /// Defaults to QUIC transport over TCP.
 /// If QUIC fails to establish a connection, we fail over to TCP.
+/// The relay transport, if any, is upgraded like TCP.
+///
+/// With a pre-shared key, TCP and relayed connections are encrypted with it
+/// before the noise handshake, so only the peers with the same key can connect.
+/// QUIC does not support pre-shared keys and cannot be used with one.
+///
+/// The connections of the peers the filter does not allow are closed as soon
+/// as the peer is authenticated.
 pub(crate) fn build_transport(
     keypair: &Keypair,
-    // todo(botch): make some of the transport options configurable
-    _config: &NetworkConfig,
+    config: &NetworkConfig,
     relay_transport: Option<ClientTransport>,
-) -> Boxed<(PeerId, StreamMuxerBox)> {
-    let id_keys = keypair;
+    peer_filter: PeerFilter,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    let psk = config.psk.as_deref().map(parse_psk).transpose()?;
+    if psk.is_some() && config.transport != TransportKind::Tcp {
+        bail!("A private network only runs over tcp, the transport must be set to tcp");
+    }
 
-    let tcp = {
+    let tcp = || {
         let tcp_config = tcp::Config::default().port_reuse(true);
-        let tcp_transport = tcp::tokio::Transport::new(tcp_config);
-
-        let noise = {
-            let dh_keys = noise::Keypair::<noise::X25519Spec>::new()
-                .into_authentic(id_keys)
-                .expect("Signing libp2p-noise static DH keypair failed.");
-
-            noise::NoiseConfig::xx(dh_keys).into_authenticated()
-        };
-
-        let mplex = {
-            let mut mplex_config = mplex::MplexConfig::new();
-            mplex_config.set_max_buffer_behaviour(mplex::MaxBufferBehaviour::Block);
-            mplex_config.set_max_buffer_size(usize::MAX);
-
-            let mut yamux_config = yamux::YamuxConfig::default();
-            yamux_config.set_window_update_mode(yamux::WindowUpdateMode::on_read());
-
-            SelectUpgrade::new(yamux_config, mplex_config)
-        };
-
-        if let Some(relay) = relay_transport {
-            tcp_transport
-                .or_transport(relay)
-                .upgrade(upgrade::Version::V1)
-                .authenticate(noise)
-                .multiplex(mplex)
-                .boxed()
-        } else {
-            tcp_transport
-                .upgrade(upgrade::Version::V1)
-                .authenticate(noise)
-                .multiplex(mplex)
-                .boxed()
-        }
+        private_transport(
+            tcp::tokio::Transport::new(tcp_config),
+            psk,
+            keypair,
+            config.muxer,
+        )
     };
 
-    let quic = {
+    let quic = || {
         let quic_config = quic::Config::new(keypair);
         quic::tokio::Transport::new(quic_config)
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed()
     };
 
-    OrTransport::new(quic, tcp)
+    let transport = match config.transport {
+        TransportKind::Tcp => tcp(),
+        TransportKind::Quic => quic(),
+        TransportKind::Both => or_transport(quic(), tcp()),
+    };
+
+    let transport = match relay_transport {
+        Some(relay) => or_transport(
+            transport,
+            private_transport(relay, psk, keypair, config.muxer),
+        ),
+        None => transport,
+    };
+    Ok(filter_transport(transport, peer_filter))
+}
+
+/// Fail the connections of the authenticated peers the filter does not allow.
+fn filter_transport(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    peer_filter: PeerFilter,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let peer_filter = Arc::new(peer_filter);
+    transport
+        .and_then(move |(peer_id, muxer), _| {
+            future::ready(if peer_filter.is_allowed(&peer_id) {
+                Ok((peer_id, muxer))
+            } else {
+                warn!("Rejecting the connection with {peer_id}, the peer is not allowed");
+                increment_counter!("swarm_connections_denied");
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("the peer {peer_id} is not allowed"),
+                ))
+            })
+        })
+        .boxed()
+}
+
+/// Upgrade a stream transport, first encrypting it with the pre-shared key if any.
+fn private_transport<T>(
+    transport: T,
+    psk: Option<PreSharedKey>,
+    keypair: &Keypair,
+    muxer: MuxerKind,
+) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    match psk {
+        Some(psk) => upgrade_transport(
+            transport.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)),
+            keypair,
+            muxer,
+        ),
+        None => upgrade_transport(transport, keypair, muxer),
+    }
+}
+
+/// Authenticate with noise and multiplex the connections of a stream transport.
+fn upgrade_transport<T>(
+    transport: T,
+    keypair: &Keypair,
+    muxer: MuxerKind,
+) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    let noise = {
+        let dh_keys = noise::Keypair::<noise::X25519Spec>::new()
+            .into_authentic(keypair)
+            .expect("Signing libp2p-noise static DH keypair failed.");
+
+        noise::NoiseConfig::xx(dh_keys).into_authenticated()
+    };
+
+    let mut mplex_config = mplex::MplexConfig::new();
+    mplex_config.set_max_buffer_behaviour(mplex::MaxBufferBehaviour::Block);
+    mplex_config.set_max_buffer_size(usize::MAX);
+
+    let mut yamux_config = yamux::YamuxConfig::default();
+    yamux_config.set_window_update_mode(yamux::WindowUpdateMode::on_read());
+
+    let authenticated = transport.upgrade(upgrade::Version::V1).authenticate(noise);
+    match muxer {
+        MuxerKind::Yamux => authenticated
+            .multiplex(SelectUpgrade::new(yamux_config, mplex_config))
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        MuxerKind::Mplex => authenticated
+            .multiplex(SelectUpgrade::new(mplex_config, yamux_config))
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed(),
+    }
+}
+
+/// Dial with `first`, failing over to `second`.
+fn or_transport(
+    first: Boxed<(PeerId, StreamMuxerBox)>,
+    second: Boxed<(PeerId, StreamMuxerBox)>,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    first
+        .or_transport(second)
         .map(|either_output, _| match either_output {
-            EitherOutput::First((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
-            EitherOutput::Second((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+            EitherOutput::First(output) => output,
+            EitherOutput::Second(output) => output,
         })
         .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSK: &str = "6189c5cf0b87fb800c1a9feeda73c6ab5e998db48fb9e6a978575c770ceef683";
+
+    #[test]
+    fn test_parse_psk() {
+        let key = parse_psk(PSK).unwrap();
+        let file = format!("/key/swarm/psk/1.0.0/\n/base16/\n{PSK}\n");
+        assert_eq!(parse_psk(&file).unwrap().fingerprint(), key.fingerprint());
+
+        assert!(parse_psk("not a key").is_err());
+        assert!(parse_psk(&PSK[2..]).is_err());
+    }
+
+    #[test]
+    fn test_supported_addresses() {
+        let tcp: Multiaddr = "/ip4/0.0.0.0/tcp/6009".parse().unwrap();
+        let quic: Multiaddr = "/ip4/0.0.0.0/udp/4890/quic-v1".parse().unwrap();
+
+        assert!(TransportKind::Tcp.supports(&tcp));
+        assert!(!TransportKind::Tcp.supports(&quic));
+        assert!(!TransportKind::Quic.supports(&tcp));
+        assert!(TransportKind::Quic.supports(&quic));
+        assert!(TransportKind::Both.supports(&tcp) && TransportKind::Both.supports(&quic));
+    }
+}
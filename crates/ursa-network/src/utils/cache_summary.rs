@@ -4,12 +4,67 @@ use scalable_cuckoo_filter::{ScalableCuckooFilter, ScalableCuckooFilterBuilder};
 use serde::{Deserialize, Serialize};
 use siphasher::sip::SipHasher13;
 
+/// A cuckoo filter of the cids cached by a node.
+///
+/// The version counts the changes made to the filter, so a peer holding a
+/// copy of the summary can apply a [`SummaryDelta`] only on top of the
+/// version it was computed from.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CacheSummary {
     filter: ScalableCuckooFilter<[u8], SipHasher13>,
+    version: u64,
+}
+
+/// Keys inserted in and removed from a [`CacheSummary`] since a version.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummaryDelta {
+    /// Version of the summary the delta applies to.
+    pub base_version: u64,
+    pub inserted: Vec<Vec<u8>>,
+    /// Removed after the keys are inserted.
+    pub removed: Vec<Vec<u8>>,
+}
+
+impl SummaryDelta {
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+
+    /// Version of the summary once the delta is applied.
+    pub fn next_version(&self) -> u64 {
+        self.base_version + (self.inserted.len() + self.removed.len()) as u64
+    }
+
+    /// Merge the delta that follows this one, so both are sent at once.
+    /// Returns `false`, leaving the delta untouched, if `next` does not apply
+    /// on top of it, or re-inserts a key it removes: inserts are applied
+    /// before removes, so the key would end up removed.
+    pub fn merge(&mut self, next: &SummaryDelta) -> bool {
+        if next.base_version != self.next_version()
+            || next.inserted.iter().any(|key| self.removed.contains(key))
+        {
+            return false;
+        }
+        self.inserted.extend(next.inserted.iter().cloned());
+        self.removed.extend(next.removed.iter().cloned());
+        true
+    }
 }
 
 impl CacheSummary {
+    /// Check the parameters of the filter, which would panic on invalid ones.
+    pub fn try_new(initial_capacity: usize, fp_rate: f64) -> Result<CacheSummary> {
+        if initial_capacity == 0 {
+            return Err(anyhow!("Cache summary capacity must not be zero."));
+        }
+        if !(fp_rate > 0.0 && fp_rate < 1.0) {
+            return Err(anyhow!(
+                "Cache summary false positive rate must be between 0 and 1, got {fp_rate}."
+            ));
+        }
+        Ok(Self::new(initial_capacity, fp_rate))
+    }
+
     pub fn new(initial_capacity: usize, fp_rate: f64) -> CacheSummary {
         CacheSummary {
             filter: ScalableCuckooFilterBuilder::new()
@@ -17,6 +72,7 @@ impl CacheSummary {
                 .false_positive_probability(fp_rate)
                 .rng(SeedableRng::from_entropy())
                 .finish(),
+            version: 0,
         }
     }
 
@@ -26,15 +82,36 @@ impl CacheSummary {
 
     pub fn insert<T: AsRef<[u8]>>(&mut self, value: T) {
         self.filter.insert(value.as_ref());
+        self.version += 1;
     }
 
     pub fn contains<T: AsRef<[u8]>>(&self, value: T) -> bool {
         self.filter.contains(value.as_ref())
     }
 
-    #[allow(dead_code)]
     pub fn remove<T: AsRef<[u8]>>(&mut self, value: T) {
         self.filter.remove(value.as_ref());
+        self.version += 1;
+    }
+
+    /// Number of changes made to the summary.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Apply a delta computed from the current version.
+    /// Returns `false`, leaving the summary untouched, if the versions differ.
+    pub fn apply(&mut self, delta: &SummaryDelta) -> bool {
+        if delta.base_version != self.version {
+            return false;
+        }
+        for key in &delta.inserted {
+            self.insert(key);
+        }
+        for key in &delta.removed {
+            self.remove(key);
+        }
+        true
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
@@ -50,13 +127,14 @@ impl Clone for CacheSummary {
     fn clone(&self) -> Self {
         CacheSummary {
             filter: self.filter.clone(),
+            version: self.version,
         }
     }
 }
 
 impl PartialEq for CacheSummary {
     fn eq(&self, other: &Self) -> bool {
-        self.filter == other.filter
+        self.filter == other.filter && self.version == other.version
     }
 }
 
@@ -112,6 +190,78 @@ mod tests {
         assert!(!filter.contains(b"oizu"));
     }
 
+    #[test]
+    fn test_apply_delta() {
+        let mut summary = CacheSummary::new(10, 0.01);
+        summary.insert(b"abc");
+        let mut copy = summary.clone();
+
+        let delta = SummaryDelta {
+            base_version: summary.version(),
+            inserted: vec![b"def".to_vec(), b"ghi".to_vec()],
+            removed: vec![b"abc".to_vec()],
+        };
+        assert!(summary.apply(&delta));
+        assert_eq!(summary.version(), 4);
+        assert!(!summary.contains(b"abc"));
+        assert!(summary.contains(b"def"));
+        assert!(summary.contains(b"ghi"));
+
+        // a delta is only applied on top of its base version
+        assert!(!summary.apply(&delta));
+        assert_eq!(summary.version(), 4);
+
+        assert!(copy.apply(&delta));
+        assert_eq!(copy.version(), summary.version());
+        assert!(copy.contains(b"def"));
+    }
+
+    #[test]
+    fn test_merge_delta() {
+        let mut summary = CacheSummary::new(10, 0.01);
+        let mut copy = summary.clone();
+
+        let mut delta = SummaryDelta {
+            base_version: summary.version(),
+            inserted: vec![b"abc".to_vec(), b"def".to_vec()],
+            removed: vec![],
+        };
+        summary.apply(&delta);
+        let next = SummaryDelta {
+            base_version: summary.version(),
+            inserted: vec![b"ghi".to_vec()],
+            removed: vec![b"abc".to_vec()],
+        };
+        summary.apply(&next);
+
+        // a delta that does not follow is not merged
+        assert!(!next.clone().merge(&delta));
+        assert!(delta.merge(&next));
+        assert_eq!(delta.next_version(), summary.version());
+        assert!(copy.apply(&delta));
+        assert_eq!(copy.version(), summary.version());
+        assert!(!copy.contains(b"abc"));
+        assert!(copy.contains(b"def"));
+        assert!(copy.contains(b"ghi"));
+
+        // a removed key inserted again must be sent apart
+        let again = SummaryDelta {
+            base_version: summary.version(),
+            inserted: vec![b"abc".to_vec()],
+            removed: vec![],
+        };
+        assert!(!delta.merge(&again));
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(CacheSummary::try_new(10, 0.01).is_ok());
+        assert!(CacheSummary::try_new(0, 0.01).is_err());
+        assert!(CacheSummary::try_new(10, 0.0).is_err());
+        assert!(CacheSummary::try_new(10, 1.0).is_err());
+        assert!(CacheSummary::try_new(10, f64::NAN).is_err());
+    }
+
     #[test]
     fn test_serialize_deserialize() {
         let mut filter = CacheSummary::new(10, 0.01);
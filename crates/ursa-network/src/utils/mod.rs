@@ -1 +1,5 @@
 pub mod cache_summary;
+pub mod peer_filter;
+pub mod peer_store;
+pub mod replication;
+pub mod reputation;
@@ -0,0 +1,51 @@
+use libp2p::PeerId;
+use std::collections::HashSet;
+
+/// Peers allowed to connect with the node.
+///
+/// Denied peers are never allowed. If the allowlist is not empty, only the
+/// peers in it are allowed.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFilter {
+    allow: HashSet<PeerId>,
+    deny: HashSet<PeerId>,
+}
+
+impl PeerFilter {
+    pub fn new(allow: &[PeerId], deny: &[PeerId]) -> Self {
+        Self {
+            allow: allow.iter().copied().collect(),
+            deny: deny.iter().copied().collect(),
+        }
+    }
+
+    pub fn is_allowed(&self, peer: &PeerId) -> bool {
+        !self.deny.contains(peer) && (self.allow.is_empty() || self.allow.contains(peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_filter() {
+        let filter = PeerFilter::default();
+        assert!(filter.is_allowed(&PeerId::random()));
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let allowed = PeerId::random();
+        let denied = PeerId::random();
+
+        let filter = PeerFilter::new(&[], &[denied]);
+        assert!(filter.is_allowed(&allowed));
+        assert!(!filter.is_allowed(&denied));
+
+        let filter = PeerFilter::new(&[allowed, denied], &[denied]);
+        assert!(filter.is_allowed(&allowed));
+        assert!(!filter.is_allowed(&denied));
+        assert!(!filter.is_allowed(&PeerId::random()));
+    }
+}
@@ -0,0 +1,192 @@
+//! Peers known to the node, kept across restarts.
+//!
+//! The service records the addresses, protocols and statistics of the peers
+//! it identified, and saves them from time to time. On startup the saved
+//! peers seed kademlia and the best scored ones are dialed, so the node does
+//! not only depend on its bootstrap nodes to join the network again.
+
+use anyhow::{bail, Result};
+use bincode::Options;
+use db::Store;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use ursa_store::Backend;
+
+use super::reputation::PeerStats;
+
+/// Key under which the known peers are persisted.
+const PEERS_KEY: &str = "ursa/peers";
+/// Version byte leading the persisted peers.
+pub const PEER_STORE_VERSION: u8 = 1;
+/// Most peers kept, the most recently seen ones.
+pub const MAX_STORED_PEERS: usize = 1024;
+/// Time after which a peer that was not seen is forgotten.
+pub const PEER_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// What is known of a peer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    /// Addresses the peer listens on.
+    pub addresses: Vec<Multiaddr>,
+    /// Unix time in seconds the peer was last connected.
+    pub last_seen: u64,
+    /// Protocols the peer supports.
+    pub protocols: Vec<String>,
+    /// Statistics of the exchanges with the peer.
+    pub stats: PeerStats,
+}
+
+/// Unix time in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// Forget the peers not seen within [`PEER_TTL`], and keep the
+/// [`MAX_STORED_PEERS`] most recently seen ones.
+pub fn prune_peers(peers: &mut HashMap<PeerId, PeerRecord>) {
+    let expired = unix_time().saturating_sub(PEER_TTL.as_secs());
+    peers.retain(|_, record| record.last_seen >= expired);
+    if peers.len() > MAX_STORED_PEERS {
+        let mut by_age: Vec<(u64, PeerId)> = peers
+            .iter()
+            .map(|(peer, record)| (record.last_seen, *peer))
+            .collect();
+        by_age.sort_by_key(|(last_seen, _)| std::cmp::Reverse(*last_seen));
+        for (_, peer) in by_age.into_iter().skip(MAX_STORED_PEERS) {
+            peers.remove(&peer);
+        }
+    }
+}
+
+/// Database of the known peers.
+#[derive(Clone)]
+pub struct PeerStore {
+    db: Arc<Backend>,
+}
+
+impl PeerStore {
+    pub fn new(db: Arc<Backend>) -> Self {
+        Self { db }
+    }
+
+    /// A store kept in memory, the peers are lost on exit.
+    pub fn memory() -> Self {
+        Self::new(Arc::new(Backend::Memory(Default::default())))
+    }
+
+    /// Read the saved peers, none if nothing was saved yet.
+    pub fn load(&self) -> Result<HashMap<PeerId, PeerRecord>> {
+        let data = match self.db.read(PEERS_KEY)? {
+            Some(data) => data,
+            None => return Ok(HashMap::new()),
+        };
+        match data.split_first() {
+            Some((&PEER_STORE_VERSION, peers)) => Ok(bincode::options().deserialize(peers)?),
+            Some((version, _)) => bail!("unsupported peer store version {version}"),
+            None => bail!("empty peer store"),
+        }
+    }
+
+    /// Save the peers seen within [`PEER_TTL`], at most [`MAX_STORED_PEERS`] of them.
+    pub fn save(&self, peers: &HashMap<PeerId, PeerRecord>) -> Result<()> {
+        let expired = unix_time().saturating_sub(PEER_TTL.as_secs());
+        let mut peers: Vec<(&PeerId, &PeerRecord)> = peers
+            .iter()
+            .filter(|(_, record)| record.last_seen >= expired)
+            .collect();
+        peers.sort_by_key(|(_, record)| std::cmp::Reverse(record.last_seen));
+        peers.truncate(MAX_STORED_PEERS);
+        let peers: HashMap<&PeerId, &PeerRecord> = peers.into_iter().collect();
+
+        let mut data = vec![PEER_STORE_VERSION];
+        bincode::options().serialize_into(&mut data, &peers)?;
+        self.db.write(PEERS_KEY, data)?;
+        Ok(())
+    }
+}
+
+impl Default for PeerStore {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(last_seen: u64) -> PeerRecord {
+        PeerRecord {
+            addresses: vec!["/ip4/10.0.0.1/tcp/6009".parse().unwrap()],
+            last_seen,
+            protocols: vec!["/ursa/kad/0.0.1".to_string()],
+            stats: PeerStats {
+                bitswap_successes: 3,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let store = PeerStore::memory();
+        assert!(store.load().unwrap().is_empty());
+
+        let peers = HashMap::from([
+            (PeerId::random(), record(unix_time())),
+            (PeerId::random(), record(unix_time() - 60)),
+        ]);
+        store.save(&peers).unwrap();
+        assert_eq!(store.load().unwrap(), peers);
+    }
+
+    #[test]
+    fn test_prune_peers() {
+        let recent = PeerId::random();
+        let mut peers = HashMap::from([
+            (recent, record(unix_time())),
+            (
+                PeerId::random(),
+                record(unix_time() - PEER_TTL.as_secs() - 60),
+            ),
+        ]);
+        prune_peers(&mut peers);
+        assert_eq!(peers.keys().collect::<Vec<_>>(), vec![&recent]);
+
+        // the most recently seen peers are kept
+        let now = unix_time();
+        let mut peers: HashMap<PeerId, PeerRecord> = (0..MAX_STORED_PEERS as u64 + 10)
+            .map(|age| (PeerId::random(), record(now - age)))
+            .collect();
+        prune_peers(&mut peers);
+        assert_eq!(peers.len(), MAX_STORED_PEERS);
+        assert!(peers
+            .values()
+            .all(|record| record.last_seen > now - MAX_STORED_PEERS as u64));
+    }
+
+    #[test]
+    fn test_save_drops_expired_peers() {
+        let store = PeerStore::memory();
+        let recent = PeerId::random();
+        let peers = HashMap::from([
+            (recent, record(unix_time())),
+            (
+                PeerId::random(),
+                record(unix_time() - PEER_TTL.as_secs() - 60),
+            ),
+        ]);
+        store.save(&peers).unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded.contains_key(&recent));
+    }
+}
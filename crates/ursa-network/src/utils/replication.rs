@@ -0,0 +1,147 @@
+//! Placement of the replicas of new content.
+//!
+//! [`ReplicationStrategy::order`] sorts the candidate peers from the most to
+//! the least suitable, the service asks them in that order until enough of
+//! them confirmed they stored a replica.
+
+use libipld::Cid;
+use libp2p::{
+    kad::{kbucket::Key as KBucketKey, record::Key},
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+use rand::prelude::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Strategy used to pick the peers replicating new content.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationStrategy {
+    /// The peers closest to the content by kademlia XOR distance, the same
+    /// peers a provider lookup would reach first.
+    #[default]
+    Closest,
+    /// Random peers.
+    Random,
+    /// The peers with the most free storage, as they last advertised it.
+    Capacity,
+    /// Peers spread over as many networks as possible, approximated by the
+    /// /16 (IPv4) or /32 (IPv6) prefix of the address they are connected on.
+    Diverse,
+}
+
+/// What is known of the candidate peers.
+#[derive(Debug)]
+pub struct PeerPlacement<'a> {
+    /// Free storage advertised by peers, `None` if unbounded.
+    pub capacity: &'a HashMap<PeerId, Option<u64>>,
+    /// Addresses peers are connected on.
+    pub addresses: &'a HashMap<PeerId, Multiaddr>,
+}
+
+impl ReplicationStrategy {
+    /// Sort `peers` for the replication of `cid`. Ties keep the order of
+    /// `peers`, so callers can pass them best scored first.
+    pub fn order(&self, cid: &Cid, mut peers: Vec<PeerId>, info: &PeerPlacement) -> Vec<PeerId> {
+        match self {
+            ReplicationStrategy::Closest => {
+                let key = KBucketKey::new(Key::new(&cid.to_bytes()));
+                peers.sort_by_cached_key(|peer| KBucketKey::from(*peer).distance(&key));
+            }
+            ReplicationStrategy::Random => peers.shuffle(&mut rand::thread_rng()),
+            ReplicationStrategy::Capacity => {
+                // unbounded peers first, peers that never advertised their capacity last
+                let free = |peer: &PeerId| match info.capacity.get(peer) {
+                    Some(None) => u64::MAX,
+                    Some(Some(free)) => *free,
+                    None => 0,
+                };
+                peers.sort_by_key(|peer| std::cmp::Reverse(free(peer)));
+            }
+            ReplicationStrategy::Diverse => {
+                let mut seen = HashSet::new();
+                let (first, rest): (Vec<PeerId>, Vec<PeerId>) =
+                    peers.into_iter().partition(|peer| {
+                        match info.addresses.get(peer).and_then(network_prefix) {
+                            Some(prefix) => seen.insert(prefix),
+                            None => true,
+                        }
+                    });
+                peers = first;
+                peers.extend(rest);
+            }
+        }
+        peers
+    }
+}
+
+/// The network prefix of the ip an address is on.
+fn network_prefix(address: &Multiaddr) -> Option<Vec<u8>> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(ip.octets()[..2].to_vec()),
+        Protocol::Ip6(ip) => Some(ip.octets()[..4].to_vec()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(n: usize) -> Vec<PeerId> {
+        (0..n).map(|_| PeerId::random()).collect()
+    }
+
+    #[test]
+    fn test_closest_order() {
+        let cid = Cid::default();
+        let peers = peers(10);
+        let info = PeerPlacement {
+            capacity: &HashMap::new(),
+            addresses: &HashMap::new(),
+        };
+        let ordered = ReplicationStrategy::Closest.order(&cid, peers.clone(), &info);
+
+        let key = KBucketKey::new(Key::new(&cid.to_bytes()));
+        let distances: Vec<_> = ordered
+            .iter()
+            .map(|peer| KBucketKey::from(*peer).distance(&key))
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(ordered.len(), peers.len());
+    }
+
+    #[test]
+    fn test_capacity_order() {
+        let peers = peers(4);
+        let capacity = HashMap::from([
+            (peers[0], Some(10)),
+            (peers[1], Some(1000)),
+            (peers[2], None),
+        ]);
+        let info = PeerPlacement {
+            capacity: &capacity,
+            addresses: &HashMap::new(),
+        };
+        let ordered = ReplicationStrategy::Capacity.order(&Cid::default(), peers.clone(), &info);
+        assert_eq!(ordered, vec![peers[2], peers[1], peers[0], peers[3]]);
+    }
+
+    #[test]
+    fn test_diverse_order() {
+        let peers = peers(4);
+        let addresses = HashMap::from([
+            (peers[0], "/ip4/10.0.0.1/tcp/6009".parse().unwrap()),
+            (peers[1], "/ip4/10.0.0.2/tcp/6009".parse().unwrap()),
+            (peers[2], "/ip4/192.168.0.1/tcp/6009".parse().unwrap()),
+            (peers[3], "/ip4/10.0.1.1/tcp/6009".parse().unwrap()),
+        ]);
+        let info = PeerPlacement {
+            capacity: &HashMap::new(),
+            addresses: &addresses,
+        };
+        let ordered = ReplicationStrategy::Diverse.order(&Cid::default(), peers.clone(), &info);
+        assert_eq!(ordered, vec![peers[0], peers[2], peers[1], peers[3]]);
+    }
+}
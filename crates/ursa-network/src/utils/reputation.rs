@@ -0,0 +1,126 @@
+//! Per peer statistics combined into a reputation score.
+//!
+//! The score is the product of:
+//!
+//! - reliability: the share of successful exchanges, starting at one half
+//!   for a peer we know nothing about.
+//! - latency: `1` for an instant round trip, one half at [`REFERENCE_RTT`],
+//!   and one half as well while the round trip time is unknown.
+//! - volume: a logarithmic bonus for the bytes served, so a large transfer
+//!   cannot make up for repeated failures.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Round trip time scoring one half.
+pub const REFERENCE_RTT: Duration = Duration::from_millis(100);
+
+/// Weight of a new round trip time in the smoothed one.
+const RTT_WEIGHT: f64 = 0.125;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// Smoothed ping round trip time in milliseconds.
+    pub rtt_ms: Option<f64>,
+    pub ping_failures: u64,
+    /// Bitswap queries the peer was asked in that completed.
+    pub bitswap_successes: u64,
+    /// Bitswap queries the peer was asked in that failed.
+    pub bitswap_failures: u64,
+    /// Graphsync and car transfers that completed.
+    pub transfer_successes: u64,
+    /// Graphsync and car transfers that sent nothing, and failed requests.
+    pub request_failures: u64,
+    /// Bytes of the dags received over graphsync and car transfers.
+    pub bytes_served: u64,
+}
+
+impl PeerStats {
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64() * 1000.0;
+        self.rtt_ms = Some(match self.rtt_ms {
+            Some(smoothed) => smoothed + RTT_WEIGHT * (rtt - smoothed),
+            None => rtt,
+        });
+    }
+
+    pub fn successes(&self) -> u64 {
+        self.bitswap_successes + self.transfer_successes
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.ping_failures + self.bitswap_failures + self.request_failures
+    }
+
+    pub fn score(&self) -> f64 {
+        let reliability =
+            (self.successes() as f64 + 1.0) / ((self.successes() + self.failures()) as f64 + 2.0);
+        let reference = REFERENCE_RTT.as_secs_f64() * 1000.0;
+        let latency = reference / (reference + self.rtt_ms.unwrap_or(reference));
+        let volume = 1.0 + (1.0 + self.bytes_served as f64 / (1 << 20) as f64).log2() / 10.0;
+        reliability * latency * volume
+    }
+}
+
+/// The statistics of a peer with the score computed from them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PeerScore {
+    pub score: f64,
+    #[serde(flatten)]
+    pub stats: PeerStats,
+}
+
+impl From<PeerStats> for PeerScore {
+    fn from(stats: PeerStats) -> Self {
+        Self {
+            score: stats.score(),
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_peer_score() {
+        let stats = PeerStats::default();
+        assert_eq!(stats.score(), 0.25);
+    }
+
+    #[test]
+    fn test_rtt_smoothing() {
+        let mut stats = PeerStats::default();
+        stats.record_rtt(Duration::from_millis(100));
+        assert_eq!(stats.rtt_ms, Some(100.0));
+        stats.record_rtt(Duration::from_millis(180));
+        assert_eq!(stats.rtt_ms, Some(110.0));
+    }
+
+    #[test]
+    fn test_score_order() {
+        let mut fast = PeerStats::default();
+        fast.record_rtt(Duration::from_millis(10));
+        let mut slow = PeerStats::default();
+        slow.record_rtt(Duration::from_millis(500));
+        assert!(fast.score() > slow.score());
+
+        let mut reliable = PeerStats {
+            bitswap_successes: 10,
+            ..Default::default()
+        };
+        let mut unreliable = PeerStats {
+            bitswap_successes: 10,
+            bitswap_failures: 20,
+            ..Default::default()
+        };
+        assert!(reliable.score() > unreliable.score());
+
+        // volume does not make up for failures
+        reliable.bytes_served = 1 << 20;
+        unreliable.bytes_served = 1 << 30;
+        assert!(reliable.score() > unreliable.score());
+        assert!(reliable.score() > PeerStats::default().score());
+    }
+}
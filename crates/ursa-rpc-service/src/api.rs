@@ -2,12 +2,13 @@ use anyhow::{anyhow, Result};
 use async_fs::{create_dir_all, File};
 use async_trait::async_trait;
 use axum::body::StreamBody;
+use bytes::Bytes;
 use db::Store;
-use futures::channel::mpsc::unbounded;
 use futures::io::BufReader;
-use futures::{AsyncRead, AsyncWriteExt, SinkExt};
+use futures::stream::{self, BoxStream};
+use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
 use fvm_ipld_blockstore::Blockstore;
-use fvm_ipld_car::{load_car, CarHeader, CarReader};
+use fvm_ipld_car::CarHeader;
 use libipld::Cid;
 use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
@@ -15,27 +16,38 @@ use std::collections::{
     hash_map::{Entry, HashMap},
     HashSet,
 };
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use surf::{http::Method, Client, RequestBuilder};
 use tokio::sync::{
-    mpsc::{unbounded_channel, UnboundedSender as Sender},
+    mpsc::{channel, unbounded_channel, UnboundedSender as Sender},
     oneshot, RwLock,
 };
-use tokio::task;
+use tokio::{task, time::timeout};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::{compat::TokioAsyncWriteCompatExt, io::ReaderStream};
 use tracing::{debug, error, info};
 use ursa_index_provider::engine::ProviderCommand;
-use ursa_network::NetworkCommand;
-use ursa_store::UrsaStore;
+use ursa_network::{block_selector, dag_selector, NetworkCommand, PeerScore, Selector};
+use ursa_store::{
+    write_car_v2, CarVersion, Chunker, ContentSource, DagStatus, DirEntry, GcStats, ListKeys,
+    MissingBlock, RootMeta, UnixFsType, UrsaStore,
+};
 
 use crate::config::OriginConfig;
 
-pub const MAX_BLOCK_SIZE: usize = 1048576;
 pub const MAX_CHUNK_SIZE: usize = 104857600;
 pub const DEFAULT_CHUNK_SIZE: usize = 10 * 1024 * 1024; // chunk to ~10MB CARs
+/// Number of blocks buffered between the dag traversal and a car writer.
+pub const DAG_STREAM_BUFFER: usize = 64;
+/// Time to wait for a graphsync traversal before falling back to bitswap.
+pub const GRAPHSYNC_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time after which a car transfer from a peer is given up.
+pub const CAR_TIMEOUT: Duration = Duration::from_secs(300);
 
 /// Network Api
 #[derive(Deserialize, Serialize)]
@@ -51,12 +63,45 @@ pub struct NetworkPutFileParams {
     pub path: String,
 }
 
-pub type NetworkPutFileResult = String;
+/// Outcome of putting a root of a car file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PutRootResult {
+    pub cid: String,
+    /// Size of the dag as a CARv1 file in bytes, 0 if it was not stored.
+    pub size: u64,
+    /// Why the root could not be stored or announced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub type NetworkPutFileResult = Vec<PutRootResult>;
 pub const NETWORK_PUT_FILE: &str = "ursa_put_file";
 
+#[derive(Deserialize, Serialize)]
+pub struct NetworkImportFileParams {
+    pub path: String,
+    /// Chunker used to split files, e.g. `size-262144` or `rabin`, fixed 256KiB chunks if not set.
+    #[serde(default)]
+    pub chunker: Option<String>,
+}
+
+pub type NetworkImportFileResult = String;
+pub const NETWORK_IMPORT_FILE: &str = "ursa_import_file";
+
 pub type NetworkGetPeers = HashSet<PeerId>;
 pub const NETWORK_GET_PEERS: &str = "ursa_get_peers";
 
+pub type NetworkGetPeerScores = HashMap<PeerId, PeerScore>;
+pub const NETWORK_GET_PEER_SCORES: &str = "ursa_get_peer_scores";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkGetReplicasParams {
+    pub cid: String,
+}
+
+pub type NetworkGetReplicas = HashSet<PeerId>;
+pub const NETWORK_GET_REPLICAS: &str = "ursa_get_replicas";
+
 pub type NetworkGetListenerAddresses = Vec<Multiaddr>;
 pub const NETWORK_LISTENER_ADDRESSES: &str = "ursa_listener_addresses";
 
@@ -64,9 +109,55 @@ pub const NETWORK_LISTENER_ADDRESSES: &str = "ursa_listener_addresses";
 pub struct NetworkGetFileParams {
     pub path: String,
     pub cid: String,
+    /// Version of the car file to write, 1 if not set.
+    #[serde(default)]
+    pub car_version: Option<u64>,
 }
 pub const NETWORK_GET_FILE: &str = "ursa_get_file";
 
+/// Store Api
+#[derive(Deserialize, Serialize)]
+pub struct StorePinParams {
+    pub cid: String,
+}
+
+pub const STORE_PIN: &str = "ursa_pin";
+
+pub type StoreUnpinResult = bool;
+pub const STORE_UNPIN: &str = "ursa_unpin";
+
+pub type StoreGcResult = GcStats;
+pub const STORE_GC: &str = "ursa_gc";
+
+#[derive(Deserialize, Serialize)]
+pub struct StoreContent {
+    pub cid: String,
+    pub pinned: bool,
+    #[serde(flatten)]
+    pub meta: RootMeta,
+}
+
+pub type StoreListResult = Vec<StoreContent>;
+pub const STORE_LIST: &str = "ursa_list_content";
+
+/// A car file streamed as an http body, which fails if the dag cannot be
+/// read to the end instead of ending early.
+pub type CarBody = StreamBody<BoxStream<'static, std::io::Result<Bytes>>>;
+
+/// Content under a UnixFS path.
+pub enum UnixFsContent {
+    File {
+        cid: Cid,
+        /// Size declared by the file node, not checked against its content.
+        size: u64,
+        content: ReceiverStream<std::io::Result<Vec<u8>>>,
+    },
+    Directory {
+        cid: Cid,
+        entries: Vec<DirEntry>,
+    },
+}
+
 /// Abstraction of Ursa's server commands
 #[async_trait]
 pub trait NetworkInterface: Sync + Send + 'static {
@@ -77,25 +168,52 @@ pub trait NetworkInterface: Sync + Send + 'static {
     async fn get_data(&self, root_cid: Cid) -> Result<Vec<(Cid, Vec<u8>)>>;
 
     /// get the file locally via cli
-    async fn get_file(&self, path: String, cid: Cid) -> Result<()>;
+    async fn get_file(&self, path: String, cid: Cid, version: CarVersion) -> Result<()>;
 
     /// Stream the car file from server
-    async fn stream(
-        &self,
-        root_cid: Cid,
-    ) -> Result<StreamBody<ReaderStream<tokio::io::DuplexStream>>>;
+    async fn stream(&self, root_cid: Cid, version: CarVersion) -> Result<CarBody>;
+
+    /// Resolve a UnixFS path under a root cid to a file or a directory
+    async fn get_unixfs(&self, root_cid: Cid, path: String) -> Result<UnixFsContent>;
 
-    /// Put a car file and start providing to the network
-    async fn put_car<R: AsyncRead + Send + Unpin>(&self, file: Car<R>) -> Result<Vec<Cid>>;
+    /// Put a car file and start providing each of its roots to the network
+    async fn put_car<R: AsyncRead + Send + Unpin>(
+        &self,
+        file: Car<R>,
+    ) -> Result<Vec<PutRootResult>>;
 
     /// Put a file using a local path
-    async fn put_file(&self, path: String) -> Result<Vec<Cid>>;
+    async fn put_file(&self, path: String) -> Result<Vec<PutRootResult>>;
+
+    /// Chunk a file into a UnixFS dag and start providing to the network
+    async fn put_unixfs<R: Read + Send + 'static>(&self, file: R, chunker: Chunker) -> Result<Cid>;
+
+    /// Import a file or a directory using a local path as UnixFS
+    async fn import_file(&self, path: String, chunker: Chunker) -> Result<Cid>;
 
     /// Get peers from the network
     async fn get_peers(&self) -> Result<HashSet<PeerId>>;
 
+    /// Get the reputation of the peers the node exchanged with
+    async fn get_peer_scores(&self) -> Result<HashMap<PeerId, PeerScore>>;
+
+    /// Get the peers that confirmed they store a replica of a root we put
+    async fn get_replicas(&self, cid: Cid) -> Result<HashSet<PeerId>>;
+
     /// Get the addresses that p2p node is listening on
     async fn get_listener_addresses(&self) -> Result<Vec<Multiaddr>>;
+
+    /// Pin a root cid so its dag survives garbage collection
+    async fn pin(&self, cid: Cid) -> Result<()>;
+
+    /// Unpin a root cid, returns false if it was not pinned
+    async fn unpin(&self, cid: Cid) -> Result<bool>;
+
+    /// Garbage collect all the blocks not reachable from a pinned root
+    async fn gc(&self) -> Result<GcStats>;
+
+    /// List the roots held by the store with their metadata
+    async fn list_content(&self) -> Result<Vec<StoreContent>>;
 }
 
 type PendingRequests = Arc<RwLock<HashMap<Cid, Vec<Sender<Result<u64>>>>>>;
@@ -103,7 +221,7 @@ type PendingRequests = Arc<RwLock<HashMap<Cid, Vec<Sender<Result<u64>>>>>>;
 #[derive(Clone)]
 pub struct NodeNetworkInterface<S>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     pub store: Arc<UrsaStore<S>>,
     pub network_send: Sender<NetworkCommand>,
@@ -116,7 +234,7 @@ where
 #[async_trait]
 impl<S> NetworkInterface for NodeNetworkInterface<S>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     async fn get(&self, cid: Cid) -> Result<Vec<u8>> {
         self.sync_content(cid).await?;
@@ -135,87 +253,149 @@ where
     }
 
     /// Used through CLI
-    async fn get_file(&self, path: String, root_cid: Cid) -> Result<()> {
+    async fn get_file(&self, path: String, root_cid: Cid, version: CarVersion) -> Result<()> {
         info!("getting and storing the file at: {path}");
 
-        let header = CarHeader {
-            roots: vec![root_cid],
-            version: 1,
-        };
-
-        let buffer: Arc<RwLock<Vec<u8>>> = Default::default();
-        let (mut tx, mut rx) = unbounded();
-
-        let buffer_cloned = buffer.clone();
-        let write_task = tokio::task::spawn(async move {
-            header
-                .write_stream_async(&mut *buffer_cloned.write().await, &mut rx)
-                .await
-                .unwrap()
-        });
-        let dag = self.get_data(root_cid).await?;
-
-        for (cid, data) in dag {
-            tx.send((cid, data)).await?;
-        }
-        drop(tx);
-        write_task.await?;
+        self.sync_content(root_cid).await?;
+        let blocks = self.dag_stream(root_cid);
 
-        let buffer: Vec<_> = buffer.read().await.clone();
         let file_path = PathBuf::from(path).join(format!("{root_cid}.car"));
         create_dir_all(file_path.parent().unwrap()).await?;
         let mut file = File::create(file_path).await?;
-        file.write_all(&buffer).await?;
+        write_car(self.store.clone(), root_cid, version, blocks, &mut file).await?;
         file.sync_all().await?;
         Ok(())
     }
 
-    async fn stream(
-        &self,
-        root_cid: Cid,
-    ) -> Result<StreamBody<ReaderStream<tokio::io::DuplexStream>>> {
-        let header = CarHeader {
-            roots: vec![root_cid],
-            version: 1,
-        };
-
-        let (mut tx, mut rx) = unbounded();
+    async fn stream(&self, root_cid: Cid, version: CarVersion) -> Result<CarBody> {
+        self.sync_content(root_cid).await?;
+        let blocks = self.dag_stream(root_cid);
         let (writer, reader) = tokio::io::duplex(1024 * 100);
+        let (result_sender, result_receiver) = oneshot::channel();
 
-        let body = StreamBody::new(ReaderStream::new(reader));
-
+        let store = self.store.clone();
         task::spawn(async move {
-            if let Err(err) = header
-                .write_stream_async(&mut writer.compat_write(), &mut rx)
-                .await
-            {
+            let mut writer = writer.compat_write();
+            let result = write_car(store, root_cid, version, blocks, &mut writer).await;
+            if let Err(err) = &result {
                 error!("Error while streaming the car file {err:?}");
             }
+            // the reader ends before the result is read
+            drop(writer);
+            let _ = result_sender.send(result);
         });
-        let dag = self.get_data(root_cid).await?;
 
-        for (cid, data) in dag {
-            tx.send((cid, data)).await?;
-        }
-        drop(tx);
+        // a failed write fails the body once the written bytes are read
+        let failure = stream::once(result_receiver).filter_map(|result| {
+            future::ready(match result {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    e.to_string(),
+                ))),
+                Err(_) => Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "the car stream was interrupted",
+                ))),
+            })
+        });
 
-        Ok(body)
+        Ok(StreamBody::new(
+            ReaderStream::new(reader).chain(failure).boxed(),
+        ))
     }
 
-    async fn put_car<R: AsyncRead + Send + Unpin>(&self, car: Car<R>) -> Result<Vec<Cid>> {
-        let size = car.size;
-        let cids = load_car(self.store.blockstore(), car).await?;
-        let root_cid = cids[0];
-        info!("The inserted cids are: {cids:?}");
-        self.provide_cid(root_cid, size).await.map(|_| cids)
+    async fn get_unixfs(&self, root_cid: Cid, path: String) -> Result<UnixFsContent> {
+        // only the nodes along the path and the dag under its target are fetched
+        let cid = loop {
+            let store = Arc::clone(&self.store);
+            let path = path.clone();
+            match task::spawn_blocking(move || store.resolve_path(&root_cid, &path)).await? {
+                Ok(cid) => break cid,
+                Err(e) => match e.downcast_ref::<MissingBlock>() {
+                    Some(MissingBlock(missing)) => self.fetch_block(*missing).await?,
+                    None => return Err(e),
+                },
+            }
+        };
+        self.sync_content(cid).await?;
+        let store = Arc::clone(&self.store);
+        let stat = task::spawn_blocking(move || store.unixfs_stat(&cid)).await??;
+
+        match stat.kind {
+            UnixFsType::File | UnixFsType::Raw => Ok(UnixFsContent::File {
+                cid,
+                size: stat.size,
+                content: self.file_stream(cid),
+            }),
+            UnixFsType::Directory | UnixFsType::HamtShard => {
+                let store = Arc::clone(&self.store);
+                let entries = task::spawn_blocking(move || store.list_dir(&cid)).await??;
+                Ok(UnixFsContent::Directory { cid, entries })
+            }
+            kind => Err(anyhow!("Unsupported UnixFS node {cid} of type {kind:?}")),
+        }
+    }
+
+    async fn put_car<R: AsyncRead + Send + Unpin>(
+        &self,
+        car: Car<R>,
+    ) -> Result<Vec<PutRootResult>> {
+        // garbage collection waits until the roots are pinned
+        let _ingest = self.store.ingest();
+        let roots = self.store.import_car(car).await?;
+        if roots.is_empty() {
+            return Err(anyhow!("The car file has no roots"));
+        }
+        info!("The inserted roots are: {roots:?}");
+
+        let mut results = Vec::with_capacity(roots.len());
+        for root_cid in roots {
+            let result = match self.put_root(root_cid).await {
+                Ok(size) => PutRootResult {
+                    cid: root_cid.to_string(),
+                    size,
+                    error: None,
+                },
+                Err(e) => {
+                    error!("Failed to put the root {root_cid}: {e:?}");
+                    PutRootResult {
+                        cid: root_cid.to_string(),
+                        size: 0,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            results.push(result);
+        }
+        Ok(results)
     }
 
     /// Used through CLI
-    async fn put_file(&self, path: String) -> Result<Vec<Cid>> {
+    async fn put_file(&self, path: String) -> Result<Vec<PutRootResult>> {
         info!("Putting the file on network: {path}");
         self.put_car(Car::from_file(path).await?).await
     }
 
+    async fn put_unixfs<R: Read + Send + 'static>(&self, file: R, chunker: Chunker) -> Result<Cid> {
+        let _ingest = self.store.ingest();
+        let store = Arc::clone(&self.store);
+        let root_cid = task::spawn_blocking(move || store.import_reader(file, chunker)).await??;
+        self.put_root(root_cid).await?;
+        Ok(root_cid)
+    }
+
+    /// Used through CLI
+    async fn import_file(&self, path: String, chunker: Chunker) -> Result<Cid> {
+        info!("Importing the file with the {chunker} chunker: {path}");
+        let _ingest = self.store.ingest();
+        let store = Arc::clone(&self.store);
+        let root_cid =
+            task::spawn_blocking(move || store.import_path(Path::new(&path), chunker)).await??;
+        self.put_root(root_cid).await?;
+        Ok(root_cid)
+    }
+
     async fn get_peers(&self) -> Result<HashSet<PeerId>> {
         let (sender, receiver) = oneshot::channel();
         let request = NetworkCommand::GetPeers { sender };
@@ -227,6 +407,30 @@ where
         }
     }
 
+    async fn get_peer_scores(&self) -> Result<HashMap<PeerId, PeerScore>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GetPeerScores { sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(scores) => Ok(scores),
+            Err(e) => Err(anyhow!(format!(
+                "GetPeerScores NetworkCommand failed {e:?}"
+            ))),
+        }
+    }
+
+    async fn get_replicas(&self, cid: Cid) -> Result<HashSet<PeerId>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GetReplicas { cid, sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(replicas) => Ok(replicas),
+            Err(e) => Err(anyhow!(format!("GetReplicas NetworkCommand failed {e:?}"))),
+        }
+    }
+
     async fn get_listener_addresses(&self) -> Result<Vec<Multiaddr>> {
         let (sender, receiver) = oneshot::channel();
         let request = NetworkCommand::GetListenerAddresses { sender };
@@ -239,11 +443,40 @@ where
             ))),
         }
     }
+
+    async fn pin(&self, cid: Cid) -> Result<()> {
+        self.sync_content(cid).await?;
+        self.store.pin(&cid)
+    }
+
+    async fn unpin(&self, cid: Cid) -> Result<bool> {
+        self.store.unpin(&cid)
+    }
+
+    async fn gc(&self) -> Result<GcStats> {
+        let store = Arc::clone(&self.store);
+        task::spawn_blocking(move || store.gc()).await?
+    }
+
+    async fn list_content(&self) -> Result<Vec<StoreContent>> {
+        let mut content: Vec<StoreContent> = self
+            .store
+            .roots_meta()
+            .into_iter()
+            .map(|(cid, meta)| StoreContent {
+                cid: cid.to_string(),
+                pinned: self.store.is_pinned(&cid),
+                meta,
+            })
+            .collect();
+        content.sort_by_key(|content| content.meta.inserted_at);
+        Ok(content)
+    }
 }
 
 impl<S> NodeNetworkInterface<S>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     pub fn new(
         store: Arc<UrsaStore<S>>,
@@ -261,27 +494,122 @@ where
         }
     }
 
-    /// Ensure a root cid is synced to the blockstore
+    /// Ensure the full dag of a root cid is synced to the blockstore.
+    /// Only the blocks missing from a partial dag are fetched.
     async fn sync_content(&self, cid: Cid) -> Result<()> {
-        if !self.store.blockstore().has(&cid)? {
-            info!("Requesting block with the cid {cid:?}");
+        // a root recorded complete is not walked again
+        if matches!(self.store.root_meta(&cid), Some(meta) if meta.complete) {
+            self.store.touch(&cid);
+            return Ok(());
+        }
+        let status = self.dag_status(cid).await?;
+        if status.is_complete() {
+            // the sizes of a root recorded while its dag was partial are stale
+            if matches!(self.store.root_meta(&cid), Some(meta) if !meta.complete) {
+                self.store.refresh_root(&cid)?;
+            }
+            self.store.touch(&cid);
+            return Ok(());
+        }
 
-            let size = match self.get_network(cid).await {
-                Ok(_) => self.store.car_size(&cid)?,
-                Err(e) => {
-                    info!("Failed to get content from network: {}", e);
-                    self.get_origin(cid).await?
+        info!(
+            "Requesting {} missing blocks of the dag with the root {cid:?}",
+            status.missing.len()
+        );
+        // garbage collection waits until the root is recorded
+        let _ingest = self.store.ingest();
+        let source = match self.get_network(cid, status.present.is_empty()).await {
+            Ok(source) => source,
+            Err(e) => {
+                info!("Failed to get content from network: {}", e);
+                if status.present.is_empty() {
+                    self.get_origin(cid).await?;
+                } else {
+                    self.get_origin_blocks(cid, status.missing).await?;
                 }
-            };
-            self.provide_cid(cid, size).await
-        } else {
-            Ok(())
-        }
+                ContentSource::Origin
+            }
+        };
+        self.store.add_root(&cid, source)?;
+        let size = self.store.car_size(&cid)?;
+        self.provide_cid(cid, size).await
+    }
+
+    /// Present and missing blocks of a dag.
+    async fn dag_status(&self, root_cid: Cid) -> Result<DagStatus> {
+        let store = self.store.clone();
+        task::spawn_blocking(move || store.dag_status(&root_cid)).await?
     }
 
-    /// Fetch content from the network
-    async fn get_network(&self, root_cid: Cid) -> Result<()> {
+    /// Fetch content from the network.
+    /// A dag missing entirely is streamed as a car file from an ursa peer caching
+    /// it first, then fetched over graphsync in a single request, and bitswap
+    /// fetches whatever is still missing.
+    async fn get_network(&self, root_cid: Cid, whole_dag: bool) -> Result<ContentSource> {
         info!("Fetching cid {root_cid} from network");
+        if whole_dag {
+            // the car is only stored once the dag is complete
+            match self.get_car(root_cid).await {
+                Ok(_) => return Ok(ContentSource::Car),
+                Err(e) => info!("Failed to get {root_cid} as a car file: {e}"),
+            }
+            match self.get_graphsync(root_cid, dag_selector()).await {
+                Ok(received) => {
+                    if self.dag_status(root_cid).await?.is_complete() {
+                        return Ok(ContentSource::Graphsync);
+                    }
+                    info!("Received {received} blocks of {root_cid} over graphsync, the dag is still partial");
+                }
+                Err(e) => info!("Failed to get {root_cid} over graphsync: {e}"),
+            }
+        }
+        self.get_bitswap(root_cid).await?;
+        Ok(ContentSource::Bitswap)
+    }
+
+    /// Fetch the full dag of a root cid over graphsync.
+    /// Returns the number of blocks received
+    async fn get_graphsync(&self, root_cid: Cid, selector: Selector) -> Result<usize> {
+        let (send, recv) = oneshot::channel();
+        self.network_send.send(NetworkCommand::GetGraphsync {
+            root: root_cid,
+            selector,
+            peers: Vec::new(),
+            sender: send,
+        })?;
+        timeout(GRAPHSYNC_TIMEOUT, recv)
+            .await
+            .map_err(|_| anyhow!("Graphsync request for {root_cid} timed out"))??
+    }
+
+    /// Stream the full dag of a root cid as a car file from a peer with the
+    /// root in its cache summary.
+    async fn get_car(&self, root_cid: Cid) -> Result<Vec<Cid>> {
+        let (send, recv) = oneshot::channel();
+        self.network_send.send(NetworkCommand::GetCar {
+            peer_id: None,
+            root: root_cid,
+            max_depth: None,
+            sender: send,
+        })?;
+        timeout(CAR_TIMEOUT, recv)
+            .await
+            .map_err(|_| anyhow!("Car request for {root_cid} timed out"))??
+    }
+
+    /// Fetch a single block from the network over graphsync, or else from the origin.
+    async fn fetch_block(&self, cid: Cid) -> Result<()> {
+        match self.get_graphsync(cid, block_selector()).await {
+            Ok(received) if received > 0 && self.store.blockstore().has(&cid)? => return Ok(()),
+            Ok(_) => info!("No peer sent the block {cid} over graphsync"),
+            Err(e) => info!("Failed to get the block {cid} over graphsync: {e}"),
+        }
+        let block = self.get_origin_block(cid).await?;
+        self.store.put_block(&cid, &block, "origin")
+    }
+
+    /// Fetch the blocks of a dag over bitswap
+    async fn get_bitswap(&self, root_cid: Cid) -> Result<()> {
         let (send, recv) = oneshot::channel();
         self.network_send.send(NetworkCommand::GetBitswap {
             cid: root_cid,
@@ -313,20 +641,14 @@ where
         // we are the first concurrent request for this cid
         let client = self.client.clone();
 
-        let https = self
-            .origin_config
-            .use_https
-            .map(|v| if v { "https://" } else { "http://" })
-            .unwrap_or("https://");
-
         let req = RequestBuilder::new(
             Method::Get,
-            format!("{https}{}/ipfs/{root_cid}", self.origin_config.ipfs_gateway).parse()?,
+            format!("{}/ipfs/{root_cid}", self.origin_url()).parse()?,
         )
         .header("Accept", "application/vnd.ipld.car")
         .build();
 
-        let store = self.store.db.clone();
+        let store = self.store.clone();
         task::spawn(async move {
             // send the request
             let result: Result<u64, String> = async {
@@ -334,19 +656,16 @@ where
                     format!("Error getting content for cid {root_cid} from origin: {e}")
                 })?;
 
-                let body = res.body_bytes().await.map_err(|e| {
-                    format!("Error receiving content for cid {root_cid} from origin: {e}")
-                })?;
-                let len = body.len() as u64;
-
-                let car = CarReader::new(body.as_slice()).await.map_err(|e| {
-                    format!("Error reading car file for cid {root_cid} from origin: {e}")
-                })?;
+                // the blocks are stored as they are read, and a section larger
+                // than the maximum block size is refused before being read
+                let mut body = res.take_body().take(u64::MAX);
+                let roots = store
+                    .import_car_from(&mut body, "origin")
+                    .await
+                    .map_err(|e| format!("Error storing cid {root_cid} from origin: {e}"))?;
+                let len = u64::MAX - body.limit();
 
-                if car.header.roots.contains(&root_cid) {
-                    car.read_into(store.as_ref())
-                        .await
-                        .map_err(|e| format!("Error storing cid {root_cid} from origin: {e}"))?;
+                if roots.contains(&root_cid) {
                     Ok(len)
                 } else {
                     Err(format!(
@@ -386,6 +705,125 @@ where
             .ok_or_else(|| anyhow!("Failed to receive status from channel"))?
     }
 
+    /// Fetch single blocks from the origin until the dag of a root cid is complete.
+    async fn get_origin_blocks(&self, root_cid: Cid, mut missing: Vec<Cid>) -> Result<()> {
+        info!("Fetching missing blocks of {root_cid} from origin (ipfs)");
+        while !missing.is_empty() {
+            for cid in missing {
+                let block = self.get_origin_block(cid).await?;
+                self.store.put_block(&cid, &block, "origin")?;
+            }
+            // the links of the fetched blocks might be missing too
+            missing = self.dag_status(root_cid).await?.missing;
+        }
+        Ok(())
+    }
+
+    /// Fetch a single raw block from the origin.
+    async fn get_origin_block(&self, cid: Cid) -> Result<Vec<u8>> {
+        let req = RequestBuilder::new(
+            Method::Get,
+            format!("{}/ipfs/{cid}", self.origin_url()).parse()?,
+        )
+        .header("Accept", "application/vnd.ipld.raw")
+        .build();
+
+        let mut res = self
+            .client
+            .send(req)
+            .await
+            .map_err(|e| anyhow!("Error getting block {cid} from origin: {e}"))?;
+
+        // do not buffer more than a block can hold, the store rejects larger blocks
+        let max_block_size = self.store.max_block_size();
+        let mut block = Vec::new();
+        res.take_body()
+            .take(max_block_size as u64 + 1)
+            .read_to_end(&mut block)
+            .await
+            .map_err(|e| anyhow!("Error receiving block {cid} from origin: {e}"))?;
+        self.store.check_block_size(&cid, block.len())?;
+        Ok(block)
+    }
+
+    /// Base url of the origin gateway
+    fn origin_url(&self) -> String {
+        let https = self
+            .origin_config
+            .use_https
+            .map(|v| if v { "https://" } else { "http://" })
+            .unwrap_or("https://");
+        format!("{https}{}", self.origin_config.ipfs_gateway)
+    }
+
+    /// Stream the content of a UnixFS file, like [`Self::dag_stream`].
+    fn file_stream(&self, cid: Cid) -> ReceiverStream<std::io::Result<Vec<u8>>> {
+        let (tx, rx) = channel(DAG_STREAM_BUFFER);
+        let chunks = self.store.read_file(&cid);
+
+        task::spawn_blocking(move || {
+            for chunk in chunks {
+                let chunk = chunk.map_err(|e| {
+                    error!("Error while reading the file {cid}: {e:?}");
+                    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+                });
+                if tx.blocking_send(chunk).is_err() {
+                    debug!("File stream for {cid} was dropped");
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Stream the blocks of a dag depth-first, without holding the dag in memory.
+    /// The traversal runs on a blocking task and is bounded by [`DAG_STREAM_BUFFER`].
+    /// A traversal error is the last item of the stream.
+    fn dag_stream(&self, root_cid: Cid) -> ReceiverStream<Result<(Cid, Vec<u8>)>> {
+        let (tx, rx) = channel(DAG_STREAM_BUFFER);
+        let blocks = self.store.dag_iter(&root_cid);
+
+        task::spawn_blocking(move || {
+            for block in blocks {
+                let failed = block.is_err();
+                if let Err(e) = &block {
+                    error!("Error while traversing the dag {root_cid}: {e:?}");
+                }
+                if tx.blocking_send(block).is_err() {
+                    debug!("Dag stream for {root_cid} was dropped");
+                    break;
+                }
+                if failed {
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Pin an uploaded root and start providing it to the network.
+    /// Returns the size of its dag as a car file.
+    async fn put_root(&self, root_cid: Cid) -> Result<u64> {
+        let status = self.dag_status(root_cid).await?;
+        if !status.is_complete() {
+            return Err(anyhow!(
+                "The dag of {root_cid} is missing {} blocks",
+                status.missing.len()
+            ));
+        }
+        // uploaded content is pinned, cached content can be garbage collected
+        self.store.pin(&root_cid)?;
+        let size = self
+            .store
+            .root_meta(&root_cid)
+            .map(|meta| meta.car_size)
+            .unwrap_or_default();
+        self.provide_cid(root_cid, size).await?;
+        Ok(size)
+    }
+
     /// Trigger the network and provider to start providing the content id.
     /// If the size is not provided, it will be calculated from the blockstore
     async fn provide_cid(&self, cid: Cid, size: u64) -> Result<()> {
@@ -424,6 +862,56 @@ pub struct Car<R> {
     reader: R,
 }
 
+/// Write the blocks of a dag as a car file with a single root, failing on
+/// the first block that could not be read.
+async fn write_car<S, W>(
+    store: Arc<UrsaStore<S>>,
+    root_cid: Cid,
+    version: CarVersion,
+    blocks: ReceiverStream<Result<(Cid, Vec<u8>)>>,
+    writer: &mut W,
+) -> Result<()>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+    W: AsyncWrite + Send + Unpin,
+{
+    let mut failure = None;
+    let mut blocks = blocks.scan(&mut failure, |failure, block| {
+        future::ready(match block {
+            Ok(block) => Some(block),
+            Err(e) => {
+                **failure = Some(e);
+                None
+            }
+        })
+    });
+    let written = match version {
+        CarVersion::V1 => {
+            let header = CarHeader {
+                roots: vec![root_cid],
+                version: 1,
+            };
+            header
+                .write_stream_async(writer, &mut blocks)
+                .await
+                .map_err(|e| e.into())
+        }
+        CarVersion::V2 => {
+            // the CARv2 header holds the size of the payload
+            let data_size = task::spawn_blocking(move || store.car_size(&root_cid)).await??;
+            write_car_v2(vec![root_cid], data_size, &mut blocks, writer).await
+        }
+    };
+    drop(blocks);
+    // the block that failed to be read explains a short payload best
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    written?;
+    writer.flush().await?;
+    Ok(())
+}
+
 impl<R> Car<R>
 where
     R: AsyncRead + Send + Unpin,
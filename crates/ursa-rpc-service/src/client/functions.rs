@@ -1,8 +1,11 @@
 use jsonrpc_v2::Error;
 
 use crate::api::{
-    NetworkGetFileParams, NetworkGetParams, NetworkGetResult, NetworkPutFileParams,
-    NetworkPutFileResult, NETWORK_GET, NETWORK_GET_FILE, NETWORK_PUT_FILE,
+    NetworkGetFileParams, NetworkGetParams, NetworkGetReplicas, NetworkGetReplicasParams,
+    NetworkGetResult, NetworkImportFileParams, NetworkImportFileResult, NetworkPutFileParams,
+    NetworkPutFileResult, StoreGcResult, StoreListResult, StorePinParams, StoreUnpinResult,
+    NETWORK_GET, NETWORK_GET_FILE, NETWORK_GET_REPLICAS, NETWORK_IMPORT_FILE, NETWORK_PUT_FILE,
+    STORE_GC, STORE_LIST, STORE_PIN, STORE_UNPIN,
 };
 
 use super::{
@@ -23,3 +26,27 @@ pub async fn get_file(params: NetworkGetFileParams) -> Result<()> {
 pub async fn put_file(params: NetworkPutFileParams) -> Result<NetworkPutFileResult> {
     call(NETWORK_PUT_FILE, params, Put).await
 }
+
+pub async fn import_file(params: NetworkImportFileParams) -> Result<NetworkImportFileResult> {
+    call(NETWORK_IMPORT_FILE, params, Put).await
+}
+
+pub async fn get_replicas(params: NetworkGetReplicasParams) -> Result<NetworkGetReplicas> {
+    call(NETWORK_GET_REPLICAS, params, Post).await
+}
+
+pub async fn pin(params: StorePinParams) -> Result<()> {
+    call(STORE_PIN, params, Put).await
+}
+
+pub async fn unpin(params: StorePinParams) -> Result<StoreUnpinResult> {
+    call(STORE_UNPIN, params, Put).await
+}
+
+pub async fn gc() -> Result<StoreGcResult> {
+    call(STORE_GC, serde_json::json!([]), Post).await
+}
+
+pub async fn list_content() -> Result<StoreListResult> {
+    call(STORE_LIST, serde_json::json!([]), Post).await
+}
@@ -0,0 +1,99 @@
+//! Content types of files served over http.
+
+/// Magic numbers of common file formats.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x00asm", "application/wasm"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"<?xml", "application/xml"),
+];
+
+/// Content type of a file, from the extension of the last segment of its path
+/// if known, otherwise sniffed from the first bytes of its content.
+pub fn content_type(path: &str, head: &[u8]) -> &'static str {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    name.rsplit_once('.')
+        .and_then(|(_, ext)| from_extension(&ext.to_ascii_lowercase()))
+        .unwrap_or_else(|| sniff(head))
+}
+
+fn from_extension(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => return None,
+    })
+}
+
+fn sniff(head: &[u8]) -> &'static str {
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return *content_type;
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(&b"WEBP"[..]) {
+        return "image/webp";
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    match std::str::from_utf8(head) {
+        Ok(text) => text_type(text),
+        // the head may end in the middle of a character
+        Err(e) if e.error_len().is_none() => {
+            text_type(std::str::from_utf8(&head[..e.valid_up_to()]).unwrap())
+        }
+        Err(_) => "application/octet-stream",
+    }
+}
+
+fn text_type(text: &str) -> &'static str {
+    let start = text
+        .trim_start()
+        .chars()
+        .take(14)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
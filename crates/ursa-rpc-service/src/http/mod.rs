@@ -1 +1,2 @@
+pub mod mime;
 pub mod routes;
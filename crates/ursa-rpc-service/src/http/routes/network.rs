@@ -1,27 +1,36 @@
 pub const BASE_PATH: &str = "./car_files";
 
-use crate::api::{Car, NetworkInterface, NodeNetworkInterface};
+use crate::{
+    api::{Car, NetworkInterface, NodeNetworkInterface, UnixFsContent},
+    http::mime::content_type,
+};
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, Path},
+    body::{Bytes, StreamBody},
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
     http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
     response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
 use db::Store;
-use futures::io::Cursor;
+use futures::{io::Cursor, stream, StreamExt};
 use fvm_ipld_blockstore::Blockstore;
 use hyper::StatusCode;
 use libipld::Cid;
-use std::{str::FromStr, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 use tokio::task;
 use tower_http::limit::RequestBodyLimitLayer;
 use tracing::{error, info};
+use ursa_store::{CarVersion, Chunker, ListKeys, PathError};
 
-pub fn init<S: Blockstore + Store + Send + Sync + 'static>() -> Router {
+pub fn init<S: Blockstore + Store + ListKeys + Send + Sync + 'static>() -> Router {
     Router::new()
         .route("/ursa/v0/", post(upload_handler::<S>))
+        .route("/ursa/v0/file", post(upload_file_handler::<S>))
         .route("/ursa/v0/:cid", get(get_handler::<S>))
+        .route("/ipfs/:cid", get(unixfs_handler::<S>))
+        .route("/ipfs/:cid/*path", get(unixfs_handler::<S>))
         .route("/ping", get(|| async { "pong" })) // to be used for TLS verification
         .layer(DefaultBodyLimit::disable())
         .layer(RequestBodyLimitLayer::new(250 * 1024 * 1024)) // 250mb
@@ -49,7 +58,7 @@ pub async fn upload_handler<S>(
     mut buf: Multipart,
 ) -> Result<impl IntoResponse, NetworkError>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     let upload_task = task::spawn(async move {
         info!("uploading file via http");
@@ -75,7 +84,7 @@ where
                         error!("{:?}", err);
                         Err(NetworkError::InternalError(err.to_string()))
                     }
-                    Ok(res) => Ok((StatusCode::OK, Json(format!("{res:?}")))),
+                    Ok(res) => Ok((StatusCode::OK, Json(res))),
                 }
             } else {
                 Err(NetworkError::BadRequest(
@@ -91,17 +100,66 @@ where
         .map_err(|err| NetworkError::InternalError(err.to_string()))?
 }
 
+#[derive(Deserialize)]
+pub struct UploadFileQuery {
+    /// Chunker used to split the file, fixed 256KiB chunks if not set.
+    chunker: Option<String>,
+}
+
+/// Upload the raw content of a file, chunked into a UnixFS dag by the node.
+pub async fn upload_file_handler<S>(
+    Query(query): Query<UploadFileQuery>,
+    Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, NetworkError>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+{
+    info!("uploading raw file via http");
+    let chunker = query
+        .chunker
+        .as_deref()
+        .map(Chunker::from_str)
+        .transpose()
+        .map_err(|e| NetworkError::BadRequest(e.to_string()))?
+        .unwrap_or_default();
+
+    match interface
+        .put_unixfs(std::io::Cursor::new(body), chunker)
+        .await
+    {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(NetworkError::InternalError(err.to_string()))
+        }
+        Ok(root_cid) => Ok((StatusCode::OK, Json(root_cid.to_string()))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GetQuery {
+    /// Version of the car file to stream, 1 if not set.
+    car_version: Option<u64>,
+}
+
 pub async fn get_handler<S>(
     Path(cid_str): Path<String>,
+    Query(query): Query<GetQuery>,
     Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
 ) -> Result<impl IntoResponse, NetworkError>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     info!("Streaming file over http");
+    let version = query
+        .car_version
+        .map(CarVersion::try_from)
+        .transpose()
+        .map_err(|e| NetworkError::BadRequest(e.to_string()))?
+        .unwrap_or_default();
     if let Ok(cid) = Cid::from_str(&cid_str) {
         let mut res = Response::builder();
-        return match interface.stream(cid).await {
+        return match interface.stream(cid, version).await {
             Ok(body) => {
                 let headers = res.headers_mut().unwrap();
                 headers.insert(
@@ -128,3 +186,67 @@ where
         )))
     }
 }
+
+/// An entry of a UnixFS directory listing.
+#[derive(Serialize)]
+pub struct DirEntry {
+    name: String,
+    cid: String,
+    size: u64,
+}
+
+/// Serve the file under a UnixFS path, or list the directory under it.
+pub async fn unixfs_handler<S>(
+    Path(params): Path<HashMap<String, String>>,
+    Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
+) -> Result<Response, NetworkError>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+{
+    let cid_str = params.get("cid").cloned().unwrap_or_default();
+    let path = params.get("path").cloned().unwrap_or_default();
+    let root_cid = Cid::from_str(&cid_str).map_err(|_| {
+        NetworkError::BadRequest(format!(
+            "Invalid Cid String, Cannot Parse {cid_str:?} to CID"
+        ))
+    })?;
+    info!("Serving /ipfs/{root_cid}/{path} over http");
+
+    match interface.get_unixfs(root_cid, path.clone()).await {
+        Ok(UnixFsContent::File { mut content, .. }) => {
+            // the first chunk is used to sniff the content type
+            let head = content.next().await;
+            let content_type = match &head {
+                Some(Ok(head)) => content_type(&path, head),
+                _ => content_type(&path, &[]),
+            };
+            // the size recorded in the file node is not checked against its
+            // content, so the body is sent without a length
+            let body = StreamBody::new(stream::iter(head).chain(content));
+            Ok((
+                StatusCode::OK,
+                [(CONTENT_TYPE, content_type.to_string())],
+                body,
+            )
+                .into_response())
+        }
+        Ok(UnixFsContent::Directory { entries, .. }) => {
+            let entries: Vec<DirEntry> = entries
+                .into_iter()
+                .map(|entry| DirEntry {
+                    name: entry.name,
+                    cid: entry.cid.to_string(),
+                    size: entry.size,
+                })
+                .collect();
+            Ok((StatusCode::OK, Json(entries)).into_response())
+        }
+        Err(err) => {
+            error!("{:?}", err);
+            match err.downcast_ref::<PathError>() {
+                Some(_) => Err(NetworkError::NotFoundError(err.to_string())),
+                None => Err(NetworkError::InternalError(err.to_string())),
+            }
+        }
+    }
+}
@@ -64,11 +64,18 @@ impl RpcServer {
             .with_method("ursa_get_cid", network::get_cid_handler::<I>)
             .with_method("ursa_get_file", network::get_file_handler::<I>)
             .with_method("ursa_put_file", network::put_file_handler::<I>)
+            .with_method("ursa_import_file", network::import_file_handler::<I>)
             .with_method("ursa_get_peers", network::get_peers::<I>)
+            .with_method("ursa_get_peer_scores", network::get_peer_scores::<I>)
+            .with_method("ursa_get_replicas", network::get_replicas_handler::<I>)
             .with_method(
                 "ursa_listener_addresses",
                 network::get_listener_addresses::<I>,
-            );
+            )
+            .with_method("ursa_pin", network::pin_handler::<I>)
+            .with_method("ursa_unpin", network::unpin_handler::<I>)
+            .with_method("ursa_gc", network::gc_handler::<I>)
+            .with_method("ursa_list_content", network::list_content_handler::<I>);
 
         RpcServer(server.finish())
     }
@@ -6,13 +6,16 @@ use axum::{
 use libipld::Cid;
 use std::{str::FromStr, sync::Arc};
 use ursa_metrics::middleware::track_metrics;
+use ursa_store::{CarVersion, Chunker};
 
 use jsonrpc_v2::{Data, Error, Params};
 
 use crate::{
     api::{
-        NetworkGetFileParams, NetworkGetListenerAddresses, NetworkGetParams, NetworkGetPeers,
-        NetworkGetResult, NetworkInterface, NetworkPutFileParams, NetworkPutFileResult,
+        NetworkGetFileParams, NetworkGetListenerAddresses, NetworkGetParams, NetworkGetPeerScores,
+        NetworkGetPeers, NetworkGetReplicas, NetworkGetReplicasParams, NetworkGetResult,
+        NetworkImportFileParams, NetworkImportFileResult, NetworkInterface, NetworkPutFileParams,
+        NetworkPutFileResult, StoreGcResult, StoreListResult, StorePinParams, StoreUnpinResult,
     },
     rpc::rpc_handler,
 };
@@ -52,8 +55,15 @@ where
     I: NetworkInterface,
 {
     let path = params.path;
+    let version = match params.car_version.map(CarVersion::try_from).transpose() {
+        Ok(version) => version.unwrap_or_default(),
+        Err(err) => {
+            error!("{:?}", err);
+            return Err(Error::INVALID_PARAMS);
+        }
+    };
     if let Ok(cid) = Cid::from_str(&params.cid) {
-        match data.0.get_file(path, cid).await {
+        match data.0.get_file(path, cid, version).await {
             Err(err) => {
                 error!("{:?}", err);
                 Err(Error::internal(err))
@@ -80,7 +90,31 @@ where
             error!("{:?}", err);
             Err(Error::internal(err))
         }
-        Ok(res) => Ok(res.iter().map(|c| Cid::from(c).to_string()).collect()),
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn import_file_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkImportFileParams>,
+) -> Result<NetworkImportFileResult>
+where
+    I: NetworkInterface,
+{
+    let chunker = match params.chunker.as_deref().map(Chunker::from_str).transpose() {
+        Ok(chunker) => chunker.unwrap_or_default(),
+        Err(err) => {
+            error!("{:?}", err);
+            return Err(Error::INVALID_PARAMS);
+        }
+    };
+
+    match data.0.import_file(params.path, chunker).await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(root_cid) => Ok(root_cid.to_string()),
     }
 }
 
@@ -97,6 +131,40 @@ where
     }
 }
 
+pub async fn get_peer_scores<I>(data: Data<Arc<I>>) -> Result<NetworkGetPeerScores>
+where
+    I: NetworkInterface,
+{
+    match data.0.get_peer_scores().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn get_replicas_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkGetReplicasParams>,
+) -> Result<NetworkGetReplicas>
+where
+    I: NetworkInterface,
+{
+    if let Ok(cid) = Cid::from_str(&params.cid) {
+        match data.0.get_replicas(cid).await {
+            Err(err) => {
+                error!("{:?}", err);
+                Err(Error::internal(err))
+            }
+            Ok(res) => Ok(res),
+        }
+    } else {
+        error!("Invalid Cid String, Cannot Parse {} to CID", &params.cid);
+        Err(Error::INVALID_PARAMS)
+    }
+}
+
 pub async fn get_listener_addresses<I>(data: Data<Arc<I>>) -> Result<NetworkGetListenerAddresses>
 where
     I: NetworkInterface,
@@ -114,3 +182,71 @@ where
         }
     }
 }
+
+pub async fn pin_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<StorePinParams>,
+) -> Result<()>
+where
+    I: NetworkInterface,
+{
+    if let Ok(cid) = Cid::from_str(&params.cid) {
+        match data.0.pin(cid).await {
+            Err(err) => {
+                error!("{:?}", err);
+                Err(Error::internal(err))
+            }
+            _ => Ok(()),
+        }
+    } else {
+        error!("Invalid Cid String, Cannot Parse {} to CID", &params.cid);
+        Err(Error::INVALID_PARAMS)
+    }
+}
+
+pub async fn unpin_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<StorePinParams>,
+) -> Result<StoreUnpinResult>
+where
+    I: NetworkInterface,
+{
+    if let Ok(cid) = Cid::from_str(&params.cid) {
+        match data.0.unpin(cid).await {
+            Err(err) => {
+                error!("{:?}", err);
+                Err(Error::internal(err))
+            }
+            Ok(res) => Ok(res),
+        }
+    } else {
+        error!("Invalid Cid String, Cannot Parse {} to CID", &params.cid);
+        Err(Error::INVALID_PARAMS)
+    }
+}
+
+pub async fn gc_handler<I>(data: Data<Arc<I>>) -> Result<StoreGcResult>
+where
+    I: NetworkInterface,
+{
+    match data.0.gc().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn list_content_handler<I>(data: Data<Arc<I>>) -> Result<StoreListResult>
+where
+    I: NetworkInterface,
+{
+    match data.0.list_content().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
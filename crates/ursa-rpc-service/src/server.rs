@@ -3,6 +3,7 @@ use axum::{Extension, Router};
 use db::Store;
 use fvm_ipld_blockstore::Blockstore;
 use std::{net::SocketAddr, sync::Arc};
+use ursa_store::ListKeys;
 
 use crate::{
     api::NodeNetworkInterface,
@@ -15,7 +16,7 @@ use tracing::info;
 
 pub struct Server<S>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     rpc_server: RpcServer,
     interface: Arc<NodeNetworkInterface<S>>,
@@ -23,7 +24,7 @@ where
 
 impl<S> Server<S>
 where
-    S: Blockstore + Store + Send + Sync + 'static,
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
 {
     pub fn new(interface: Arc<NodeNetworkInterface<S>>) -> Self {
         Self {
@@ -1,16 +1,18 @@
 #[cfg(test)]
 mod tests {
-    use crate::api::{NetworkInterface, NodeNetworkInterface};
+    use crate::api::{Car, NetworkInterface, NodeNetworkInterface, UnixFsContent};
     use crate::config::OriginConfig;
     use crate::tests::{dummy_ipfs, init, setup_logger};
     use anyhow::Result;
     use async_fs::{remove_file, File};
-    use futures::io::BufReader;
-    use fvm_ipld_car::load_car;
+    use futures::{io::BufReader, stream, TryStreamExt};
+    use fvm_ipld_car::{load_car, CarHeader};
+    use libipld::{cbor::DagCborCodec, ipld, multihash::Code, Block, DefaultParams};
     use std::path::Path;
     use std::sync::Arc;
     use tokio::task;
     use tracing::error;
+    use ursa_store::{CarVersion, Chunker};
 
     #[tokio::test]
     async fn test_put_and_get() -> Result<()> {
@@ -32,10 +34,12 @@ mod tests {
         let put_file = interface
             .put_file("../../test_files/test.car".to_string())
             .await?;
-        let root_cid = put_file[0];
+        assert_eq!(put_file.len(), 1);
+        assert_eq!(put_file[0].error, None);
+        let root_cid = put_file[0].cid.parse()?;
 
         interface
-            .get_file("../../test_files".to_string(), root_cid)
+            .get_file("../../test_files".to_string(), root_cid, CarVersion::V1)
             .await?;
 
         let path = format!("../../test_files/{root_cid}.car");
@@ -50,6 +54,101 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_put_multi_root_car() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+        ));
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let encode = |ipld| Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &ipld);
+        let leaf = encode(ipld!({ "name": "leaf" }))?;
+        let complete = encode(ipld!({ "name": "complete", "link": leaf.cid() }))?;
+        let missing = encode(ipld!({ "name": "missing" }))?;
+        let partial = encode(ipld!({ "name": "partial", "link": missing.cid() }))?;
+
+        // the car file holds every block but the one linked from the partial root
+        let header = CarHeader {
+            roots: vec![*complete.cid(), *partial.cid()],
+            version: 1,
+        };
+        let mut blocks = stream::iter(
+            [&complete, &leaf, &partial].map(|block| (*block.cid(), block.data().to_vec())),
+        );
+        let mut bytes = Vec::new();
+        header.write_stream_async(&mut bytes, &mut blocks).await?;
+        let car = Car::new(bytes.len() as u64, futures::io::Cursor::new(bytes));
+
+        let results = interface.put_car(car).await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].cid, complete.cid().to_string());
+        assert_eq!(results[0].error, None);
+        assert_eq!(
+            results[0].size,
+            store.root_meta(complete.cid()).unwrap().car_size
+        );
+        assert!(store.is_pinned(complete.cid()));
+
+        assert_eq!(results[1].cid, partial.cid().to_string());
+        assert_eq!(results[1].size, 0);
+        assert!(results[1].error.is_some());
+        assert!(!store.is_pinned(partial.cid()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_unixfs() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+        ));
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let content = std::fs::read("../../test_files/test.car")?;
+        let root_cid = interface
+            .put_unixfs(std::io::Cursor::new(content.clone()), Chunker::Fixed(4096))
+            .await?;
+        assert!(store.is_pinned(&root_cid));
+
+        let leaves: Vec<u8> = interface
+            .get_data(root_cid)
+            .await?
+            .into_iter()
+            .filter(|(cid, _)| cid.codec() == 0x55)
+            .flat_map(|(_, data)| data)
+            .collect();
+        assert_eq!(leaves, content);
+
+        match interface.get_unixfs(root_cid, "/".to_string()).await? {
+            UnixFsContent::File {
+                size,
+                content: file,
+                ..
+            } => {
+                assert_eq!(size, content.len() as u64);
+                let file: Vec<Vec<u8>> = file.try_collect().await?;
+                assert_eq!(file.concat(), content);
+            }
+            UnixFsContent::Directory { .. } => panic!("the content should be a file"),
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_origin_fallback() -> Result<()> {
         setup_logger();
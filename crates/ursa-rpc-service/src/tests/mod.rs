@@ -3,7 +3,6 @@ mod server_test;
 
 use anyhow::Result;
 use axum::{headers::HeaderMap, routing::get, Router};
-use db::MemoryDB;
 use libp2p::identity::Keypair;
 use libp2p::Multiaddr;
 use simple_logger::SimpleLogger;
@@ -11,7 +10,7 @@ use std::sync::Arc;
 use tracing::{log::LevelFilter, warn};
 use ursa_index_provider::{config::ProviderConfig, engine::ProviderEngine};
 use ursa_network::{NetworkConfig, UrsaService};
-use ursa_store::UrsaStore;
+use ursa_store::{MemoryStore, UrsaStore};
 
 pub fn setup_logger() {
     let level = LevelFilter::Debug;
@@ -24,15 +23,15 @@ pub fn setup_logger() {
     }
 }
 
-pub fn get_store() -> Arc<UrsaStore<MemoryDB>> {
-    let db = Arc::new(MemoryDB::default());
+pub fn get_store() -> Arc<UrsaStore<MemoryStore>> {
+    let db = Arc::new(MemoryStore::default());
     Arc::new(UrsaStore::new(Arc::clone(&db)))
 }
 
 type InitResult = Result<(
-    UrsaService<MemoryDB>,
-    ProviderEngine<MemoryDB>,
-    Arc<UrsaStore<MemoryDB>>,
+    UrsaService<MemoryStore>,
+    ProviderEngine<MemoryStore>,
+    Arc<UrsaStore<MemoryStore>>,
 )>;
 
 pub fn init() -> InitResult {
@@ -0,0 +1,257 @@
+//! A flat-file database in the style of go-ds-flatfs.
+//!
+//! Every key is stored in its own file, named by the hex encoding of the key
+//! and sharded in directories by the two characters before its last one
+//! (`next-to-last/2`). Files are written under a temporary name and renamed
+//! into place, so a key is never read half written.
+//!
+//! A file name cannot be longer than 255 bytes, so keys longer than
+//! [`MAX_NAMED_KEY`] are stored in a file named by the sha2-256 of the key
+//! instead, with the key written before the value.
+
+use anyhow::{bail, Result};
+use db::{Error, Store};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::ListKeys;
+
+/// Sharding function of the database, recorded in its `SHARDING` file.
+pub const FLATFS_SHARDING: &str = "/repo/flatfs/shard/v1/next-to-last/2";
+const SHARDING_FILE: &str = "SHARDING";
+const EXTENSION: &str = "data";
+/// Extension of the files of the keys longer than [`MAX_NAMED_KEY`].
+const LONG_EXTENSION: &str = "long";
+/// Longest key, in bytes, stored in a file named by its hex encoding.
+pub const MAX_NAMED_KEY: usize = 100;
+
+#[derive(Debug)]
+pub struct FlatFs {
+    path: PathBuf,
+    /// Sync files to disk before renaming them into place.
+    sync: bool,
+    /// Counter making the names of temporary files unique.
+    temp: AtomicU64,
+}
+
+impl FlatFs {
+    /// Open the database at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        let sharding = path.join(SHARDING_FILE);
+        match fs::read_to_string(&sharding) {
+            Ok(found) if found.trim() == FLATFS_SHARDING => {}
+            Ok(found) => bail!("Unsupported flatfs sharding {:?} in {path:?}", found.trim()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::write(&sharding, format!("{FLATFS_SHARDING}\n"))?
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self {
+            path,
+            sync: true,
+            temp: AtomicU64::new(0),
+        })
+    }
+
+    /// Do not sync written files to disk, trading durability for write speed.
+    pub fn without_sync(mut self) -> Self {
+        self.sync = false;
+        self
+    }
+
+    fn key_path(&self, key: &[u8]) -> PathBuf {
+        let (name, extension) = if key.len() > MAX_NAMED_KEY {
+            (hex(Code::Sha2_256.digest(key).digest()), LONG_EXTENSION)
+        } else {
+            (hex(key), EXTENSION)
+        };
+        let padded = format!("{name:_>3}");
+        let shard = &padded[padded.len() - 3..padded.len() - 1];
+        self.path.join(shard).join(format!("{name}.{extension}"))
+    }
+
+    fn read_file(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let data = match fs::read(self.key_path(key)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if key.len() <= MAX_NAMED_KEY {
+            return Ok(Some(data));
+        }
+        // the key before the value tells a long key from another with the same hash
+        match split_long_key(&data)? {
+            (stored, value) if stored == key => Ok(Some(value.to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    fn write_file(&self, path: &Path, value: &[u8]) -> io::Result<()> {
+        if let Some(shard) = path.parent() {
+            fs::create_dir_all(shard)?;
+        }
+        let temp = self.path.join(format!(
+            ".temp-{}-{}",
+            std::process::id(),
+            self.temp.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = File::create(&temp).and_then(|mut file| {
+            file.write_all(value)?;
+            if self.sync {
+                file.sync_data()?;
+            }
+            Ok(())
+        });
+        match written.and_then(|_| fs::rename(&temp, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Other(e.to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(name: &str) -> Option<Vec<u8>> {
+    if name.len() % 2 != 0 {
+        return None;
+    }
+    (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Split the content of the file of a long key into the key and the value.
+fn split_long_key(data: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated long key file");
+    if data.len() < 4 {
+        return Err(invalid());
+    }
+    let (len, data) = data.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    if data.len() < len {
+        return Err(invalid());
+    }
+    Ok(data.split_at(len))
+}
+
+impl Store for FlatFs {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.read_file(key.as_ref()).map_err(io_error)
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        if key.len() <= MAX_NAMED_KEY {
+            return self
+                .write_file(&self.key_path(key), value)
+                .map_err(io_error);
+        }
+        let len = u32::try_from(key.len())
+            .map_err(|_| Error::Other(format!("key of {} bytes is too long", key.len())))?;
+        let mut data = Vec::with_capacity(4 + key.len() + value.len());
+        data.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(key);
+        data.extend_from_slice(value);
+        self.write_file(&self.key_path(key), &data)
+            .map_err(io_error)
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        match fs::remove_file(self.key_path(key.as_ref())) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        if key.len() > MAX_NAMED_KEY {
+            return Ok(self.read_file(key).map_err(io_error)?.is_some());
+        }
+        match fs::metadata(self.key_path(key)) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+}
+
+impl ListKeys for FlatFs {
+    fn for_each_key(&self, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        for shard in fs::read_dir(&self.path)? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(shard.path())? {
+                let path = file?.path();
+                let name = path.file_stem().and_then(|name| name.to_str());
+                let extension = path.extension().and_then(|extension| extension.to_str());
+                match (name, extension) {
+                    (Some(name), Some(EXTENSION)) => {
+                        if let Some(key) = from_hex(name) {
+                            f(&key)?;
+                        }
+                    }
+                    (Some(_), Some(LONG_EXTENSION)) => {
+                        let data = match fs::read(&path) {
+                            Ok(data) => data,
+                            // deleted since the directory was read
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                            Err(e) => return Err(e.into()),
+                        };
+                        f(split_long_key(&data)?.0)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Blockstore for FlatFs {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.read(k.to_bytes()).map_err(|e| e.into())
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.write(k.to_bytes(), block).map_err(|e| e.into())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        self.exists(k.to_bytes()).map_err(|e| e.into())
+    }
+}
@@ -0,0 +1,77 @@
+//! An in-memory database, for tests and ephemeral nodes.
+//!
+//! Unlike `db::MemoryDB` it can list its keys, which the store needs to walk
+//! every block it holds.
+
+use anyhow::Result;
+use db::{Error, Store};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::Cid;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use super::ListKeys;
+
+/// Keys and values kept in memory, clones share the same data.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    db: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl Store for MemoryStore {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self.db.read().unwrap().get(key.as_ref()).cloned())
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.db
+            .write()
+            .unwrap()
+            .insert(key.as_ref().to_vec(), value.as_ref().to_vec());
+        Ok(())
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.db.write().unwrap().remove(key.as_ref());
+        Ok(())
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(self.db.read().unwrap().contains_key(key.as_ref()))
+    }
+}
+
+impl Blockstore for MemoryStore {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.read(k.to_bytes()).map_err(|e| e.into())
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.write(k.to_bytes(), block).map_err(|e| e.into())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        self.exists(k.to_bytes()).map_err(|e| e.into())
+    }
+}
+
+impl ListKeys for MemoryStore {
+    fn for_each_key(&self, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        // the keys are copied so `f` can use the store
+        let keys: Vec<Vec<u8>> = self.db.read().unwrap().keys().cloned().collect();
+        keys.iter().try_for_each(|key| f(key))
+    }
+}
@@ -0,0 +1,158 @@
+//! Database backends of the [`UrsaStore`](crate::UrsaStore).
+//!
+//! The node picks a [`BackendKind`] in its config and opens it as a [`Backend`],
+//! which dispatches to the chosen database. Every backend implements both
+//! [`Store`] and [`Blockstore`], with blocks keyed by the bytes of their cid,
+//! and [`ListKeys`] so every block can be walked.
+
+mod flatfs;
+mod memory;
+
+pub use self::flatfs::*;
+pub use self::memory::*;
+
+#[cfg(not(feature = "rocksdb"))]
+use anyhow::bail;
+use anyhow::Result;
+#[cfg(feature = "rocksdb")]
+use db::{rocks::RocksDb, rocks_config::RocksDbConfig};
+use db::{Error, Store};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::Cid;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A database listing the keys it holds, so the store can walk every block
+/// and not only the ones reachable from the roots it knows.
+pub trait ListKeys {
+    /// Call `f` with every key of the database, stopping at the first error.
+    fn for_each_key(&self, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()>;
+}
+
+#[cfg(feature = "rocksdb")]
+impl ListKeys for RocksDb {
+    fn for_each_key(&self, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let mut iter = self.db.raw_iterator();
+        iter.seek_to_first();
+        while iter.valid() {
+            if let Some(key) = iter.key() {
+                f(key)?;
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(())
+    }
+}
+
+/// Database used to hold the blocks and the metadata of a store.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// A RocksDB database.
+    #[default]
+    RocksDb,
+    /// One file per key, sharded in directories, see [`FlatFs`].
+    FlatFs,
+    /// Kept in memory and lost on exit, for tests and ephemeral nodes.
+    Memory,
+}
+
+/// A database opened from a [`BackendKind`].
+pub enum Backend {
+    #[cfg(feature = "rocksdb")]
+    RocksDb(RocksDb),
+    FlatFs(FlatFs),
+    Memory(MemoryStore),
+}
+
+impl Backend {
+    /// Open the database of a backend at `path`, ignored by the memory backend.
+    pub fn open(kind: BackendKind, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(match kind {
+            #[cfg(feature = "rocksdb")]
+            BackendKind::RocksDb => {
+                Backend::RocksDb(RocksDb::open(path.as_ref(), &RocksDbConfig::default())?)
+            }
+            #[cfg(not(feature = "rocksdb"))]
+            BackendKind::RocksDb => bail!("ursa-store was built without the rocksdb feature"),
+            BackendKind::FlatFs => Backend::FlatFs(FlatFs::open(path)?),
+            BackendKind::Memory => Backend::Memory(MemoryStore::default()),
+        })
+    }
+
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            #[cfg(feature = "rocksdb")]
+            Backend::RocksDb(_) => BackendKind::RocksDb,
+            Backend::FlatFs(_) => BackendKind::FlatFs,
+            Backend::Memory(_) => BackendKind::Memory,
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($backend:expr, $db:ident => $body:expr) => {
+        match $backend {
+            #[cfg(feature = "rocksdb")]
+            Backend::RocksDb($db) => $body,
+            Backend::FlatFs($db) => $body,
+            Backend::Memory($db) => $body,
+        }
+    };
+}
+
+impl Store for Backend {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        dispatch!(self, db => db.read(key))
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        dispatch!(self, db => db.write(key, value))
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        dispatch!(self, db => db.delete(key))
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        dispatch!(self, db => db.exists(key))
+    }
+}
+
+impl Blockstore for Backend {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        dispatch!(self, db => Blockstore::get(db, k))
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        dispatch!(self, db => db.put_keyed(k, block))
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        dispatch!(self, db => db.has(k))
+    }
+}
+
+impl ListKeys for Backend {
+    fn for_each_key(&self, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        dispatch!(self, db => db.for_each_key(f))
+    }
+}
+
+#[cfg(test)]
+#[path = "../tests/backend_tests.rs"]
+mod backend_tests;
@@ -0,0 +1,546 @@
+//! CARv2 import and export for the [`UrsaStore`].
+//!
+//! A CARv2 file wraps a CARv1 data payload between a fixed size header and an
+//! optional index:
+//!
+//! ```text
+//! | pragma (11) | header (40) | padding | CARv1 payload | padding | index |
+//! ```
+//!
+//! The only index format supported is `MultihashIndexSorted`, which maps the
+//! multihash of every block to the offset of its section in the CARv1 payload.
+//! Imports read the payload as a stream, so the index is only checked against
+//! the offsets of the blocks read.
+
+use anyhow::{anyhow, bail};
+use db::Store;
+use futures::io::{copy, sink, Cursor};
+use futures::{stream, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_car::CarHeader;
+use fvm_ipld_encoding::{from_slice, to_vec};
+use integer_encoding::VarInt;
+use libipld::{Cid, Result};
+use std::collections::BTreeMap;
+use std::io::Read;
+use tracing::{info, warn};
+
+use crate::UrsaStore;
+
+/// Fixed bytes identifying a CARv2 file, a CARv1 header with `version: 2`.
+pub const CARV2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+/// Size of the CARv2 header following the pragma.
+pub const CARV2_HEADER_SIZE: usize = 40;
+/// Multicodec of the `MultihashIndexSorted` index format.
+pub const MULTIHASH_INDEX_SORTED: u64 = 0x0401;
+/// Largest CARv2 index read on import.
+pub const MAX_CAR_INDEX_SIZE: u64 = 64 * 1024 * 1024;
+/// Bytes a section may take in addition to its block, for the cid.
+const MAX_CID_SIZE: usize = 128;
+
+/// Version of the CAR files to emit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CarVersion {
+    #[default]
+    V1,
+    /// CARv2 with a `MultihashIndexSorted` index.
+    V2,
+}
+
+impl TryFrom<u64> for CarVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(version: u64) -> Result<Self> {
+        match version {
+            1 => Ok(CarVersion::V1),
+            2 => Ok(CarVersion::V2),
+            _ => Err(anyhow!("Unsupported car version {version}")),
+        }
+    }
+}
+
+/// The CARv2 header, all offsets are from the start of the file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CarV2Header {
+    pub characteristics: [u8; 16],
+    /// Offset of the CARv1 payload.
+    pub data_offset: u64,
+    /// Size of the CARv1 payload in bytes.
+    pub data_size: u64,
+    /// Offset of the index, 0 if the file has no index.
+    pub index_offset: u64,
+}
+
+impl CarV2Header {
+    pub fn from_bytes(bytes: &[u8; CARV2_HEADER_SIZE]) -> Self {
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        Self {
+            characteristics: bytes[..16].try_into().unwrap(),
+            data_offset: u64_at(16),
+            data_size: u64_at(24),
+            index_offset: u64_at(32),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; CARV2_HEADER_SIZE] {
+        let mut bytes = [0; CARV2_HEADER_SIZE];
+        bytes[..16].copy_from_slice(&self.characteristics);
+        bytes[16..24].copy_from_slice(&self.data_offset.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.data_size.to_le_bytes());
+        bytes[32..].copy_from_slice(&self.index_offset.to_le_bytes());
+        bytes
+    }
+}
+
+/// Offset of a block section in a CARv1 payload.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexRecord {
+    /// Multihash code of the block cid.
+    pub code: u64,
+    /// Multihash digest of the block cid.
+    pub digest: Vec<u8>,
+    /// Offset of the section from the start of the CARv1 payload.
+    pub offset: u64,
+}
+
+/// A `MultihashIndexSorted` CARv2 index.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CarIndex {
+    /// Records sorted by multihash code and digest.
+    records: Vec<IndexRecord>,
+}
+
+impl CarIndex {
+    pub fn new(mut records: Vec<IndexRecord>) -> Self {
+        records.sort();
+        records.dedup_by(|a, b| a.code == b.code && a.digest == b.digest);
+        Self { records }
+    }
+
+    pub fn records(&self) -> &[IndexRecord] {
+        &self.records
+    }
+
+    /// Offset of the section of a block in the CARv1 payload
+    pub fn get(&self, cid: &Cid) -> Option<u64> {
+        let key = (cid.hash().code(), cid.hash().digest());
+        self.records
+            .binary_search_by(|record| (record.code, record.digest.as_slice()).cmp(&key))
+            .ok()
+            .map(|i| self.records[i].offset)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // codes -> digest widths -> records, each level sorted
+        let mut codes: BTreeMap<u64, BTreeMap<u32, Vec<&IndexRecord>>> = BTreeMap::new();
+        for record in &self.records {
+            codes
+                .entry(record.code)
+                .or_default()
+                .entry(record.digest.len() as u32 + 8)
+                .or_default()
+                .push(record);
+        }
+
+        let mut bytes = MULTIHASH_INDEX_SORTED.encode_var_vec();
+        bytes.extend((codes.len() as i32).to_le_bytes());
+        for (code, buckets) in codes {
+            bytes.extend(code.to_le_bytes());
+            bytes.extend((buckets.len() as i32).to_le_bytes());
+            for (width, records) in buckets {
+                bytes.extend(width.to_le_bytes());
+                bytes.extend((records.len() as u64 * width as u64).to_le_bytes());
+                for record in records {
+                    bytes.extend(&record.digest);
+                    bytes.extend(record.offset.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let (codec, read) =
+            u64::decode_var(bytes).ok_or_else(|| anyhow!("Invalid car index codec"))?;
+        if codec != MULTIHASH_INDEX_SORTED {
+            bail!("Unsupported car index codec {codec:#x}");
+        }
+
+        let mut reader = &bytes[read..];
+        let mut records = Vec::new();
+        for _ in 0..read_i32(&mut reader)? {
+            let code = read_u64(&mut reader)?;
+            for _ in 0..read_i32(&mut reader)? {
+                let width = read_u32(&mut reader)? as usize;
+                let len = read_u64(&mut reader)? as usize;
+                if width <= 8 || len % width != 0 || len > reader.len() {
+                    bail!("Invalid car index bucket of width {width} and length {len}");
+                }
+                let (bucket, rest) = reader.split_at(len);
+                for entry in bucket.chunks_exact(width) {
+                    let (digest, offset) = entry.split_at(width - 8);
+                    records.push(IndexRecord {
+                        code,
+                        digest: digest.to_vec(),
+                        offset: u64::from_le_bytes(offset.try_into().unwrap()),
+                    });
+                }
+                reader = rest;
+            }
+        }
+        Ok(Self::new(records))
+    }
+}
+
+fn read_i32(reader: &mut &[u8]) -> Result<i32> {
+    let mut buf = [0; 4];
+    Read::read_exact(reader, &mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut buf = [0; 4];
+    Read::read_exact(reader, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64> {
+    let mut buf = [0; 8];
+    Read::read_exact(reader, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read the next length prefixed section of a CARv1 payload, `None` at its end.
+/// Returns the bytes read with the section, which is refused before being read
+/// if it is longer than `max_len`.
+async fn read_section<R>(reader: &mut R, max_len: usize) -> Result<Option<(u64, Vec<u8>)>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut prefix = Vec::new();
+    loop {
+        let mut byte = [0];
+        if reader.read(&mut byte).await? == 0 {
+            if prefix.is_empty() {
+                return Ok(None);
+            }
+            bail!("Unexpected end of the car payload");
+        }
+        prefix.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if prefix.len() == 10 {
+            bail!("Invalid car section length");
+        }
+    }
+    let (len, _) =
+        usize::decode_var(&prefix).ok_or_else(|| anyhow!("Invalid car section length"))?;
+    if len > max_len {
+        bail!("Car section of {len} bytes is larger than {max_len} bytes");
+    }
+    let mut section = vec![0; len];
+    reader.read_exact(&mut section).await?;
+    Ok(Some(((prefix.len() + len) as u64, section)))
+}
+
+/// Reads the blocks of a CARv1 payload one section at a time. A section
+/// larger than a block can be is refused before being read.
+pub struct CarBlockReader<R> {
+    reader: R,
+    /// Longest section accepted.
+    max_len: usize,
+    /// Offset of the next section from the start of the payload.
+    offset: u64,
+    pub header: CarHeader,
+}
+
+impl<R> CarBlockReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Read the header of a CARv1 payload holding blocks of at most
+    /// `max_block_size` bytes.
+    pub async fn new(mut reader: R, max_block_size: usize) -> Result<Self> {
+        let max_len = max_block_size + MAX_CID_SIZE;
+        let (offset, header) = read_section(&mut reader, max_len)
+            .await?
+            .ok_or_else(|| anyhow!("Empty car file"))?;
+        let header: CarHeader = from_slice(&header)?;
+        if header.version != 1 {
+            bail!("Unsupported car version {}", header.version);
+        }
+        Ok(Self {
+            reader,
+            max_len,
+            offset,
+            header,
+        })
+    }
+
+    /// Change the largest block accepted from the next section on.
+    pub fn set_max_block_size(&mut self, max_block_size: usize) {
+        self.max_len = max_block_size + MAX_CID_SIZE;
+    }
+
+    /// Offset from the start of the payload of the next block section,
+    /// the size of the payload once every block is read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The next block of the payload, `None` at its end.
+    pub async fn next_block(&mut self) -> Result<Option<(Cid, Vec<u8>)>> {
+        let (read, mut section) = match read_section(&mut self.reader, self.max_len).await? {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+        let mut cursor = std::io::Cursor::new(section.as_slice());
+        let cid = Cid::read_bytes(&mut cursor)?;
+        let cid_len = cursor.position() as usize;
+        section.drain(..cid_len);
+        self.offset = self
+            .offset
+            .checked_add(read)
+            .ok_or_else(|| anyhow!("Invalid car payload, the offsets overflow"))?;
+        Ok(Some((cid, section)))
+    }
+}
+
+/// Write a length prefixed section.
+pub(crate) async fn write_section<W>(writer: &mut W, parts: &[&[u8]]) -> Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    let prefix = len.encode_var_vec();
+    writer.write_all(&prefix).await?;
+    for part in parts {
+        writer.write_all(part).await?;
+    }
+    Ok((prefix.len() + len) as u64)
+}
+
+/// Write a CARv2 file with a `MultihashIndexSorted` index.
+///
+/// The size of the CARv1 payload is written in the header before the blocks,
+/// so it has to be known upfront, see [`UrsaStore::car_size`].
+pub async fn write_car_v2<W, St>(
+    roots: Vec<Cid>,
+    data_size: u64,
+    blocks: &mut St,
+    writer: &mut W,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    St: Stream<Item = (Cid, Vec<u8>)> + Unpin,
+{
+    let data_offset = (CARV2_PRAGMA.len() + CARV2_HEADER_SIZE) as u64;
+    let header = CarV2Header {
+        characteristics: [0; 16],
+        data_offset,
+        data_size,
+        index_offset: data_offset + data_size,
+    };
+    writer.write_all(&CARV2_PRAGMA).await?;
+    writer.write_all(&header.to_bytes()).await?;
+
+    let car_header = to_vec(&CarHeader { roots, version: 1 })?;
+    let mut offset = write_section(writer, &[&car_header]).await?;
+    let mut records = Vec::new();
+    while let Some((cid, data)) = blocks.next().await {
+        records.push(IndexRecord {
+            code: cid.hash().code(),
+            digest: cid.hash().digest().to_vec(),
+            offset,
+        });
+        offset += write_section(writer, &[&cid.to_bytes(), &data]).await?;
+    }
+    if offset != data_size {
+        bail!("The car payload is {offset} bytes but the header declares {data_size} bytes");
+    }
+
+    writer.write_all(&CarIndex::new(records).encode()).await?;
+    Ok(())
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Write the dag under a root cid as a car file with a single root.
+    /// Every block of the dag must be present.
+    pub async fn export_car<W>(
+        &self,
+        root_cid: &Cid,
+        version: CarVersion,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        match version {
+            CarVersion::V1 => self.write_car(root_cid, None, writer).await?,
+            CarVersion::V2 => {
+                let data_size = self.car_size(root_cid)?;
+                let blocks = self.dag_iter(root_cid);
+                // a block that fails to be read ends the payload early,
+                // which fails the payload size check
+                let mut blocks = stream::iter(blocks.map_while(|block| block.ok()));
+                write_car_v2(vec![*root_cid], data_size, &mut blocks, writer).await?;
+            }
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Stream the dag under a root cid as a CARv1 file, down to `max_depth`
+    /// links below the root if set. Fails on the first missing block.
+    pub async fn write_car<W>(
+        &self,
+        root_cid: &Cid,
+        max_depth: Option<usize>,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let header = to_vec(&CarHeader {
+            roots: vec![*root_cid],
+            version: 1,
+        })?;
+        write_section(writer, &[&header]).await?;
+        for block in self.dag_iter(root_cid).with_max_depth(max_depth) {
+            let (cid, data) = block?;
+            write_section(writer, &[&cid.to_bytes(), &data]).await?;
+        }
+        Ok(())
+    }
+
+    /// Import a CARv1 or CARv2 file into the blockstore.
+    /// Returns the roots of the car file.
+    ///
+    /// The blocks are stored as the payload is read, a CARv2 index is not used
+    /// to import them. It is only checked against the payload once every block
+    /// is written, and ignored with a warning if it does not match.
+    pub async fn import_car<R>(&self, reader: R) -> Result<Vec<Cid>>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        self.import_car_from(reader, "car").await
+    }
+
+    /// Read the blocks of a CARv1 payload, refusing the sections larger than
+    /// the blocks the store accepts.
+    pub async fn car_reader<R>(&self, reader: R) -> Result<CarBlockReader<R>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        CarBlockReader::new(reader, self.max_block_size()).await
+    }
+
+    /// Import a car file like [`UrsaStore::import_car`], labelling the
+    /// rejected blocks with `source`, e.g. `origin`.
+    pub async fn import_car_from<R>(&self, mut reader: R, source: &'static str) -> Result<Vec<Cid>>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let mut pragma = [0; CARV2_PRAGMA.len()];
+        let mut read = 0;
+        while read < pragma.len() {
+            match reader.read(&mut pragma[read..]).await? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        if pragma != CARV2_PRAGMA {
+            let mut reader = Cursor::new(pragma[..read].to_vec()).chain(reader);
+            return self.import_payload(&mut reader, source, None).await;
+        }
+
+        let mut header = [0; CARV2_HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+        let header = CarV2Header::from_bytes(&header);
+        let mut position = (CARV2_PRAGMA.len() + CARV2_HEADER_SIZE) as u64;
+
+        skip(&mut reader, header.data_offset.checked_sub(position)).await?;
+        let mut payload = (&mut reader).take(header.data_size);
+        let mut records = Vec::new();
+        let roots = self
+            .import_payload(&mut payload, source, Some(&mut records))
+            .await?;
+        if payload.limit() != 0 {
+            bail!("Unexpected end of the CARv2 file");
+        }
+        position = header.data_offset + header.data_size;
+
+        // the blocks are already stored, the index is only checked against
+        // the payload and never used to import
+        if header.index_offset != 0 {
+            skip(&mut reader, header.index_offset.checked_sub(position)).await?;
+            let mut bytes = Vec::new();
+            (&mut reader)
+                .take(MAX_CAR_INDEX_SIZE + 1)
+                .read_to_end(&mut bytes)
+                .await?;
+            let index = if bytes.len() as u64 > MAX_CAR_INDEX_SIZE {
+                Err(anyhow!(
+                    "The index is larger than {MAX_CAR_INDEX_SIZE} bytes"
+                ))
+            } else {
+                CarIndex::decode(&bytes)
+            };
+            match index {
+                Ok(index) if index == CarIndex::new(records) => {
+                    info!("Checked the car index of {} blocks", index.records.len());
+                }
+                Ok(_) => warn!("Ignoring the index of the car file, it does not match the payload"),
+                Err(e) => warn!("Ignoring the index of the car file: {e:?}"),
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Store the blocks of a CARv1 payload as they are read, a section larger
+    /// than a block can be is refused before being read.
+    /// Returns the roots of the payload, and pushes the offsets of its blocks
+    /// to `records` if set.
+    async fn import_payload<R>(
+        &self,
+        reader: &mut R,
+        source: &'static str,
+        mut records: Option<&mut Vec<IndexRecord>>,
+    ) -> Result<Vec<Cid>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut car = self.car_reader(reader).await?;
+        let mut offset = car.offset();
+        while let Some((cid, data)) = car.next_block().await? {
+            self.put_block(&cid, &data, source)?;
+            if let Some(records) = records.as_mut() {
+                records.push(IndexRecord {
+                    code: cid.hash().code(),
+                    digest: cid.hash().digest().to_vec(),
+                    offset,
+                });
+            }
+            offset = car.offset();
+        }
+        Ok(car.header.roots)
+    }
+}
+
+/// Discard the bytes up to the next section of a CARv2 file.
+async fn skip<R>(reader: &mut R, len: Option<u64>) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = len.ok_or_else(|| anyhow!("Invalid CARv2 header, overlapping sections"))?;
+    if copy(reader.take(len), &mut sink()).await? != len {
+        bail!("Unexpected end of the CARv2 file");
+    }
+    Ok(())
+}
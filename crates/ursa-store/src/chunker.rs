@@ -0,0 +1,197 @@
+//! File chunkers used by the UnixFS importer.
+
+use anyhow::{anyhow, bail};
+use libipld::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// Default size of fixed size chunks, 256KiB.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+/// Chunks larger than this would not fit in a block.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Size of the rolling hash window of the rabin chunker.
+const RABIN_WINDOW: usize = 64;
+const RABIN_PRIME: u64 = 0x3DA3_358B_4DC1_73;
+/// `RABIN_PRIME ^ RABIN_WINDOW`, used to roll the oldest byte out of the window.
+const RABIN_POW: u64 = {
+    let mut pow: u64 = 1;
+    let mut i = 0;
+    while i < RABIN_WINDOW {
+        pow = pow.wrapping_mul(RABIN_PRIME);
+        i += 1;
+    }
+    pow
+};
+
+/// Strategy used to split files into blocks.
+///
+/// Parsed from the same strings as go-ipfs: `size-{size}`, `rabin` or
+/// `rabin-{min}-{avg}-{max}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Chunker {
+    /// Chunks of a fixed size.
+    Fixed(usize),
+    /// Content defined chunks, cut where a Rabin-Karp rolling hash of the
+    /// last bytes matches, so unchanged parts of a file produce the same chunks.
+    Rabin { min: usize, avg: usize, max: usize },
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Chunker::Fixed(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+impl Chunker {
+    /// Rabin chunker with go-ipfs default sizes around an average chunk size.
+    pub fn rabin(avg: usize) -> Self {
+        Chunker::Rabin {
+            min: avg / 3,
+            avg,
+            max: avg + avg / 2,
+        }
+    }
+
+    /// Split a reader into chunks.
+    pub fn chunks<R: Read>(self, reader: R) -> Chunks<R> {
+        Chunks {
+            reader,
+            chunker: self,
+            pending: Vec::new(),
+        }
+    }
+
+    fn validate(self) -> Result<Self> {
+        match self {
+            Chunker::Fixed(size) if size == 0 || size > MAX_CHUNK_SIZE => {
+                bail!("Chunk size {size} must be between 1 and {MAX_CHUNK_SIZE}")
+            }
+            Chunker::Rabin { min, avg, max }
+                if min == 0 || min > avg || avg > max || max > MAX_CHUNK_SIZE =>
+            {
+                bail!("Invalid rabin chunk sizes {min}-{avg}-{max}")
+            }
+            chunker => Ok(chunker),
+        }
+    }
+}
+
+impl FromStr for Chunker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = |size: &str| {
+            size.parse::<usize>()
+                .map_err(|e| anyhow!("Invalid chunk size {size:?}: {e}"))
+        };
+        let chunker = match s.split('-').collect::<Vec<_>>().as_slice() {
+            ["size", size] => Chunker::Fixed(parse(size)?),
+            ["rabin"] => Chunker::rabin(DEFAULT_CHUNK_SIZE),
+            ["rabin", avg] => Chunker::rabin(parse(avg)?),
+            ["rabin", min, avg, max] => Chunker::Rabin {
+                min: parse(min)?,
+                avg: parse(avg)?,
+                max: parse(max)?,
+            },
+            _ => bail!("Unknown chunker {s:?}"),
+        };
+        chunker.validate()
+    }
+}
+
+impl TryFrom<String> for Chunker {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Chunker> for String {
+    fn from(chunker: Chunker) -> Self {
+        chunker.to_string()
+    }
+}
+
+impl fmt::Display for Chunker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chunker::Fixed(size) => write!(f, "size-{size}"),
+            Chunker::Rabin { min, avg, max } => write!(f, "rabin-{min}-{avg}-{max}"),
+        }
+    }
+}
+
+/// Iterator over the chunks of a reader.
+pub struct Chunks<R> {
+    reader: R,
+    chunker: Chunker,
+    /// Bytes read past the end of the last chunk.
+    pending: Vec<u8>,
+}
+
+impl<R: Read> Chunks<R> {
+    /// Read into the pending bytes until there are `len` of them or the reader ends.
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let mut read = self.pending.len();
+        self.pending.resize(len, 0);
+        while read < len {
+            match self.reader.read(&mut self.pending[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.pending.truncate(read);
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let max = match self.chunker {
+            Chunker::Fixed(size) => size,
+            Chunker::Rabin { max, .. } => max,
+        };
+        if let Err(e) = self.fill(max) {
+            return Some(Err(e));
+        }
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let cut = match self.chunker {
+            Chunker::Fixed(_) => self.pending.len(),
+            Chunker::Rabin { min, avg, .. } => rabin_boundary(&self.pending, min, avg),
+        };
+        let rest = self.pending.split_off(cut);
+        Some(Ok(std::mem::replace(&mut self.pending, rest)))
+    }
+}
+
+/// Length of the next content defined chunk of `data`, which holds at most one chunk.
+fn rabin_boundary(data: &[u8], min: usize, avg: usize) -> usize {
+    // a boundary is found on average every `avg` bytes
+    let bits = avg.next_power_of_two().trailing_zeros();
+    let mut hash: u64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        hash = hash
+            .wrapping_mul(RABIN_PRIME)
+            .wrapping_add(*byte as u64 + 1);
+        if i >= RABIN_WINDOW {
+            let oldest = data[i - RABIN_WINDOW] as u64 + 1;
+            hash = hash.wrapping_sub(oldest.wrapping_mul(RABIN_POW));
+        }
+        if i + 1 >= min && bits > 0 && hash >> (64 - bits) == 0 {
+            return i + 1;
+        }
+    }
+    data.len()
+}
@@ -0,0 +1,299 @@
+//! Capacity bounded storage for the [`UrsaStore`].
+//!
+//! When a storage quota is configured, [`UrsaStore::evict`] removes whole dags of
+//! unpinned roots, picked by the configured [`EvictionPolicy`], until the store fits
+//! in the quota again. Blocks shared with a remaining root are kept. Every root
+//! recorded with [`UrsaStore::add_root`] runs the eviction, whatever the path the
+//! content came from.
+//!
+//! The blocks of complete dags are counted once however many roots hold them:
+//! every block has the number of complete roots reaching it persisted under
+//! `ursa/refs/{cid}`, and the bytes of the counted blocks under [`COUNTED_KEY`].
+//! A partial dag is counted by its present blocks that no complete dag holds,
+//! until it is complete.
+//!
+//! Evicted roots are broadcast to the subscribers of
+//! [`UrsaStore::subscribe_evictions`], so the network and the index provider
+//! stop providing them.
+
+use db::Store;
+use fnv::{FnvHashMap, FnvHashSet};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{store::DefaultParams, Block, Cid, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::gc::GcStats;
+use crate::meta::RootMeta;
+use crate::UrsaStore;
+
+/// Prefix of the keys counting the complete roots that reach a block.
+pub const REFS_PREFIX: &str = "ursa/refs/";
+/// Key under which the bytes of the counted blocks are persisted.
+pub const COUNTED_KEY: &str = "ursa/counted";
+/// Evicted roots a subscriber can lag behind before missing some.
+pub const EVICTIONS_CAPACITY: usize = 1024;
+
+fn refs_key(cid: &Cid) -> String {
+    format!("{REFS_PREFIX}{cid}")
+}
+
+/// Read a persisted counter, 0 if missing.
+pub(crate) fn read_counter<S: Store>(db: &S, key: &str) -> Result<u64> {
+    match db.read(key)? {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.as_slice().try_into()?)),
+        None => Ok(0),
+    }
+}
+
+/// Persist a counter, removing it when it drops to 0.
+pub(crate) fn write_counter<S: Store>(db: &S, key: &str, value: u64) -> Result<()> {
+    if value == 0 {
+        db.delete(key)?;
+    } else {
+        db.write(key, value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Policy used to pick the roots to evict when the store is over its quota.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// Evict the least recently used roots first.
+    #[default]
+    Lru,
+    /// Evict the least frequently used roots first.
+    Lfu,
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Bound the store to `max_bytes`, evicting roots with the given policy.
+    /// A quota of 0 leaves the store unbounded.
+    pub fn with_quota(mut self, max_bytes: u64, policy: EvictionPolicy) -> Self {
+        self.max_storage_bytes = max_bytes;
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Bytes held by the known roots, blocks shared between complete dags
+    /// counted once.
+    pub fn used_bytes(&self) -> u64 {
+        self.used(&self.roots.read().unwrap())
+    }
+
+    pub(crate) fn used(&self, roots: &FnvHashMap<Cid, RootMeta>) -> u64 {
+        let partial = self.partial_bytes(roots).unwrap_or_else(|e| {
+            warn!("Failed to measure the partial dags, counting their sizes: {e:?}");
+            roots
+                .values()
+                .filter(|meta| !meta.counted)
+                .map(|meta| meta.size)
+                .sum()
+        });
+        self.counted_bytes.load(Ordering::Relaxed) + partial
+    }
+
+    /// Bytes of the blocks present under the roots that are not counted,
+    /// each block once, leaving out the blocks a complete dag holds.
+    fn partial_bytes(&self, roots: &FnvHashMap<Cid, RootMeta>) -> Result<u64> {
+        let mut current: Vec<Cid> = roots
+            .iter()
+            .filter(|(_, meta)| !meta.counted)
+            .map(|(cid, _)| *cid)
+            .collect();
+        let mut seen = FnvHashSet::default();
+        let mut bytes = 0;
+        while let Some(cid) = current.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            // the dag under a counted block is counted too
+            if read_counter(self.db.as_ref(), &refs_key(&cid))? > 0 {
+                continue;
+            }
+            if let Some(data) = self.db.get(&cid)? {
+                bytes += data.len() as u64;
+                let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                block.references(&mut current)?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Count the blocks of a complete dag once more.
+    pub(crate) fn count_dag(&self, root_cid: &Cid) -> Result<()> {
+        let mut current = vec![*root_cid];
+        let mut seen = FnvHashSet::default();
+        let mut added = 0;
+        while let Some(cid) = current.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            if let Some(data) = self.db.get(&cid)? {
+                let refs = read_counter(self.db.as_ref(), &refs_key(&cid))? + 1;
+                write_counter(self.db.as_ref(), &refs_key(&cid), refs)?;
+                if refs == 1 {
+                    added += data.len() as u64;
+                }
+                let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                block.references(&mut current)?;
+            }
+        }
+        let counted = self.counted_bytes.fetch_add(added, Ordering::Relaxed) + added;
+        write_counter(self.db.as_ref(), COUNTED_KEY, counted)
+    }
+
+    /// Forget removed roots and delete the blocks of their dags that no
+    /// remaining root reaches. The dags of unknown roots are removed too.
+    pub(crate) fn drop_roots(
+        &self,
+        roots: &mut FnvHashMap<Cid, RootMeta>,
+        removed: &[Cid],
+    ) -> Result<GcStats> {
+        let removed: Vec<(Cid, Option<RootMeta>)> = removed
+            .iter()
+            .map(|cid| (*cid, roots.remove(cid)))
+            .collect();
+        self.remove_roots(roots, removed.iter().map(|(cid, _)| cid))?;
+
+        // the blocks of partial dags are not counted, they are marked instead
+        let partial = self.mark(
+            roots
+                .iter()
+                .filter(|(_, meta)| !meta.counted)
+                .map(|(cid, _)| *cid),
+        )?;
+
+        let mut stats = GcStats::default();
+        let mut uncounted = 0;
+        for (root_cid, meta) in &removed {
+            let counted = meta.as_ref().map_or(false, |meta| meta.counted);
+            let mut current = vec![*root_cid];
+            let mut seen = FnvHashSet::default();
+            while let Some(cid) = current.pop() {
+                if !seen.insert(cid) {
+                    continue;
+                }
+                let data = match self.db.get(&cid)? {
+                    Some(data) => data,
+                    None => continue,
+                };
+                let len = data.len() as u64;
+                let mut refs = read_counter(self.db.as_ref(), &refs_key(&cid))?;
+                if counted {
+                    refs = refs.saturating_sub(1);
+                    write_counter(self.db.as_ref(), &refs_key(&cid), refs)?;
+                    if refs == 0 {
+                        uncounted += len;
+                    }
+                }
+                if refs > 0 || partial.contains(&cid) {
+                    // the rest of the dag is held by another root, a counted
+                    // dag still has to release its references to it
+                    if !counted {
+                        continue;
+                    }
+                } else {
+                    self.db.delete(cid.to_bytes())?;
+                    stats.blocks_removed += 1;
+                    stats.bytes_freed += len;
+                }
+                let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                block.references(&mut current)?;
+            }
+            if meta.is_some() {
+                stats.roots_removed += 1;
+            }
+        }
+
+        let previous = self
+            .counted_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |counted| {
+                Some(counted.saturating_sub(uncounted))
+            })
+            .unwrap_or_else(|counted| counted);
+        if previous < uncounted {
+            warn!("The counted bytes ({previous}) are below the bytes of the removed blocks ({uncounted})");
+        }
+        write_counter(
+            self.db.as_ref(),
+            COUNTED_KEY,
+            previous.saturating_sub(uncounted),
+        )?;
+        Ok(stats)
+    }
+
+    /// Receive the roots evicted from now on.
+    pub fn subscribe_evictions(&self) -> broadcast::Receiver<Cid> {
+        self.evictions.subscribe()
+    }
+
+    /// Bytes left in the quota, `None` if the store is unbounded.
+    pub fn free_bytes(&self) -> Option<u64> {
+        if self.max_storage_bytes == 0 {
+            return None;
+        }
+        Some(self.max_storage_bytes.saturating_sub(self.used_bytes()))
+    }
+
+    /// Evict unpinned roots until the store fits in its quota.
+    /// Returns the evicted roots.
+    pub fn evict(&self) -> Result<Vec<Cid>> {
+        if self.max_storage_bytes == 0 {
+            return Ok(Vec::new());
+        }
+
+        let pins = self.pins.read().unwrap();
+        let mut roots = self.roots.write().unwrap();
+        if self.used(&roots) <= self.max_storage_bytes {
+            return Ok(Vec::new());
+        }
+
+        let mut candidates: Vec<(Cid, u64, u64)> = roots
+            .iter()
+            .filter(|(cid, _)| !pins.contains(cid))
+            .map(|(cid, meta)| (*cid, meta.last_access, meta.hits))
+            .collect();
+        match self.eviction_policy {
+            EvictionPolicy::Lru => candidates.sort_by_key(|(_, last_access, _)| *last_access),
+            EvictionPolicy::Lfu => {
+                candidates.sort_by_key(|(_, last_access, hits)| (*hits, *last_access))
+            }
+        }
+
+        // roots are evicted one at a time, as the bytes freed by a root
+        // depend on the blocks it shares with the others
+        let mut evicted = Vec::new();
+        let mut stats = GcStats::default();
+        for (cid, ..) in candidates {
+            if self.used(&roots) <= self.max_storage_bytes {
+                break;
+            }
+            let removed = self.drop_roots(&mut roots, &[cid])?;
+            stats.roots_removed += removed.roots_removed;
+            stats.blocks_removed += removed.blocks_removed;
+            stats.bytes_freed += removed.bytes_freed;
+            // no subscriber is not an error
+            let _ = self.evictions.send(cid);
+            evicted.push(cid);
+        }
+
+        let used = self.used(&roots);
+        if used > self.max_storage_bytes {
+            warn!(
+                "Pinned content ({used} bytes) exceeds the storage quota of {} bytes",
+                self.max_storage_bytes
+            );
+        }
+        if !evicted.is_empty() {
+            info!("Evicted {} roots: {stats:?}", evicted.len());
+        }
+        Ok(evicted)
+    }
+}
@@ -0,0 +1,301 @@
+//! Pinning and garbage collection for the [`UrsaStore`].
+//!
+//! The store keeps two persisted sets of root cids next to the blocks:
+//!
+//! - `roots`: every root the node has stored content for (uploads, bitswap, graphsync, origin).
+//! - `pins`: the roots that must never be garbage collected.
+//!
+//! [`UrsaStore::gc`] marks every block reachable from a pinned root and sweeps the
+//! whole blockstore, deleting every block that was not marked. Eviction marks
+//! the other roots and only sweeps the dags it removes.
+//!
+//! Content being written is not reachable from a pin until it is recorded, so
+//! writers hold an [`IngestGuard`] meanwhile and garbage collection refuses to
+//! start while one is alive. The blocks written while a collection runs are
+//! not swept either.
+
+use anyhow::bail;
+use db::Store;
+use fnv::{FnvHashMap, FnvHashSet};
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::{from_slice, to_vec};
+use libipld::{store::DefaultParams, Block, Cid, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::{atomic::Ordering, Arc, Mutex};
+use tracing::info;
+
+use crate::eviction::{write_counter, COUNTED_KEY, REFS_PREFIX};
+use crate::meta::{delete_meta, ContentSource, RootMeta};
+use crate::{ListKeys, UrsaStore};
+
+/// Key under which the pinned root cids are persisted.
+pub const PINS_KEY: &str = "ursa/pins";
+/// Key under which the known root cids are persisted.
+pub const ROOTS_KEY: &str = "ursa/roots";
+
+/// Summary of a garbage collection run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcStats {
+    /// Number of unpinned roots that were collected.
+    pub roots_removed: usize,
+    /// Number of blocks deleted from the blockstore.
+    pub blocks_removed: usize,
+    /// Total size of the deleted blocks in bytes.
+    pub bytes_freed: u64,
+}
+
+/// The writes in progress, shared by the store and its [`IngestGuard`]s.
+#[derive(Debug, Default)]
+pub(crate) struct Ingests {
+    /// Number of live guards.
+    pub(crate) active: usize,
+    /// Blocks written since the running garbage collection started, `None`
+    /// if none is running.
+    pub(crate) written: Option<FnvHashSet<Cid>>,
+}
+
+/// Keeps garbage collection from running while content is written to the
+/// store, until the content is recorded as a root or pinned. The write ends
+/// when the guard is dropped.
+#[derive(Debug)]
+pub struct IngestGuard {
+    ingests: Arc<Mutex<Ingests>>,
+}
+
+impl Drop for IngestGuard {
+    fn drop(&mut self) {
+        self.ingests.lock().unwrap().active -= 1;
+    }
+}
+
+/// Read a persisted set of cids from the store.
+pub(crate) fn read_cids<S: Store>(db: &S, key: &str) -> Result<FnvHashSet<Cid>> {
+    match db.read(key)? {
+        Some(bytes) => {
+            let cids: Vec<Cid> = from_slice(&bytes)?;
+            Ok(cids.into_iter().collect())
+        }
+        None => Ok(FnvHashSet::default()),
+    }
+}
+
+/// Persist a set of cids in the store.
+pub(crate) fn write_cids<'a, S: Store>(
+    db: &S,
+    key: &str,
+    cids: impl Iterator<Item = &'a Cid>,
+) -> Result<()> {
+    let cids: Vec<&Cid> = cids.collect();
+    db.write(key, to_vec(&cids)?)?;
+    Ok(())
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Start writing content, garbage collection is refused until the guard is dropped.
+    pub fn ingest(&self) -> IngestGuard {
+        self.ingests.lock().unwrap().active += 1;
+        IngestGuard {
+            ingests: Arc::clone(&self.ingests),
+        }
+    }
+
+    /// Keep a block written while garbage collection runs from being swept.
+    pub(crate) fn record_write(&self, cid: &Cid) {
+        if let Some(written) = self.ingests.lock().unwrap().written.as_mut() {
+            written.insert(*cid);
+        }
+    }
+
+    /// Pin a root cid, protecting its dag from garbage collection.
+    pub fn pin(&self, root_cid: &Cid) -> Result<()> {
+        {
+            let mut pins = self.pins.write().unwrap();
+            if pins.insert(*root_cid) {
+                write_cids(self.db.as_ref(), PINS_KEY, pins.iter())?;
+            }
+        }
+        self.add_root(root_cid, ContentSource::Upload)
+    }
+
+    /// Unpin a root cid, its dag will be removed on the next garbage collection.
+    /// Returns `false` if the root was not pinned.
+    pub fn unpin(&self, root_cid: &Cid) -> Result<bool> {
+        let mut pins = self.pins.write().unwrap();
+        if pins.remove(root_cid) {
+            write_cids(self.db.as_ref(), PINS_KEY, pins.iter())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Check if a root cid is pinned
+    pub fn is_pinned(&self, root_cid: &Cid) -> bool {
+        self.pins.read().unwrap().contains(root_cid)
+    }
+
+    /// Return all the pinned root cids
+    pub fn pins(&self) -> Vec<Cid> {
+        self.pins.read().unwrap().iter().copied().collect()
+    }
+
+    /// Return all the known root cids
+    pub fn roots(&self) -> Vec<Cid> {
+        self.roots.read().unwrap().keys().copied().collect()
+    }
+
+    /// Remove a root, pinned or not, and the blocks of its dag that are not
+    /// shared with another known root. The blocks of a root that is not known,
+    /// like a dag fetched but not recorded yet, are removed the same way.
+    pub fn remove(&self, root_cid: &Cid) -> Result<GcStats> {
+        self.unpin(root_cid)?;
+        let mut roots = self.roots.write().unwrap();
+        let stats = self.drop_roots(&mut roots, &[*root_cid])?;
+        info!("Removed the dag with the root {root_cid}: {stats:?}");
+        Ok(stats)
+    }
+
+    /// Delete the metadata of roots taken out of `roots`, and persist the remaining ones.
+    pub(crate) fn remove_roots<'a>(
+        &self,
+        roots: &FnvHashMap<Cid, RootMeta>,
+        removed: impl Iterator<Item = &'a Cid>,
+    ) -> Result<()> {
+        for root_cid in removed {
+            delete_meta(self.db.as_ref(), root_cid)?;
+        }
+        write_cids(self.db.as_ref(), ROOTS_KEY, roots.keys())
+    }
+
+    /// Return the cids of all blocks reachable from the given roots.
+    /// Missing blocks are skipped.
+    pub(crate) fn mark(&self, roots: impl Iterator<Item = Cid>) -> Result<FnvHashSet<Cid>> {
+        let mut current: Vec<Cid> = roots.collect();
+        let mut marked = FnvHashSet::default();
+
+        while let Some(cid) = current.pop() {
+            if marked.contains(&cid) {
+                continue;
+            }
+            if let Some(data) = self.db.get(&cid)? {
+                let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                block.references(&mut current)?;
+                marked.insert(cid);
+            }
+        }
+        Ok(marked)
+    }
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+{
+    /// Call `f` with the cid of every block of the blockstore, reachable from
+    /// a known root or not. Keys that are not cids, like the metadata of the
+    /// store, are skipped.
+    pub fn for_each_block(&self, mut f: impl FnMut(Cid) -> Result<()>) -> Result<()> {
+        self.db.for_each_key(&mut |key| match Cid::try_from(key) {
+            Ok(cid) if cid.to_bytes() == key => f(cid),
+            _ => Ok(()),
+        })
+    }
+
+    /// Mark and sweep garbage collection.
+    ///
+    /// Every block reachable from a pinned root is marked, then every block of
+    /// the blockstore that was not marked is deleted: the dags of the unpinned
+    /// roots, and the blocks no known root reaches, like partial downloads or
+    /// blocks fetched alone over bitswap.
+    ///
+    /// Fails while an [`IngestGuard`] is alive.
+    pub fn gc(&self) -> Result<GcStats> {
+        {
+            let mut ingests = self.ingests.lock().unwrap();
+            if ingests.active > 0 {
+                bail!(
+                    "Garbage collection refused, {} writes are in progress",
+                    ingests.active
+                );
+            }
+            ingests.written = Some(FnvHashSet::default());
+        }
+        let stats = self.collect_garbage();
+        self.ingests.lock().unwrap().written = None;
+        stats
+    }
+
+    /// Garbage collection once the writes are recorded, see [`UrsaStore::gc`].
+    pub(crate) fn collect_garbage(&self) -> Result<GcStats> {
+        let pins = self.pins.read().unwrap();
+        let mut roots = self.roots.write().unwrap();
+        info!(
+            "Starting garbage collection with {} pinned roots",
+            pins.len()
+        );
+
+        let marked = self.mark(pins.iter().copied())?;
+        let mut garbage = Vec::new();
+        self.for_each_block(|cid| {
+            if !marked.contains(&cid) {
+                garbage.push(cid);
+            }
+            Ok(())
+        })?;
+
+        let mut stats = GcStats::default();
+        for cid in garbage {
+            // blocks written since the collection started are kept, the ones
+            // written once deleted are present again
+            let ingests = self.ingests.lock().unwrap();
+            if matches!(&ingests.written, Some(written) if written.contains(&cid)) {
+                continue;
+            }
+            if let Some(data) = self.db.get(&cid)? {
+                stats.bytes_freed += data.len() as u64;
+            }
+            self.db.delete(cid.to_bytes())?;
+            stats.blocks_removed += 1;
+        }
+
+        let unpinned: Vec<Cid> = roots
+            .keys()
+            .filter(|cid| !pins.contains(cid))
+            .copied()
+            .collect();
+        for cid in &unpinned {
+            roots.remove(cid);
+        }
+        stats.roots_removed = unpinned.len();
+        self.remove_roots(&roots, unpinned.iter())?;
+        self.recount(&roots)?;
+        info!("Garbage collection done: {stats:?}");
+        Ok(stats)
+    }
+
+    /// Count the blocks of the complete roots again from scratch, dropping
+    /// the counts of the blocks deleted behind the accounting's back.
+    pub(crate) fn recount(&self, roots: &FnvHashMap<Cid, RootMeta>) -> Result<()> {
+        let mut counts = Vec::new();
+        self.db.for_each_key(&mut |key| {
+            if key.starts_with(REFS_PREFIX.as_bytes()) {
+                counts.push(key.to_vec());
+            }
+            Ok(())
+        })?;
+        for key in counts {
+            self.db.delete(key)?;
+        }
+        self.counted_bytes.store(0, Ordering::Relaxed);
+        write_counter(self.db.as_ref(), COUNTED_KEY, 0)?;
+        for (cid, meta) in roots {
+            if meta.counted {
+                self.count_dag(cid)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,25 @@
+mod backend;
+mod car;
+mod chunker;
+mod eviction;
+mod gc;
+mod meta;
+mod snapshot;
 mod store;
+mod traversal;
+mod unixfs;
+mod verify;
 
+pub use self::backend::*;
+pub use self::car::*;
+pub use self::chunker::*;
+pub use self::eviction::*;
+pub use self::gc::*;
+pub use self::meta::*;
+pub use self::snapshot::*;
 pub use self::store::*;
+pub use self::traversal::*;
+pub use self::unixfs::*;
+pub use self::verify::*;
 #[cfg(test)]
 mod tests;
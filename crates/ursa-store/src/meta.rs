@@ -0,0 +1,255 @@
+//! Per-root content metadata for the [`UrsaStore`].
+//!
+//! Every known root has a [`RootMeta`] persisted under `ursa/meta/{cid}`, so the
+//! size of a dag and its usage are known without walking it. The list of roots
+//! itself is persisted under [`ROOTS_KEY`]. Accesses only update the metadata
+//! in memory until [`UrsaStore::flush_accesses`] persists them, which
+//! [`UrsaStore::flush_accesses_every`] does on a timer.
+
+use db::Store;
+use fnv::FnvHashSet;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_car::CarHeader;
+use fvm_ipld_encoding::{from_slice, to_vec};
+use integer_encoding::VarInt;
+use libipld::{store::DefaultParams, Block, Cid, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::warn;
+
+use crate::gc::{write_cids, ROOTS_KEY};
+use crate::UrsaStore;
+
+/// Prefix of the keys under which root metadata is persisted.
+pub const META_PREFIX: &str = "ursa/meta/";
+
+/// How the content of a root reached the store.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentSource {
+    /// Uploaded to this node.
+    Upload,
+    /// Fetched from peers over bitswap.
+    Bitswap,
+    /// Pushed by a peer over graphsync.
+    Graphsync,
+    /// Streamed from a peer as a car file.
+    Car,
+    /// Fetched from the origin gateway.
+    Origin,
+    /// Held before metadata was recorded.
+    #[default]
+    Unknown,
+}
+
+/// Metadata of a root held by the store.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootMeta {
+    /// Sum of the block sizes of the dag in bytes.
+    pub size: u64,
+    /// Size of the dag as a CARv1 file in bytes.
+    pub car_size: u64,
+    /// Number of blocks of the dag.
+    pub blocks: u64,
+    /// Whether every block of the dag was present when the sizes were computed.
+    pub complete: bool,
+    /// Unix time in milliseconds the root was added.
+    pub inserted_at: u64,
+    /// Unix time in milliseconds of the last access.
+    pub last_access: u64,
+    /// Number of times the root was accessed.
+    pub hits: u64,
+    pub source: ContentSource,
+    /// Whether the blocks of the dag are counted in the store usage, done
+    /// once the dag is complete so blocks shared with other roots count once.
+    #[serde(default)]
+    pub counted: bool,
+}
+
+fn meta_key(cid: &Cid) -> String {
+    format!("{META_PREFIX}{cid}")
+}
+
+/// Read the persisted metadata of a root.
+pub(crate) fn read_meta<S: Store>(db: &S, cid: &Cid) -> Result<Option<RootMeta>> {
+    match db.read(meta_key(cid))? {
+        Some(bytes) => Ok(Some(from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Persist the metadata of a root.
+pub(crate) fn write_meta<S: Store>(db: &S, cid: &Cid, meta: &RootMeta) -> Result<()> {
+    db.write(meta_key(cid), to_vec(meta)?)?;
+    Ok(())
+}
+
+/// Remove the persisted metadata of a root.
+pub(crate) fn delete_meta<S: Store>(db: &S, cid: &Cid) -> Result<()> {
+    db.delete(meta_key(cid))?;
+    Ok(())
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Record a root cid whose dag is held by the store, computing its sizes.
+    /// The sizes of a known root are refreshed, its usage and source are kept.
+    /// Only known roots are considered for collection.
+    ///
+    /// Roots are evicted afterwards if the store is over its quota.
+    pub fn add_root(&self, root_cid: &Cid, source: ContentSource) -> Result<()> {
+        self.record_root(root_cid, source)?;
+        self.evict()?;
+        Ok(())
+    }
+
+    fn record_root(&self, root_cid: &Cid, source: ContentSource) -> Result<()> {
+        let stats = self.dag_meta(root_cid)?;
+        let mut roots = self.roots.write().unwrap();
+        let new = !roots.contains_key(root_cid);
+        let meta = roots.entry(*root_cid).or_insert_with(|| {
+            let now = self.tick();
+            RootMeta {
+                inserted_at: now,
+                last_access: now,
+                source,
+                ..Default::default()
+            }
+        });
+        meta.size = stats.size;
+        meta.car_size = stats.car_size;
+        meta.blocks = stats.blocks;
+        meta.complete = stats.complete;
+        if meta.complete && !meta.counted {
+            self.count_dag(root_cid)?;
+            meta.counted = true;
+        }
+        let meta = meta.clone();
+
+        if new {
+            write_cids(self.db.as_ref(), ROOTS_KEY, roots.keys())?;
+        }
+        write_meta(self.db.as_ref(), root_cid, &meta)
+    }
+
+    /// Recompute the sizes of a known root, e.g. after missing blocks were fetched.
+    pub fn refresh_root(&self, root_cid: &Cid) -> Result<()> {
+        if self.roots.read().unwrap().contains_key(root_cid) {
+            self.add_root(root_cid, ContentSource::Unknown)?;
+        }
+        Ok(())
+    }
+
+    /// Metadata of a known root
+    pub fn root_meta(&self, root_cid: &Cid) -> Option<RootMeta> {
+        self.roots.read().unwrap().get(root_cid).cloned()
+    }
+
+    /// Metadata of all the known roots
+    pub fn roots_meta(&self) -> Vec<(Cid, RootMeta)> {
+        self.roots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(cid, meta)| (*cid, meta.clone()))
+            .collect()
+    }
+
+    /// Record an access to a root cid, in memory only.
+    pub fn touch(&self, root_cid: &Cid) {
+        let mut roots = self.roots.write().unwrap();
+        if let Some(meta) = roots.get_mut(root_cid) {
+            meta.hits += 1;
+            meta.last_access = self.tick();
+            self.touched.lock().unwrap().insert(*root_cid);
+        }
+    }
+
+    /// Persist the metadata of the roots accessed since the last call.
+    pub fn flush_accesses(&self) -> Result<()> {
+        let touched = std::mem::take(&mut *self.touched.lock().unwrap());
+        let roots = self.roots.read().unwrap();
+        // roots removed since their access are skipped
+        for cid in touched.iter().filter(|cid| roots.contains_key(cid)) {
+            write_meta(self.db.as_ref(), cid, &roots[cid])?;
+        }
+        Ok(())
+    }
+
+    /// Persist the accesses every `period` on a task, which ends once the store is dropped.
+    pub fn flush_accesses_every(self: &Arc<Self>, period: Duration) -> JoinHandle<()> {
+        let store = Arc::downgrade(self);
+        task::spawn(async move {
+            let mut ticks = interval(period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes immediately
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let store = match store.upgrade() {
+                    Some(store) => store,
+                    None => break,
+                };
+                match task::spawn_blocking(move || store.flush_accesses()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Failed to save the content accesses: {e:?}"),
+                    Err(e) => warn!("Failed to save the content accesses: {e:?}"),
+                }
+            }
+        })
+    }
+
+    /// Walk a dag to compute its sizes, missing blocks are skipped.
+    pub(crate) fn dag_meta(&self, root_cid: &Cid) -> Result<RootMeta> {
+        let header = to_vec(&CarHeader {
+            roots: vec![*root_cid],
+            version: 1,
+        })?;
+        let mut meta = RootMeta {
+            car_size: (header.len().encode_var_vec().len() + header.len()) as u64,
+            complete: true,
+            ..Default::default()
+        };
+
+        let mut current = vec![*root_cid];
+        let mut refs = FnvHashSet::default();
+        while let Some(cid) = current.pop() {
+            if !refs.insert(cid) {
+                continue;
+            }
+            match self.db.get(&cid)? {
+                Some(data) => {
+                    let section = cid.to_bytes().len() + data.len();
+                    meta.car_size += (section.encode_var_vec().len() + section) as u64;
+                    meta.size += data.len() as u64;
+                    meta.blocks += 1;
+                    let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                    block.references(&mut current)?;
+                }
+                None => meta.complete = false,
+            }
+        }
+        Ok(meta)
+    }
+
+    /// Unix time in milliseconds, strictly increasing between calls
+    /// so accesses are ordered even within the same millisecond.
+    pub(crate) fn tick(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or_default();
+        let last = self
+            .clock
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap();
+        now.max(last + 1)
+    }
+}
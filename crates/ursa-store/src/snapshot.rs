@@ -0,0 +1,254 @@
+//! Full-store snapshots.
+//!
+//! A snapshot is a CARv1 file whose single root is a DAG-CBOR [`SnapshotManifest`].
+//! The manifest block is followed by every block of the content store, the
+//! ones no root reaches included, then by the blocks of the index provider
+//! advertisement chain, so a node can be moved without copying its database files.
+
+use anyhow::{anyhow, bail};
+use db::Store;
+use fnv::FnvHashSet;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_car::CarHeader;
+use fvm_ipld_encoding::{from_slice, to_vec, DAG_CBOR};
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use tracing::info;
+
+use crate::car::{write_section, CarBlockReader};
+use crate::gc::{write_cids, PINS_KEY};
+use crate::meta::{write_meta, RootMeta};
+use crate::verify::verify_block;
+use crate::{ListKeys, UrsaStore};
+
+/// Version of the snapshot format.
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+/// Largest manifest accepted, unlike blocks it grows with the number of roots.
+pub const MAX_MANIFEST_SIZE: usize = 64 << 20;
+
+/// A root of the content store in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRoot {
+    pub cid: Cid,
+    pub pinned: bool,
+    pub meta: RootMeta,
+}
+
+/// First block of a snapshot, describing the blocks following it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u64,
+    /// Unix time in milliseconds the snapshot was taken.
+    pub created_at: u64,
+    pub roots: Vec<SnapshotRoot>,
+    /// Number of content store blocks following the manifest.
+    pub content_blocks: u64,
+    /// Head of the advertisement chain of the index provider.
+    pub provider_head: Option<Cid>,
+    /// Number of provider store blocks following the content blocks.
+    pub provider_blocks: u64,
+}
+
+/// Write a snapshot of a content store, and of the advertisement chain
+/// under `provider_head` in an index provider store.
+pub async fn export_snapshot<S, P, W>(
+    store: &UrsaStore<S>,
+    provider_store: &UrsaStore<P>,
+    provider_head: Option<Cid>,
+    writer: &mut W,
+) -> Result<SnapshotManifest>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+    P: Blockstore + Store + Send + Sync + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let mut roots: Vec<SnapshotRoot> = store
+        .roots_meta()
+        .into_iter()
+        .map(|(cid, meta)| SnapshotRoot {
+            cid,
+            pinned: store.is_pinned(&cid),
+            meta,
+        })
+        .collect();
+    roots.sort_by_key(|root| root.meta.inserted_at);
+    let mut content = Vec::new();
+    store.for_each_block(|cid| {
+        content.push(cid);
+        Ok(())
+    })?;
+    let provider = provider_store.present_blocks(provider_head)?;
+
+    let manifest = SnapshotManifest {
+        version: SNAPSHOT_VERSION,
+        created_at: store.tick(),
+        roots,
+        content_blocks: content.len() as u64,
+        provider_head,
+        provider_blocks: provider.len() as u64,
+    };
+    let manifest_bytes = to_vec(&manifest)?;
+    if manifest_bytes.len() > MAX_MANIFEST_SIZE {
+        bail!(
+            "The snapshot manifest of {} roots is larger than {MAX_MANIFEST_SIZE} bytes",
+            manifest.roots.len()
+        );
+    }
+    let manifest_cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&manifest_bytes));
+    info!(
+        "Exporting a snapshot of {} roots, {} blocks and {} provider blocks",
+        manifest.roots.len(),
+        manifest.content_blocks,
+        manifest.provider_blocks
+    );
+
+    let header = to_vec(&CarHeader {
+        roots: vec![manifest_cid],
+        version: 1,
+    })?;
+    write_section(writer, &[&header]).await?;
+    write_section(writer, &[&manifest_cid.to_bytes(), &manifest_bytes]).await?;
+    for cid in content {
+        let data = store
+            .db
+            .get(&cid)?
+            .ok_or_else(|| anyhow!("The block {cid} was removed during the export"))?;
+        write_section(writer, &[&cid.to_bytes(), &data]).await?;
+    }
+    for cid in provider {
+        let data = provider_store
+            .db
+            .get(&cid)?
+            .ok_or_else(|| anyhow!("The provider block {cid} was removed during the export"))?;
+        write_section(writer, &[&cid.to_bytes(), &data]).await?;
+    }
+    writer.flush().await?;
+    Ok(manifest)
+}
+
+/// Import a snapshot into an empty content store and index provider store.
+///
+/// Every block is verified and refused past the maximum block size of its
+/// store. The roots, pins and metadata of the content store are restored;
+/// persisting the returned provider head is left to the caller.
+pub async fn import_snapshot<S, P, R>(
+    store: &UrsaStore<S>,
+    provider_store: &UrsaStore<P>,
+    reader: R,
+) -> Result<SnapshotManifest>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+    P: Blockstore + Store + Send + Sync + 'static,
+    R: AsyncRead + Send + Unpin,
+{
+    if !store.roots().is_empty() {
+        bail!("Snapshots can only be imported into an empty store");
+    }
+
+    let mut car = CarBlockReader::new(reader, MAX_MANIFEST_SIZE).await?;
+    let manifest_cid = match car.header.roots.as_slice() {
+        [cid] => *cid,
+        roots => bail!("A snapshot has a single root, found {}", roots.len()),
+    };
+    let (cid, data) = car
+        .next_block()
+        .await?
+        .ok_or_else(|| anyhow!("The snapshot manifest is missing"))?;
+    if cid != manifest_cid {
+        bail!("The first block of a snapshot must be its manifest");
+    }
+    verify_block(&cid, &data)?;
+    let manifest: SnapshotManifest = from_slice(&data)?;
+    if manifest.version != SNAPSHOT_VERSION {
+        bail!("Unsupported snapshot version {}", manifest.version);
+    }
+
+    car.set_max_block_size(store.max_block_size());
+    for _ in 0..manifest.content_blocks {
+        let (cid, data) = car
+            .next_block()
+            .await?
+            .ok_or_else(|| anyhow!("The snapshot is missing content blocks"))?;
+        store.put_block(&cid, &data, "snapshot")?;
+    }
+    car.set_max_block_size(provider_store.max_block_size());
+    for _ in 0..manifest.provider_blocks {
+        let (cid, data) = car
+            .next_block()
+            .await?
+            .ok_or_else(|| anyhow!("The snapshot is missing provider blocks"))?;
+        provider_store.put_block(&cid, &data, "snapshot")?;
+    }
+    if car.next_block().await?.is_some() {
+        bail!("The snapshot has more blocks than its manifest declares");
+    }
+
+    store.restore_roots(&manifest.roots)?;
+    info!(
+        "Imported a snapshot of {} roots, {} blocks and {} provider blocks",
+        manifest.roots.len(),
+        manifest.content_blocks,
+        manifest.provider_blocks
+    );
+    Ok(manifest)
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Blocks of the dags under the given roots present in the store, without
+    /// duplicates, in depth-first order of each dag.
+    fn present_blocks(&self, roots: impl IntoIterator<Item = Cid>) -> Result<Vec<Cid>> {
+        let mut seen = FnvHashSet::default();
+        let mut blocks = Vec::new();
+        for root_cid in roots {
+            for cid in self.dag_status(&root_cid)?.present {
+                if seen.insert(cid) {
+                    blocks.push(cid);
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Register the roots of a snapshot, keeping their recorded usage.
+    ///
+    /// The pins are restored first, so adding the roots never evicts a pinned one.
+    fn restore_roots(&self, snapshot_roots: &[SnapshotRoot]) -> Result<()> {
+        {
+            let mut pins = self.pins.write().unwrap();
+            pins.extend(
+                snapshot_roots
+                    .iter()
+                    .filter(|root| root.pinned)
+                    .map(|root| root.cid),
+            );
+            write_cids(self.db.as_ref(), PINS_KEY, pins.iter())?;
+        }
+        for root in snapshot_roots {
+            self.add_root(&root.cid, root.meta.source)?;
+        }
+
+        let mut roots = self.roots.write().unwrap();
+        for root in snapshot_roots {
+            if let Some(meta) = roots.get_mut(&root.cid) {
+                meta.inserted_at = root.meta.inserted_at;
+                meta.last_access = root.meta.last_access;
+                meta.hits = root.meta.hits;
+                write_meta(self.db.as_ref(), &root.cid, meta)?;
+            }
+        }
+
+        let last_access = roots.values().map(|meta| meta.last_access).max();
+        self.clock
+            .fetch_max(last_access.unwrap_or_default(), Ordering::Relaxed);
+        Ok(())
+    }
+}
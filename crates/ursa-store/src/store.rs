@@ -1,10 +1,8 @@
 use anyhow::anyhow;
 use db::Store;
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use fvm_ipld_blockstore::Blockstore;
-use fvm_ipld_car::CarHeader;
 use fvm_ipld_encoding::{de::DeserializeOwned, from_slice, ser::Serialize, to_vec, DAG_CBOR};
-use integer_encoding::VarInt;
 use ipld_traversal::blockstore::Blockstore as GSBlockstore;
 use libipld::{
     cid,
@@ -13,11 +11,39 @@ use libipld::{
     Block, Cid, Result,
 };
 use libp2p_bitswap::BitswapStore;
-use std::sync::Arc;
+use std::sync::{atomic::AtomicU64, Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
+
+use crate::eviction::{read_counter, EvictionPolicy, COUNTED_KEY, EVICTIONS_CAPACITY};
+use crate::gc::{read_cids, Ingests, PINS_KEY, ROOTS_KEY};
+use crate::meta::{read_meta, ContentSource, RootMeta};
+use crate::verify::{DagLimits, DEFAULT_MAX_BLOCK_SIZE};
 
 #[derive(Debug)]
 pub struct UrsaStore<S> {
     pub db: Arc<S>,
+    /// Root cids protected from garbage collection.
+    pub(crate) pins: RwLock<FnvHashSet<Cid>>,
+    /// Root cids of all the dags held by the store, with their metadata.
+    pub(crate) roots: RwLock<FnvHashMap<Cid, RootMeta>>,
+    /// Storage quota in bytes, 0 if unbounded.
+    pub(crate) max_storage_bytes: u64,
+    /// Policy used to evict roots when over the quota.
+    pub(crate) eviction_policy: EvictionPolicy,
+    /// Last timestamp given to a root access.
+    pub(crate) clock: AtomicU64,
+    /// Roots accessed since their metadata was last persisted.
+    pub(crate) touched: Mutex<FnvHashSet<Cid>>,
+    /// Bytes of the blocks of the complete roots, each block counted once.
+    pub(crate) counted_bytes: AtomicU64,
+    /// Broadcasts the evicted roots.
+    pub(crate) evictions: broadcast::Sender<Cid>,
+    /// Maximum size of a block written to the store.
+    pub(crate) max_block_size: usize,
+    /// Writes in progress, garbage collection does not run meanwhile.
+    pub(crate) ingests: Arc<Mutex<Ingests>>,
+    /// Limits on the bytes written for the dags being fetched.
+    pub(crate) dag_limits: Arc<Mutex<DagLimits>>,
 }
 
 impl<S> UrsaStore<S>
@@ -25,7 +51,51 @@ where
     S: Blockstore + Store + Send + Sync + 'static,
 {
     pub fn new(db: Arc<S>) -> Self {
-        Self { db }
+        let pins =
+            read_cids(db.as_ref(), PINS_KEY).expect("reading pins from store should not fail");
+        let mut roots = FnvHashMap::default();
+        let mut unindexed = Vec::new();
+        for cid in
+            read_cids(db.as_ref(), ROOTS_KEY).expect("reading roots from store should not fail")
+        {
+            match read_meta(db.as_ref(), &cid).expect("reading root metadata should not fail") {
+                Some(meta) => {
+                    roots.insert(cid, meta);
+                }
+                None => unindexed.push(cid),
+            }
+        }
+        let clock = roots.values().map(|meta| meta.last_access).max();
+        // complete roots recorded before their blocks were counted
+        let uncounted: Vec<Cid> = roots
+            .iter()
+            .filter(|(_, meta)| meta.complete && !meta.counted)
+            .map(|(cid, _)| *cid)
+            .collect();
+        let counted_bytes = read_counter(db.as_ref(), COUNTED_KEY)
+            .expect("reading usage from store should not fail");
+
+        let store = Self {
+            db,
+            pins: RwLock::new(pins),
+            roots: RwLock::new(roots),
+            max_storage_bytes: 0,
+            eviction_policy: EvictionPolicy::default(),
+            clock: AtomicU64::new(clock.unwrap_or_default()),
+            touched: Mutex::default(),
+            counted_bytes: AtomicU64::new(counted_bytes),
+            evictions: broadcast::channel(EVICTIONS_CAPACITY).0,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            ingests: Arc::default(),
+            dag_limits: Arc::default(),
+        };
+        // roots persisted before their metadata was recorded
+        for cid in unindexed.into_iter().chain(uncounted) {
+            store
+                .add_root(&cid, ContentSource::Unknown)
+                .expect("indexing roots should not fail");
+        }
+        store
     }
 
     /// return the inner blockstore
@@ -33,56 +103,25 @@ where
         &self.db
     }
 
-    /// traverse a dag and get full dag given a root cid
+    /// traverse a dag and get full dag given a root cid.
+    /// Blocks are ordered depth-first in link order, see [`UrsaStore::dag_iter`].
     pub fn dag_traversal(&self, root_cid: &Cid) -> Result<Vec<(Cid, Vec<u8>)>> {
-        let mut res = Vec::new();
-        // get full dag starting with root id
-        let mut current = FnvHashSet::default();
-        let mut refs = FnvHashSet::default();
-        current.insert(*root_cid);
-
-        while let Some(cid) = current.iter().next().copied() {
-            current.remove(&cid);
-            if refs.contains(&cid) {
-                continue;
-            }
-            match self.db.get(&cid)? {
-                Some(data) => {
-                    res.push((cid, data.clone()));
-                    let next_block = Block::<DefaultParams>::new(cid, data)?;
-                    next_block.references(&mut current)?;
-                    refs.insert(cid);
-                }
-                None => {
-                    // TODO: handle the case where parts of the dags are missing
-                    return Err(anyhow!(
-                        "The block with cid {:?} from the dag with the root {:?} is missing ",
-                        cid,
-                        root_cid
-                    ));
-                }
-            }
-        }
-        Ok(res)
+        self.dag_iter(root_cid).collect()
     }
 
-    /// Calculate a car file size from a root cid
+    /// Calculate a car file size from a root cid.
+    /// The size of a known root is read from its metadata.
     pub fn car_size(&self, root_cid: &Cid) -> Result<u64> {
-        let dag = self.dag_traversal(root_cid)?;
-
-        let header_bytes = to_vec(&CarHeader {
-            roots: vec![*root_cid],
-            version: 1,
-        })?;
-        let mut len = header_bytes.len();
-
-        for (cid, bytes) in dag {
-            let block_len = bytes.len() + cid.to_bytes().len();
-            len += block_len.encode_var_vec().len(); // varint size
-            len += block_len;
+        let meta = match self.root_meta(root_cid) {
+            Some(meta) if meta.complete => meta,
+            _ => self.dag_meta(root_cid)?,
+        };
+        if !meta.complete {
+            return Err(anyhow!(
+                "The dag with the root {root_cid} is missing blocks"
+            ));
         }
-
-        Ok(len as u64)
+        Ok(meta.car_size)
     }
 }
 
@@ -163,25 +202,11 @@ where
     }
 
     fn insert(&mut self, block: &Block<Self::Params>) -> Result<()> {
-        self.0.db.put_keyed(block.cid(), block.data()).unwrap();
-
-        Ok(())
+        self.0.put_block(block.cid(), block.data(), "bitswap")
     }
 
     fn missing_blocks(&mut self, cid: &Cid) -> Result<Vec<Cid>> {
-        let mut stack = vec![*cid];
-        let mut missing = vec![];
-
-        while let Some(cid) = stack.pop() {
-            if let Some(data) = self.get(&cid)? {
-                let block = Block::<Self::Params>::new_unchecked(cid, data);
-                block.references(&mut stack)?;
-            } else {
-                missing.push(cid);
-            }
-        }
-
-        Ok(missing)
+        Ok(self.0.dag_status(cid)?.missing)
     }
 }
 
@@ -195,9 +220,7 @@ where
     S: Blockstore + Store + Send + Sync + 'static,
 {
     pub fn insert(&mut self, block: &Block<DefaultParams>) -> Result<()> {
-        self.0.db.put_keyed(block.cid(), block.data()).unwrap();
-
-        Ok(())
+        self.0.put_block(block.cid(), block.data(), "graphsync")
     }
 
     pub fn get(&mut self, cid: &Cid) -> Result<Option<Vec<u8>>> {
@@ -214,7 +237,7 @@ where
     }
 
     fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
-        self.0.blockstore().put_keyed(k, block)
+        self.0.put_block(k, block, "graphsync")
     }
 
     fn delete_block(&self, k: &Cid) -> Result<()> {
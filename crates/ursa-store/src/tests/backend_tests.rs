@@ -0,0 +1,167 @@
+#[cfg(test)]
+mod tests {
+    use async_fs::File;
+    use db::Store;
+    use futures::io::BufReader;
+    use fvm_ipld_blockstore::Blockstore;
+    use libipld::{cbor::DagCborCodec, ipld, multihash::Code, Block, DefaultParams};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use crate::tests::setup_logger;
+    use crate::{Backend, BackendKind, ContentSource, FlatFs, UrsaStore, MAX_NAMED_KEY};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ursa-backend-{name}-{}", std::process::id()))
+    }
+
+    /// Checks every backend has to pass.
+    async fn conformance<S>(db: S) -> anyhow::Result<()>
+    where
+        S: Blockstore + Store + Send + Sync + 'static,
+    {
+        // keys
+        assert_eq!(db.read("missing")?, None);
+        assert!(!db.exists("missing")?);
+        db.write("key", b"value")?;
+        assert_eq!(db.read("key")?, Some(b"value".to_vec()));
+        assert!(db.exists("key")?);
+        db.write("key", b"other value")?;
+        assert_eq!(db.read("key")?, Some(b"other value".to_vec()));
+        db.delete("key")?;
+        assert_eq!(db.read("key")?, None);
+        db.delete("key")?;
+
+        for key in [&b"a"[..], &[0, 255, 7], &[42; 200]] {
+            db.write(key, key)?;
+            assert_eq!(db.read(key)?, Some(key.to_vec()));
+        }
+        db.write("empty", b"")?;
+        assert_eq!(db.read("empty")?, Some(Vec::new()));
+
+        // blocks
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("a"))?;
+        assert!(!db.has(block.cid())?);
+        assert_eq!(db.get(block.cid())?, None);
+        db.put_keyed(block.cid(), block.data())?;
+        assert!(db.has(block.cid())?);
+        assert_eq!(db.get(block.cid())?, Some(block.data().to_vec()));
+        assert_eq!(
+            db.read(block.cid().to_bytes())?,
+            Some(block.data().to_vec())
+        );
+
+        // a store on top of the backend
+        let store = UrsaStore::new(Arc::new(db));
+        let file = File::open(Path::new("../../test_files/test.car")).await?;
+        let roots = store.import_car(BufReader::new(file)).await?;
+        let dag = store.dag_traversal(&roots[0])?;
+        assert!(store.dag_status(&roots[0])?.is_complete());
+        store.add_root(&roots[0], ContentSource::Bitswap)?;
+        store.pin(block.cid())?;
+
+        let reopened = UrsaStore::new(Arc::clone(&store.db));
+        assert_eq!(reopened.roots().len(), 2);
+        assert!(reopened.is_pinned(block.cid()));
+
+        let stats = store.gc()?;
+        assert_eq!(stats.blocks_removed, dag.len());
+        for (cid, _) in dag {
+            assert!(!store.blockstore().has(&cid)?);
+        }
+        assert!(store.blockstore().has(block.cid())?);
+        Ok(())
+    }
+
+    /// Keys written to a backend are still there once it is opened again.
+    fn persistence(kind: BackendKind, path: &Path) -> anyhow::Result<()> {
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("b"))?;
+        {
+            let db = Backend::open(kind, path)?;
+            db.write("key", b"value")?;
+            db.put_keyed(block.cid(), block.data())?;
+        }
+        let db = Backend::open(kind, path)?;
+        assert_eq!(db.kind(), kind);
+        assert_eq!(db.read("key")?, Some(b"value".to_vec()));
+        assert_eq!(db.get(block.cid())?, Some(block.data().to_vec()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() -> anyhow::Result<()> {
+        setup_logger();
+        conformance(Backend::open(BackendKind::Memory, "")?).await
+    }
+
+    #[tokio::test]
+    async fn test_flatfs_backend() -> anyhow::Result<()> {
+        setup_logger();
+        let path = temp_path("flatfs");
+        let result = conformance(Backend::open(BackendKind::FlatFs, &path)?).await;
+        let reopened = persistence(BackendKind::FlatFs, &path.join("reopened"));
+        std::fs::remove_dir_all(&path)?;
+        result.and(reopened)
+    }
+
+    #[test]
+    fn test_flatfs_long_keys() -> anyhow::Result<()> {
+        let path = temp_path("long-keys");
+        let db = FlatFs::open(&path)?.without_sync();
+        let keys = [
+            vec![42; MAX_NAMED_KEY],
+            vec![42; 200],
+            vec![43; 200],
+            vec![7; 4096],
+        ];
+        let result = (|| -> anyhow::Result<()> {
+            for key in &keys {
+                db.write(key, key)?;
+            }
+            for key in &keys {
+                assert_eq!(db.read(key)?, Some(key.clone()));
+                assert!(db.exists(key)?);
+            }
+            db.delete(&keys[1])?;
+            assert_eq!(db.read(&keys[1])?, None);
+            assert!(!db.exists(&keys[1])?);
+            assert_eq!(db.read(&keys[2])?, Some(keys[2].clone()));
+
+            // every file name fits in a directory entry
+            for shard in std::fs::read_dir(&path)? {
+                let shard = shard?.path();
+                if shard.is_dir() {
+                    for file in std::fs::read_dir(shard)? {
+                        assert!(file?.file_name().len() <= 255);
+                    }
+                }
+            }
+            Ok(())
+        })();
+        std::fs::remove_dir_all(&path)?;
+        result
+    }
+
+    #[test]
+    fn test_flatfs_sharding() -> anyhow::Result<()> {
+        let path = temp_path("sharding");
+        std::fs::create_dir_all(&path)?;
+        std::fs::write(path.join("SHARDING"), "/repo/flatfs/shard/v1/prefix/2\n")?;
+        let result = Backend::open(BackendKind::FlatFs, &path);
+        std::fs::remove_dir_all(&path)?;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[tokio::test]
+    async fn test_rocksdb_backend() -> anyhow::Result<()> {
+        setup_logger();
+        let path = temp_path("rocksdb");
+        let result = conformance(Backend::open(BackendKind::RocksDb, &path)?).await;
+        let reopened = persistence(BackendKind::RocksDb, &path.with_extension("reopened"));
+        std::fs::remove_dir_all(&path)?;
+        std::fs::remove_dir_all(path.with_extension("reopened"))?;
+        result.and(reopened)
+    }
+}
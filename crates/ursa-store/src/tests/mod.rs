@@ -1,9 +1,8 @@
-use db::MemoryDB;
 use simple_logger::SimpleLogger;
 use std::sync::Arc;
 use tracing::{log::LevelFilter, warn};
 
-use crate::UrsaStore;
+use crate::{MemoryStore, UrsaStore};
 
 pub fn setup_logger() {
     let level = LevelFilter::Debug;
@@ -16,7 +15,7 @@ pub fn setup_logger() {
     }
 }
 
-pub fn get_store() -> Arc<UrsaStore<MemoryDB>> {
-    let db = Arc::new(MemoryDB::default());
+pub fn get_store() -> Arc<UrsaStore<MemoryStore>> {
+    let db = Arc::new(MemoryStore::default());
     Arc::new(UrsaStore::new(Arc::clone(&db)))
 }
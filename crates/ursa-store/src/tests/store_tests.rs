@@ -1,13 +1,28 @@
 #[cfg(test)]
 mod tests {
     use async_fs::File;
-    use futures::io::BufReader;
-    use fvm_ipld_car::{load_car, CarReader};
-    use libipld::Cid;
+    use futures::{
+        io::{BufReader, Cursor},
+        stream,
+    };
+    use fvm_ipld_blockstore::Blockstore;
+    use fvm_ipld_car::{load_car, CarHeader, CarReader};
+    use libipld::{
+        cbor::DagCborCodec, ipld, multihash::Code, pb::DagPbCodec, Block, Cid, DefaultParams, Ipld,
+    };
+    use std::collections::HashSet;
     use std::path::Path;
-    use std::sync::Arc;
+    use std::sync::{atomic::Ordering, Arc};
+    use std::time::Duration;
 
     use crate::tests::{get_store, setup_logger};
+    use crate::{
+        export_snapshot, import_snapshot, unixfs::murmur3_x64_64, write_car_v2, BlockError,
+        CarIndex, CarV2Header, CarVersion, Chunker, ContentSource, EvictionPolicy, MemoryStore,
+        MissingBlock, PathError, UnixFsStat, UnixFsType, UrsaStore, CARV2_PRAGMA,
+        QUARANTINE_PREFIX,
+    };
+    use db::Store;
 
     #[tokio::test]
     async fn test_dag_traversal() -> anyhow::Result<()> {
@@ -31,7 +46,862 @@ mod tests {
 
         let res = store_2.dag_traversal(&cids[0])?;
         assert_eq!(cids_vec.len(), res.len());
-        // todo: check if they both have sam cids
+        let res_cids: HashSet<Cid> = res.into_iter().map(|(cid, _)| cid).collect();
+        assert_eq!(cids_vec.into_iter().collect::<HashSet<_>>(), res_cids);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dag_iter_order() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let put = |ipld: Ipld| -> anyhow::Result<Cid> {
+            let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld)?;
+            store.blockstore().put_keyed(block.cid(), block.data())?;
+            Ok(*block.cid())
+        };
+
+        let a = put(ipld!("a"))?;
+        let b = put(ipld!("b"))?;
+        let c = put(ipld!("c"))?;
+        let middle = put(ipld!([a, b]))?;
+        // `a` is linked twice but only yielded once
+        let root = put(ipld!([middle, c, a]))?;
+
+        let order = store
+            .dag_iter(&root)
+            .map(|block| block.map(|(cid, _)| cid))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(order, vec![root, middle, a, b, c]);
+
+        // a depth limit stops at the links of the deepest blocks
+        for (depth, expected) in [(0, vec![root]), (1, vec![root, middle, c, a])] {
+            let order = store
+                .dag_iter(&root)
+                .with_max_depth(Some(depth))
+                .map(|block| block.map(|(cid, _)| cid))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            assert_eq!(order, expected);
+        }
+
+        // `d` is first reached at the depth limit, then by a shorter path
+        let e = put(ipld!("e"))?;
+        let d = put(ipld!([e]))?;
+        let long = put(ipld!([d]))?;
+        let shortcut = put(ipld!([long, d]))?;
+        let order = store
+            .dag_iter(&shortcut)
+            .with_max_depth(Some(2))
+            .map(|block| block.map(|(cid, _)| cid))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(order, vec![shortcut, long, d, e]);
+
+        // a missing block stops the iteration with an error
+        store.blockstore().delete(b.to_bytes())?;
+        let mut blocks = store.dag_iter(&root);
+        assert!(blocks.next().unwrap().is_ok());
+        assert!(blocks.next().unwrap().is_ok());
+        assert!(blocks.next().unwrap().is_ok());
+        assert!(blocks.next().unwrap().is_err());
+        assert!(blocks.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dag_status() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let put = |ipld: Ipld| -> anyhow::Result<(Cid, u64)> {
+            let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld)?;
+            store.blockstore().put_keyed(block.cid(), block.data())?;
+            Ok((*block.cid(), block.data().len() as u64))
+        };
+
+        let (a, a_len) = put(ipld!("a"))?;
+        let (b, _) = put(ipld!("b"))?;
+        let (middle, middle_len) = put(ipld!([a, b]))?;
+        let (root, root_len) = put(ipld!([middle]))?;
+
+        let status = store.dag_status(&root)?;
+        assert!(status.is_complete());
+        assert_eq!(status.present.len(), 4);
+
+        store.blockstore().delete(b.to_bytes())?;
+        let status = store.dag_status(&root)?;
+        assert!(!status.is_complete());
+        assert_eq!(status.present, vec![root, middle, a]);
+        assert_eq!(status.missing, vec![b]);
+        assert_eq!(status.present_bytes, root_len + middle_len + a_len);
+
+        store.blockstore().delete(root.to_bytes())?;
+        let status = store.dag_status(&root)?;
+        assert!(status.present.is_empty());
+        assert_eq!(status.missing, vec![root]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_car_v2() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+
+        let path = Path::new("../../test_files/test.car");
+        let file = File::open(path).await?;
+        let cids = store.import_car(BufReader::new(file)).await?;
+        let root = cids[0];
+        let dag = store.dag_traversal(&root)?;
+
+        let data_size = store.car_size(&root)?;
+        let mut car = Vec::new();
+        write_car_v2(
+            vec![root],
+            data_size,
+            &mut stream::iter(dag.clone()),
+            &mut car,
+        )
+        .await?;
+        assert_eq!(car[..CARV2_PRAGMA.len()], CARV2_PRAGMA);
+        let header = CarV2Header::from_bytes(car[11..51].try_into()?);
+        assert_eq!(header.data_size, data_size);
+
+        // the payload is a CARv1 file with the blocks in dag order
+        let payload = &car[header.data_offset as usize..header.index_offset as usize];
+        let mut reader = CarReader::new(payload).await?;
+        let mut payload_cids = Vec::new();
+        while let Some(block) = reader.next_block().await? {
+            payload_cids.push(block.cid);
+        }
+        let dag_cids: Vec<Cid> = dag.iter().map(|(cid, _)| *cid).collect();
+        assert_eq!(payload_cids, dag_cids);
+
+        let index = CarIndex::decode(&car[header.index_offset as usize..])?;
+        assert_eq!(index.records().len(), dag.len());
+        assert!(dag_cids.iter().all(|cid| index.get(cid).is_some()));
+
+        // the payload is streamed into the store and the index checked against it
+        let store_2 = get_store();
+        assert_eq!(store_2.import_car(car.as_slice()).await?, vec![root]);
+        assert!(store_2.dag_status(&root)?.is_complete());
+
+        // an index that does not match the payload is ignored
+        let mut bad_index = car.clone();
+        let last = bad_index.len() - 1;
+        bad_index[last] ^= 0xff;
+        let store_3 = get_store();
+        assert_eq!(store_3.import_car(bad_index.as_slice()).await?, vec![root]);
+        assert!(store_3.dag_status(&root)?.is_complete());
+
+        // a truncated payload is not
+        let truncated = &car[..header.index_offset as usize - 1];
+        assert!(get_store().import_car(truncated).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_and_scrub() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let block = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("a"))?;
+        let other = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("b"))?;
+        let root =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!([*block.cid()]))?;
+
+        // a block is rejected if its data does not match the cid
+        let err = store
+            .put_block(block.cid(), other.data(), "test")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BlockError>(),
+            Some(BlockError::HashMismatch(cid)) if cid == block.cid()
+        ));
+        assert!(!store.blockstore().has(block.cid())?);
+
+        store.put_block(root.cid(), root.data(), "test")?;
+        store.put_block(block.cid(), block.data(), "test")?;
+        store.add_root(root.cid(), ContentSource::Bitswap)?;
+        let report = store.scrub(false)?;
+        assert_eq!(report.blocks_checked, 2);
+        assert!(report.corrupted.is_empty());
+
+        // corrupt the block behind the store's back
+        store.blockstore().put_keyed(block.cid(), other.data())?;
+        let report = store.scrub(false)?;
+        assert_eq!(report.corrupted, vec![*block.cid()]);
+        assert!(store.blockstore().has(block.cid())?);
+
+        let report = store.scrub(true)?;
+        assert_eq!(report.corrupted, vec![*block.cid()]);
+        assert!(!store.blockstore().has(block.cid())?);
+        assert_eq!(
+            store
+                .db
+                .read(format!("{QUARANTINE_PREFIX}{}", block.cid()))?,
+            Some(other.data().to_vec())
+        );
+        assert_eq!(store.scrub(false)?.blocks_missing, 1);
+
+        // blocks no root reaches are scrubbed too
+        let orphan =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("orphan"))?;
+        store.blockstore().put_keyed(orphan.cid(), other.data())?;
+        assert_eq!(store.scrub(false)?.corrupted, vec![*orphan.cid()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_block_size() -> anyhow::Result<()> {
+        setup_logger();
+        let store = UrsaStore::new(Arc::new(MemoryStore::default())).with_max_block_size(256);
+        let small =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &Ipld::Bytes(vec![0; 8]))?;
+        let large = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Sha2_256,
+            &Ipld::Bytes(vec![0; 300]),
+        )?;
+
+        let err = store
+            .put_block(large.cid(), large.data(), "test")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BlockError>(),
+            Some(BlockError::TooLarge(cid, size, 256)) if cid == large.cid() && *size == large.data().len()
+        ));
+        assert!(!store.blockstore().has(large.cid())?);
+        store.put_block(small.cid(), small.data(), "test")?;
+
+        // car files with an oversized block are rejected
+        let header = CarHeader {
+            roots: vec![*large.cid()],
+            version: 1,
+        };
+        let mut blocks = stream::iter(vec![(*large.cid(), large.data().to_vec())]);
+        let mut car = Vec::new();
+        header.write_stream_async(&mut car, &mut blocks).await?;
+        assert!(store.import_car(Cursor::new(car)).await.is_err());
+        assert!(!store.blockstore().has(large.cid())?);
+
+        // a section that can not hold a valid block is refused before being read
+        let huge = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Sha2_256,
+            &Ipld::Bytes(vec![0; 1024]),
+        )?;
+        let mut car = Vec::new();
+        CarHeader {
+            roots: vec![*huge.cid()],
+            version: 1,
+        }
+        .write_stream_async(
+            &mut car,
+            &mut stream::iter(vec![(*huge.cid(), huge.data().to_vec())]),
+        )
+        .await?;
+        let err = store.import_car(Cursor::new(car)).await.unwrap_err();
+        assert!(err.to_string().contains("Car section"));
+
+        // so are local imports chunked above the limit
+        let content = vec![7; 1024];
+        let err = store
+            .import_reader(content.as_slice(), Chunker::Fixed(512))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BlockError>(),
+            Some(BlockError::TooLarge(_, 512, 256))
+        ));
+        store.import_reader(content.as_slice(), Chunker::Fixed(256))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unixfs_import() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let links = |cid: &Cid| -> anyhow::Result<Vec<Ipld>> {
+            let data = store.blockstore().get(cid)?.unwrap();
+            match Block::<DefaultParams>::new(*cid, data)?.ipld()? {
+                Ipld::Map(mut node) => match node.remove("Links") {
+                    Some(Ipld::List(links)) => Ok(links),
+                    _ => anyhow::bail!("dag-pb node without links"),
+                },
+                _ => anyhow::bail!("not a dag-pb node"),
+            }
+        };
+
+        // 200 chunks do not fit in a single node
+        let data: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let root = store.import_reader(data.as_slice(), Chunker::Fixed(1024))?;
+        assert_eq!(root.codec(), 0x70);
+        assert_eq!(links(&root)?.len(), 2);
+        let mut content = Vec::new();
+        for block in store.dag_iter(&root) {
+            let (cid, block) = block?;
+            if cid.codec() == 0x55 {
+                assert!(block.len() <= 1024);
+                content.extend(block);
+            }
+        }
+        assert_eq!(content, data);
+
+        // an empty file is the same empty raw block as in go-ipfs
+        let empty = store.import_reader([].as_slice(), Chunker::default())?;
+        assert_eq!(
+            empty.to_string(),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+
+        let dir = std::env::temp_dir().join(format!("ursa-unixfs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("c"))?;
+        std::fs::write(dir.join("b.txt"), b"b")?;
+        std::fs::write(dir.join("a.txt"), b"a")?;
+        std::fs::write(dir.join("c").join("d.txt"), &data)?;
+        let root = store.import_path(&dir, Chunker::Fixed(1024));
+        std::fs::remove_dir_all(&dir)?;
+        let names: Vec<Ipld> = links(&root?)?
+            .into_iter()
+            .map(|link| match link {
+                Ipld::Map(mut link) => link.remove("Name").unwrap(),
+                _ => panic!("invalid dag-pb link"),
+            })
+            .collect();
+        assert_eq!(names, vec![ipld!("a.txt"), ipld!("b.txt"), ipld!("c")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unixfs_resolve() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let data: Vec<u8> = (0..10 * 1024).map(|i| (i % 251) as u8).collect();
+        let file = store.import_reader(data.as_slice(), Chunker::Fixed(1024))?;
+        assert_eq!(
+            store.unixfs_stat(&file)?,
+            UnixFsStat {
+                kind: UnixFsType::File,
+                size: data.len() as u64
+            }
+        );
+        let content = store.read_file(&file).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(content.concat(), data);
+
+        let put = |node: Ipld| -> anyhow::Result<Cid> {
+            let block = Block::<DefaultParams>::encode(DagPbCodec, Code::Sha2_256, &node)?;
+            store.blockstore().put_keyed(block.cid(), block.data())?;
+            Ok(*block.cid())
+        };
+        // slot of a name in a shard with a fanout of 256 at a depth
+        let slot = |name: &str, depth: usize| {
+            format!(
+                "{:02X}",
+                murmur3_x64_64(name.as_bytes()).to_be_bytes()[depth]
+            )
+        };
+        let shard_data = vec![0x08, 0x05, 0x28, 0x22, 0x30, 0x80, 0x02];
+
+        // "a.txt" is in the root shard and "b.txt" in a sub-shard
+        let sub_shard = put(ipld!({
+            "Data": shard_data.clone(),
+            "Links": [{ "Hash": file, "Name": format!("{}b.txt", slot("b.txt", 1)), "Tsize": 0 }],
+        }))?;
+        let shard = put(ipld!({
+            "Data": shard_data,
+            "Links": [
+                { "Hash": file, "Name": format!("{}a.txt", slot("a.txt", 0)), "Tsize": 0 },
+                { "Hash": sub_shard, "Name": slot("b.txt", 0), "Tsize": 0 },
+            ],
+        }))?;
+        let dir = put(ipld!({
+            "Data": vec![0x08, 0x01],
+            "Links": [{ "Hash": shard, "Name": "shard", "Tsize": 0 }],
+        }))?;
+
+        assert_eq!(store.resolve_path(&dir, "/shard/a.txt")?, file);
+        assert_eq!(store.resolve_path(&dir, "shard/b.txt/")?, file);
+        assert_eq!(store.resolve_path(&dir, "")?, dir);
+        let err = store.resolve_path(&dir, "shard/c.txt").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PathError>(),
+            Some(PathError::NotFound(name, _)) if name == "c.txt"
+        ));
+        let err = store.resolve_path(&dir, "shard/a.txt/c.txt").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PathError>(),
+            Some(PathError::NotADirectory(cid)) if *cid == file
+        ));
+
+        let names: Vec<String> = store
+            .list_dir(&shard)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert_eq!(store.list_dir(&dir)?[0].cid, shard);
+
+        // the missing block along a path is reported
+        store.blockstore().delete(sub_shard.to_bytes())?;
+        let err = store.resolve_path(&dir, "shard/b.txt").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MissingBlock>(),
+            Some(MissingBlock(cid)) if *cid == sub_shard
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunkers() -> anyhow::Result<()> {
+        assert_eq!("size-1024".parse::<Chunker>()?, Chunker::Fixed(1024));
+        assert_eq!(
+            "rabin-16-32-64".parse::<Chunker>()?,
+            Chunker::Rabin {
+                min: 16,
+                avg: 32,
+                max: 64
+            }
+        );
+        assert!("size-0".parse::<Chunker>().is_err());
+        assert!("rabin-64-32-16".parse::<Chunker>().is_err());
+        assert!("buzhash".parse::<Chunker>().is_err());
+
+        // pseudo random content, so the rolling hash finds boundaries
+        let mut state = 1u64;
+        let data: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+        let chunker = Chunker::rabin(4096);
+        let chunks = chunker
+            .chunks(data.as_slice())
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(chunks.len() > 1);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 4096 / 3 && chunk.len() <= 4096 + 2048);
+        }
+        assert_eq!(chunks.concat(), data);
+
+        // chunks after an insertion are unchanged
+        let mut shifted = vec![0; 100];
+        shifted.extend(&data);
+        let shifted = chunker
+            .chunks(shifted.as_slice())
+            .collect::<Result<HashSet<_>, _>>()?;
+        let shared = chunks
+            .iter()
+            .filter(|chunk| shifted.contains(*chunk))
+            .count();
+        assert!(shared > chunks.len() / 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_root_meta() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+
+        let path = Path::new("../../test_files/test.car");
+        let file = File::open(path).await?;
+        let cids = store.import_car(BufReader::new(file)).await?;
+        let root = cids[0];
+        store.add_root(&root, ContentSource::Origin)?;
+
+        let dag = store.dag_traversal(&root)?;
+        let mut car = Vec::new();
+        let header = CarHeader {
+            roots: vec![root],
+            version: 1,
+        };
+        header
+            .write_stream_async(&mut car, &mut stream::iter(dag.clone()))
+            .await?;
+
+        let meta = store.root_meta(&root).unwrap();
+        assert_eq!(meta.blocks, dag.len() as u64);
+        assert_eq!(
+            meta.size,
+            dag.iter().map(|(_, data)| data.len() as u64).sum::<u64>()
+        );
+        assert_eq!(meta.car_size, car.len() as u64);
+        assert_eq!(store.car_size(&root)?, car.len() as u64);
+        assert!(meta.complete);
+        assert_eq!(meta.source, ContentSource::Origin);
+        assert_eq!(meta.hits, 0);
+
+        store.touch(&root);
+        let touched = store.root_meta(&root).unwrap();
+        assert_eq!(touched.hits, 1);
+        assert!(touched.last_access > meta.last_access);
+
+        // accesses are persisted in the underlying db once flushed
+        let reopened = UrsaStore::new(Arc::clone(&store.db));
+        assert_eq!(reopened.root_meta(&root), Some(meta));
+        store.flush_accesses()?;
+        let reopened = UrsaStore::new(Arc::clone(&store.db));
+        assert_eq!(reopened.root_meta(&root), Some(touched));
+
+        // and on a timer
+        store.touch(&root);
+        let flush_task = store.flush_accesses_every(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        flush_task.abort();
+        let reopened = UrsaStore::new(Arc::clone(&store.db));
+        assert_eq!(reopened.root_meta(&root).map(|meta| meta.hits), Some(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let provider_store = get_store();
+
+        let path = Path::new("../../test_files/test.car");
+        let cids = load_car(store.blockstore(), BufReader::new(File::open(path).await?)).await?;
+        store.pin(&cids[0])?;
+        let cached =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("cached"))?;
+        store.put_block(cached.cid(), cached.data(), "test")?;
+        store.add_root(cached.cid(), ContentSource::Bitswap)?;
+        store.touch(cached.cid());
+        // a block no root reaches, like a partial download
+        let orphan =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("orphan"))?;
+        store.put_block(orphan.cid(), orphan.data(), "test")?;
+
+        // a two advertisements chain
+        let first = Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("ad"))?;
+        let head = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Blake3_256,
+            &ipld!({ "PreviousID": *first.cid() }),
+        )?;
+        for block in [&first, &head] {
+            provider_store.put_block(block.cid(), block.data(), "test")?;
+        }
+
+        let mut snapshot = Cursor::new(Vec::new());
+        let exported =
+            export_snapshot(&store, &provider_store, Some(*head.cid()), &mut snapshot).await?;
+        assert_eq!(exported.roots.len(), 2);
+        assert_eq!(exported.provider_blocks, 2);
+
+        let restored = get_store();
+        let restored_provider = get_store();
+        let snapshot = snapshot.into_inner();
+        let imported = import_snapshot(&restored, &restored_provider, snapshot.as_slice()).await?;
+        assert_eq!(imported, exported);
+        assert_eq!(imported.provider_head, Some(*head.cid()));
+
+        for cid in store.dag_status(&cids[0])?.present {
+            assert!(restored.blockstore().has(&cid)?);
+        }
+        assert!(restored.blockstore().has(orphan.cid())?);
+        assert!(restored_provider.blockstore().has(first.cid())?);
+        assert!(restored.is_pinned(&cids[0]));
+        assert!(!restored.is_pinned(cached.cid()));
+        assert_eq!(
+            restored.root_meta(cached.cid()),
+            store.root_meta(cached.cid())
+        );
+        assert_eq!(restored.root_meta(&cids[0]), store.root_meta(&cids[0]));
+
+        // blocks past the maximum block size of the store are refused
+        let small = UrsaStore::new(Arc::new(MemoryStore::default())).with_max_block_size(16);
+        assert!(
+            import_snapshot(&small, &restored_provider, snapshot.as_slice())
+                .await
+                .is_err()
+        );
+
+        // only empty stores can be restored
+        assert!(
+            import_snapshot(&restored, &restored_provider, snapshot.as_slice())
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gc() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+
+        let path = Path::new("../../test_files/test.car");
+        let file = File::open(path).await?;
+        let reader = BufReader::new(file);
+        let cids = load_car(store.blockstore(), reader).await?;
+        let dag = store.dag_traversal(&cids[0])?;
+        store.add_root(&cids[0], ContentSource::Bitswap)?;
+
+        let block =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("pinned"))?;
+        store.blockstore().put_keyed(block.cid(), block.data())?;
+        store.pin(block.cid())?;
+
+        // a block no root reaches
+        let orphan =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("orphan"))?;
+        store.blockstore().put_keyed(orphan.cid(), orphan.data())?;
+
+        let stats = store.gc()?;
+        assert_eq!(stats.roots_removed, 1);
+        assert_eq!(stats.blocks_removed, dag.len() + 1);
+        for (cid, _) in dag {
+            assert!(!store.blockstore().has(&cid)?);
+        }
+        assert!(!store.blockstore().has(orphan.cid())?);
+        assert!(store.blockstore().has(block.cid())?);
+        assert_eq!(store.roots(), vec![*block.cid()]);
+
+        // pins are persisted in the underlying db
+        let reopened = UrsaStore::new(Arc::clone(&store.db));
+        assert!(reopened.is_pinned(block.cid()));
+
+        assert!(store.unpin(block.cid())?);
+        let stats = store.gc()?;
+        assert_eq!(stats.blocks_removed, 1);
+        assert!(!store.blockstore().has(block.cid())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_during_ingest() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+
+        // an upload is written, then pinned
+        let ingest = store.ingest();
+        let block =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("uploaded"))?;
+        store.put_block(block.cid(), block.data(), "test")?;
+        assert!(store.gc().is_err());
+        assert!(store.blockstore().has(block.cid())?);
+        store.pin(block.cid())?;
+        drop(ingest);
+        assert_eq!(store.gc()?.blocks_removed, 0);
+        assert!(store.blockstore().has(block.cid())?);
+
+        // a block written while a collection runs is not swept
+        let orphan =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("orphan"))?;
+        store.ingests.lock().unwrap().written = Some(Default::default());
+        store.put_block(orphan.cid(), orphan.data(), "test")?;
+        assert_eq!(store.collect_garbage()?.blocks_removed, 0);
+        store.ingests.lock().unwrap().written = None;
+        assert_eq!(store.gc()?.blocks_removed, 1);
+        assert!(!store.blockstore().has(orphan.cid())?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evict() -> anyhow::Result<()> {
+        setup_logger();
+        let db = Arc::new(MemoryStore::default());
+
+        let path = Path::new("../../test_files/test.car");
+        let file = File::open(path).await?;
+        let reader = BufReader::new(file);
+        let cids = load_car(db.as_ref(), reader).await?;
+
+        let store = UrsaStore::new(Arc::clone(&db));
+        store.add_root(&cids[0], ContentSource::Bitswap)?;
+        let car_size = store.used_bytes();
+
+        let block =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("cached"))?;
+        store.blockstore().put_keyed(block.cid(), block.data())?;
+        store.add_root(block.cid(), ContentSource::Bitswap)?;
+
+        // the car root is now the most recently used
+        store.touch(&cids[0]);
+        assert!(store.used_bytes() > car_size);
+
+        let store = store.with_quota(car_size, EvictionPolicy::Lru);
+        let mut evictions = store.subscribe_evictions();
+        let evicted = store.evict()?;
+        assert_eq!(evicted, vec![*block.cid()]);
+        assert_eq!(evictions.try_recv()?, *block.cid());
+        assert!(!store.blockstore().has(block.cid())?);
+        assert!(store.blockstore().has(&cids[0])?);
+        assert_eq!(store.used_bytes(), car_size);
+        assert!(store.evict()?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quota_counts_shared_blocks_once() -> anyhow::Result<()> {
+        setup_logger();
+        let db = Arc::new(MemoryStore::default());
+
+        let path = Path::new("../../test_files/test.car");
+        let file = File::open(path).await?;
+        let reader = BufReader::new(file);
+        let cids = load_car(db.as_ref(), reader).await?;
+
+        let store = UrsaStore::new(Arc::clone(&db));
+        store.add_root(&cids[0], ContentSource::Bitswap)?;
+        let car_size = store.used_bytes();
+
+        // a sub-dag only holds blocks already counted for the car root
+        let dag = store.dag_traversal(&cids[0])?;
+        let (sub_root, _) = dag[1];
+        store.add_root(&sub_root, ContentSource::Bitswap)?;
+        assert_eq!(store.used_bytes(), car_size);
+
+        // the counts are persisted
+        let reopened = UrsaStore::new(Arc::clone(&db));
+        assert_eq!(reopened.used_bytes(), car_size);
+
+        // adding a root over the quota evicts the least recently used ones
+        let store = store.with_quota(car_size, EvictionPolicy::Lru);
+        store.touch(&sub_root);
+        let block =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("cached"))?;
+        store.blockstore().put_keyed(block.cid(), block.data())?;
+        store.add_root(block.cid(), ContentSource::Bitswap)?;
+        assert!(store.used_bytes() <= car_size);
+        assert!(!store.roots().contains(&cids[0]));
+        assert!(store.roots().contains(block.cid()));
+        assert!(!store.blockstore().has(&cids[0])?);
+
+        // blocks of the remaining sub-dag are kept
+        for (cid, _) in store.dag_traversal(&sub_root)? {
+            assert!(store.blockstore().has(&cid)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_quota_partial_dag_shares_counted_blocks() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let shared =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("shared"))?;
+        let missing =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("missing"))?;
+        let complete = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Blake3_256,
+            &ipld!([*shared.cid()]),
+        )?;
+        let partial = Block::<DefaultParams>::encode(
+            DagCborCodec,
+            Code::Blake3_256,
+            &ipld!([*shared.cid(), *missing.cid()]),
+        )?;
+        for block in [&shared, &complete, &partial] {
+            store.put_block(block.cid(), block.data(), "test")?;
+        }
+        store.add_root(complete.cid(), ContentSource::Bitswap)?;
+        store.add_root(partial.cid(), ContentSource::Bitswap)?;
+
+        // the shared block is only counted with the complete dag
+        let bytes = [&shared, &complete, &partial]
+            .iter()
+            .map(|block| block.data().len() as u64)
+            .sum::<u64>();
+        assert_eq!(store.used_bytes(), bytes);
+
+        // a counter below the counted blocks does not underflow
+        store.counted_bytes.store(0, Ordering::Relaxed);
+        store.remove(complete.cid())?;
+        assert_eq!(store.counted_bytes.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limit_dag() -> anyhow::Result<()> {
+        setup_logger();
+        let remote = get_store();
+        let file = File::open(Path::new("../../test_files/test.car")).await?;
+        let root = load_car(remote.blockstore(), BufReader::new(file)).await?[0];
+        let dag = remote.dag_traversal(&root)?;
+        let dag_size: u64 = dag.iter().map(|(_, data)| data.len() as u64).sum();
+
+        // the blocks past the limit are refused
+        let limited = get_store();
+        let limit = limited.limit_dag(root, dag_size - 1);
+        let written = dag
+            .iter()
+            .take_while(|(cid, data)| limited.put_block(cid, data, "test").is_ok())
+            .count();
+        assert_eq!(written, dag.len() - 1);
+
+        // a dag within its limit is written
+        let store = get_store();
+        let _limit = store.limit_dag(root, dag_size);
+        for (cid, data) in &dag {
+            store.put_block(cid, data, "test")?;
+        }
+        assert!(store.dag_status(&root)?.is_complete());
+
+        // blocks out of the dag are not counted, and the limit ends with its guard
+        let other =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Blake3_256, &ipld!("other"))?;
+        limited.put_block(other.cid(), other.data(), "test")?;
+        drop(limit);
+        let (cid, data) = dag.last().unwrap();
+        limited.put_block(cid, data, "test")?;
+        assert!(limited.dag_status(&root)?.is_complete());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_unknown_root() -> anyhow::Result<()> {
+        setup_logger();
+        let db = Arc::new(MemoryStore::default());
+        let file = File::open(Path::new("../../test_files/test.car")).await?;
+        let root = load_car(db.as_ref(), BufReader::new(file)).await?[0];
+
+        // a dag fetched but not recorded as a root
+        let store = UrsaStore::new(Arc::clone(&db));
+        let dag = store.dag_traversal(&root)?;
+        let stats = store.remove(&root)?;
+        assert_eq!(stats.roots_removed, 0);
+        assert_eq!(stats.blocks_removed, dag.len());
+        for (cid, _) in dag {
+            assert!(!store.blockstore().has(&cid)?);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_and_remove() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let file = File::open(Path::new("../../test_files/test.car")).await?;
+        let root = store.import_car(BufReader::new(file)).await?[0];
+        let dag = store.dag_traversal(&root)?;
+        store.pin(&root)?;
+
+        for version in [CarVersion::V1, CarVersion::V2] {
+            let mut car = Vec::new();
+            store.export_car(&root, version, &mut car).await?;
+            let other = get_store();
+            assert_eq!(other.import_car(Cursor::new(car)).await?, vec![root]);
+            assert_eq!(other.dag_traversal(&root)?, dag);
+        }
+
+        let report = store.verify_dag(&root)?;
+        assert_eq!(report.blocks_checked, dag.len());
+        assert!(report.corrupted.is_empty());
+
+        // a block shared with another root is kept
+        let (shared, _) = dag[1].clone();
+        store.add_root(&shared, ContentSource::Bitswap)?;
+        let stats = store.remove(&root)?;
+        assert!(!store.is_pinned(&root));
+        assert_eq!(store.roots(), vec![shared]);
+        assert!(!store.blockstore().has(&root)?);
+        assert!(store.blockstore().has(&shared)?);
+        assert!(stats.blocks_removed > 0 && stats.blocks_removed < dag.len());
+
+        assert!(store
+            .export_car(&root, CarVersion::V1, &mut Vec::new())
+            .await
+            .is_err());
+        assert_eq!(store.verify_dag(&root)?.blocks_missing, 1);
         Ok(())
     }
 }
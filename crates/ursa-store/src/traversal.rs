@@ -0,0 +1,174 @@
+//! Lazy dag traversal and partial dag inspection for the [`UrsaStore`].
+
+use anyhow::anyhow;
+use db::Store;
+use fnv::{FnvHashMap, FnvHashSet};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{store::DefaultParams, Block, Cid, Result};
+use std::sync::Arc;
+
+use crate::UrsaStore;
+
+/// Blocks of a dag held by the store, and the ones it is missing.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DagStatus {
+    /// Blocks present in the store, depth-first in link order.
+    pub present: Vec<Cid>,
+    /// Blocks linked from a present block but missing from the store.
+    /// Links of missing blocks are unknown, so the dag may be missing more.
+    pub missing: Vec<Cid>,
+    /// Total size of the present blocks in bytes.
+    pub present_bytes: u64,
+}
+
+impl DagStatus {
+    /// Check if every block of the dag is present.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Iterator over the blocks of a dag.
+///
+/// Blocks are read lazily from the blockstore and yielded depth-first in link
+/// order, which is the canonical block order of a CAR file. Blocks linked more
+/// than once are only yielded the first time. With a maximum depth, a block
+/// reached again by a shorter path has its links visited again from that
+/// depth, so every block within the depth is yielded. The iteration stops
+/// after the first error, e.g. a missing block.
+pub struct DagIter<S> {
+    db: Arc<S>,
+    root_cid: Cid,
+    /// Blocks left to visit with their depth below the root.
+    stack: Vec<(Cid, usize)>,
+    /// Visited blocks with the shallowest depth they were reached at.
+    seen: FnvHashMap<Cid, usize>,
+    max_depth: Option<usize>,
+}
+
+impl<S> DagIter<S>
+where
+    S: Blockstore,
+{
+    pub fn new(db: Arc<S>, root_cid: Cid) -> Self {
+        Self {
+            db,
+            root_cid,
+            stack: vec![(root_cid, 0)],
+            seen: FnvHashMap::default(),
+            max_depth: None,
+        }
+    }
+
+    /// Only yield the blocks at most `max_depth` links below the root,
+    /// a depth of 0 yields the root block alone.
+    pub fn with_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn visit(&mut self, cid: Cid, depth: usize) -> Result<(Cid, Vec<u8>)> {
+        match self.db.get(&cid)? {
+            Some(data) => {
+                let block = Block::<DefaultParams>::new(cid, data)?;
+                self.push_links(&block, depth)?;
+                Ok(block.into_inner())
+            }
+            None => Err(anyhow!(
+                "The block with cid {:?} from the dag with the root {:?} is missing ",
+                cid,
+                self.root_cid
+            )),
+        }
+    }
+
+    /// Visit the links of a block again, now that it was reached at a shallower depth.
+    fn revisit(&mut self, cid: Cid, depth: usize) -> Result<()> {
+        match self.db.get(&cid)? {
+            Some(data) => self.push_links(&Block::<DefaultParams>::new_unchecked(cid, data), depth),
+            None => Err(anyhow!(
+                "The block with cid {:?} from the dag with the root {:?} is missing ",
+                cid,
+                self.root_cid
+            )),
+        }
+    }
+
+    fn push_links(&mut self, block: &Block<DefaultParams>, depth: usize) -> Result<()> {
+        if self.max_depth.map_or(true, |max_depth| depth < max_depth) {
+            let mut links = Vec::new();
+            block.references(&mut links)?;
+            // reversed so the first link is visited first
+            self.stack
+                .extend(links.into_iter().rev().map(|link| (link, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+impl<S> Iterator for DagIter<S>
+where
+    S: Blockstore,
+{
+    type Item = Result<(Cid, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((cid, depth)) = self.stack.pop() {
+            if let Some(&seen_depth) = self.seen.get(&cid) {
+                // without a maximum depth every link of a seen block was visited
+                if self.max_depth.is_none() || seen_depth <= depth {
+                    continue;
+                }
+                self.seen.insert(cid, depth);
+                if let Err(e) = self.revisit(cid, depth) {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            self.seen.insert(cid, depth);
+            let res = self.visit(cid, depth);
+            if res.is_err() {
+                self.stack.clear();
+            }
+            return Some(res);
+        }
+        None
+    }
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Lazily traverse the dag under a root cid, depth-first in link order.
+    pub fn dag_iter(&self, root_cid: &Cid) -> DagIter<S> {
+        DagIter::new(Arc::clone(&self.db), *root_cid)
+    }
+
+    /// Walk the dag under a root cid, collecting present and missing blocks
+    /// instead of failing on the first missing block.
+    pub fn dag_status(&self, root_cid: &Cid) -> Result<DagStatus> {
+        let mut status = DagStatus::default();
+        let mut stack = vec![*root_cid];
+        let mut seen = FnvHashSet::default();
+
+        while let Some(cid) = stack.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            match self.db.get(&cid)? {
+                Some(data) => {
+                    status.present_bytes += data.len() as u64;
+                    let block = Block::<DefaultParams>::new_unchecked(cid, data);
+                    let mut links = Vec::new();
+                    block.references(&mut links)?;
+                    stack.extend(links.into_iter().rev());
+                    status.present.push(cid);
+                }
+                None => status.missing.push(cid),
+            }
+        }
+        Ok(status)
+    }
+}
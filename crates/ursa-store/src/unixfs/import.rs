@@ -0,0 +1,153 @@
+//! UnixFS importer.
+//!
+//! Files are split by a [`Chunker`] into raw leaves, linked by a balanced tree
+//! of dag-pb nodes with at most [`MAX_LINKS`] links each, like the go-ipfs
+//! importer with cid v1 and raw leaves. Directories are dag-pb nodes linking
+//! their entries by name; sharded directories are not produced.
+
+use anyhow::{anyhow, bail};
+use db::Store;
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid, Result,
+};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::{put_bytes, put_uint, UnixFsType, DAG_PB, RAW};
+use crate::chunker::Chunker;
+use crate::UrsaStore;
+
+/// Maximum number of links of a file node, as in go-ipfs.
+pub const MAX_LINKS: usize = 174;
+
+/// A dag-pb link to an imported block.
+struct Link {
+    cid: Cid,
+    name: String,
+    /// Total size of the blocks of the linked dag.
+    tsize: u64,
+    /// Size of the file content under the link.
+    filesize: u64,
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Import the content of a reader as a UnixFS file, returning its root cid.
+    pub fn import_reader<R: Read>(&self, reader: R, chunker: Chunker) -> Result<Cid> {
+        Ok(self.import_file(reader, chunker)?.cid)
+    }
+
+    /// Import a file or a directory tree as UnixFS, returning its root cid.
+    /// Symbolic links are followed.
+    pub fn import_path(&self, path: &Path, chunker: Chunker) -> Result<Cid> {
+        Ok(self.import_entry(path, chunker)?.cid)
+    }
+
+    fn import_entry(&self, path: &Path, chunker: Chunker) -> Result<Link> {
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            self.import_dir(path, chunker)
+        } else if metadata.is_file() {
+            self.import_file(BufReader::new(File::open(path)?), chunker)
+        } else {
+            bail!("{path:?} is neither a file nor a directory")
+        }
+    }
+
+    fn import_dir(&self, path: &Path, chunker: Chunker) -> Result<Link> {
+        let mut links = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|name| anyhow!("The file name {name:?} is not valid UTF-8"))?;
+            let mut link = self.import_entry(&entry.path(), chunker)?;
+            link.name = name;
+            links.push(link);
+        }
+        // dag-pb links are sorted by name
+        links.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut data = Vec::new();
+        put_uint(&mut data, 1, UnixFsType::Directory as u64);
+        self.put_node(&links, &data)
+    }
+
+    fn import_file<R: Read>(&self, reader: R, chunker: Chunker) -> Result<Link> {
+        let mut level = Vec::new();
+        let mut chunks = chunker.chunks(reader).peekable();
+        // an empty file is a single empty leaf
+        if chunks.peek().is_none() {
+            level.push(self.put_leaf(Vec::new())?);
+        }
+        for chunk in chunks {
+            level.push(self.put_leaf(chunk?)?);
+        }
+
+        while level.len() > 1 {
+            level = level
+                .chunks(MAX_LINKS)
+                .map(|links| {
+                    let blocksizes: Vec<u64> = links.iter().map(|link| link.filesize).collect();
+                    let data = file_data(blocksizes.iter().sum(), &blocksizes);
+                    self.put_node(links, &data)
+                })
+                .collect::<Result<_>>()?;
+        }
+        Ok(level.pop().unwrap())
+    }
+
+    fn put_leaf(&self, chunk: Vec<u8>) -> Result<Link> {
+        let cid = Cid::new_v1(RAW, Code::Sha2_256.digest(&chunk));
+        self.check_block_size(&cid, chunk.len())?;
+        self.record_write(&cid);
+        self.db.put_keyed(&cid, &chunk)?;
+        Ok(Link {
+            cid,
+            name: String::new(),
+            tsize: chunk.len() as u64,
+            filesize: chunk.len() as u64,
+        })
+    }
+
+    /// Write a dag-pb node with UnixFS `data`.
+    fn put_node(&self, links: &[Link], data: &[u8]) -> Result<Link> {
+        let mut node = Vec::new();
+        for link in links {
+            let mut pb_link = Vec::new();
+            put_bytes(&mut pb_link, 1, &link.cid.to_bytes());
+            put_bytes(&mut pb_link, 2, link.name.as_bytes());
+            put_uint(&mut pb_link, 3, link.tsize);
+            put_bytes(&mut node, 2, &pb_link);
+        }
+        put_bytes(&mut node, 1, data);
+
+        let cid = Cid::new_v1(DAG_PB, Code::Sha2_256.digest(&node));
+        self.check_block_size(&cid, node.len())?;
+        self.record_write(&cid);
+        self.db.put_keyed(&cid, &node)?;
+        Ok(Link {
+            cid,
+            name: String::new(),
+            tsize: node.len() as u64 + links.iter().map(|link| link.tsize).sum::<u64>(),
+            filesize: links.iter().map(|link| link.filesize).sum(),
+        })
+    }
+}
+
+/// UnixFS data of a file node.
+fn file_data(filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut data = Vec::new();
+    put_uint(&mut data, 1, UnixFsType::File as u64);
+    put_uint(&mut data, 3, filesize);
+    for size in blocksizes {
+        put_uint(&mut data, 4, *size);
+    }
+    data
+}
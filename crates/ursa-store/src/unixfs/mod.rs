@@ -0,0 +1,184 @@
+//! UnixFS support for the [`UrsaStore`](crate::UrsaStore).
+//!
+//! UnixFS nodes are dag-pb blocks whose data is a protobuf encoded UnixFS
+//! `Data` message, and file content may be stored in raw leaves. Both
+//! protobuf messages are small enough to be encoded and decoded by hand here.
+
+mod import;
+mod resolve;
+
+pub use self::import::*;
+pub use self::resolve::*;
+
+use anyhow::{anyhow, bail};
+use integer_encoding::VarInt;
+use libipld::{Cid, Result};
+
+pub(crate) const RAW: u64 = 0x55;
+pub(crate) const DAG_PB: u64 = 0x70;
+/// Multicodec of the murmur3-x64-64 hash used by HAMT sharded directories.
+pub(crate) const MURMUR3_X64_64: u64 = 0x22;
+
+/// UnixFS `Data.DataType` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixFsType {
+    Raw = 0,
+    Directory = 1,
+    File = 2,
+    Metadata = 3,
+    Symlink = 4,
+    HamtShard = 5,
+}
+
+impl TryFrom<u64> for UnixFsType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        Ok(match value {
+            0 => UnixFsType::Raw,
+            1 => UnixFsType::Directory,
+            2 => UnixFsType::File,
+            3 => UnixFsType::Metadata,
+            4 => UnixFsType::Symlink,
+            5 => UnixFsType::HamtShard,
+            _ => bail!("Unknown UnixFS data type {value}"),
+        })
+    }
+}
+
+/// A decoded dag-pb `PBLink`.
+#[derive(Debug, Clone)]
+pub(crate) struct PbLink {
+    pub cid: Cid,
+    pub name: String,
+    pub tsize: u64,
+}
+
+/// A decoded dag-pb `PBNode`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PbNode {
+    pub links: Vec<PbLink>,
+    pub data: Vec<u8>,
+}
+
+impl PbNode {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut node = PbNode::default();
+        for (field, value) in decode_fields(bytes)? {
+            match (field, value) {
+                (1, Field::Bytes(data)) => node.data = data.to_vec(),
+                (2, Field::Bytes(link)) => node.links.push(PbLink::decode(link)?),
+                _ => bail!("Invalid dag-pb node field {field}"),
+            }
+        }
+        Ok(node)
+    }
+}
+
+impl PbLink {
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let (mut cid, mut name, mut tsize) = (None, String::new(), 0);
+        for (field, value) in decode_fields(bytes)? {
+            match (field, value) {
+                (1, Field::Bytes(hash)) => cid = Some(Cid::try_from(hash)?),
+                (2, Field::Bytes(bytes)) => name = String::from_utf8(bytes.to_vec())?,
+                (3, Field::Varint(size)) => tsize = size,
+                _ => bail!("Invalid dag-pb link field {field}"),
+            }
+        }
+        let cid = cid.ok_or_else(|| anyhow!("dag-pb link without a hash"))?;
+        Ok(PbLink { cid, name, tsize })
+    }
+}
+
+/// A decoded UnixFS `Data` message.
+#[derive(Debug, Clone)]
+pub(crate) struct UnixFsData {
+    pub kind: UnixFsType,
+    pub data: Vec<u8>,
+    pub filesize: Option<u64>,
+    pub hash_type: Option<u64>,
+    pub fanout: Option<u64>,
+}
+
+impl UnixFsData {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut kind = None;
+        let mut unixfs = UnixFsData {
+            kind: UnixFsType::Raw,
+            data: Vec::new(),
+            filesize: None,
+            hash_type: None,
+            fanout: None,
+        };
+        for (field, value) in decode_fields(bytes)? {
+            match (field, value) {
+                (1, Field::Varint(value)) => kind = Some(UnixFsType::try_from(value)?),
+                (2, Field::Bytes(data)) => unixfs.data = data.to_vec(),
+                (3, Field::Varint(size)) => unixfs.filesize = Some(size),
+                (5, Field::Varint(code)) => unixfs.hash_type = Some(code),
+                (6, Field::Varint(fanout)) => unixfs.fanout = Some(fanout),
+                // block sizes, mode and mtime are not needed to read the node
+                _ => {}
+            }
+        }
+        unixfs.kind = kind.ok_or_else(|| anyhow!("UnixFS data without a type"))?;
+        Ok(unixfs)
+    }
+}
+
+/// Value of a protobuf field.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Decode the fields of a protobuf message in order.
+fn decode_fields(mut buf: &[u8]) -> Result<Vec<(u64, Field<'_>)>> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let value = match key & 7 {
+            0 => Field::Varint(read_varint(&mut buf)?),
+            2 => {
+                let len = read_varint(&mut buf)? as usize;
+                if len > buf.len() {
+                    bail!("Unexpected end of a protobuf message");
+                }
+                let (bytes, rest) = buf.split_at(len);
+                buf = rest;
+                Field::Bytes(bytes)
+            }
+            wire @ (1 | 5) => {
+                let len = if wire == 1 { 8 } else { 4 };
+                buf = buf
+                    .get(len..)
+                    .ok_or_else(|| anyhow!("Unexpected end of a protobuf message"))?;
+                Field::Fixed
+            }
+            wire => bail!("Unsupported protobuf wire type {wire}"),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64> {
+    let (value, len) = u64::decode_var(buf).ok_or_else(|| anyhow!("Invalid protobuf varint"))?;
+    *buf = &buf[len..];
+    Ok(value)
+}
+
+/// Append a protobuf varint field.
+fn put_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    buf.extend((field << 3).encode_var_vec());
+    buf.extend(value.encode_var_vec());
+}
+
+/// Append a protobuf length-delimited field.
+fn put_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    buf.extend(((field << 3) | 2).encode_var_vec());
+    buf.extend(bytes.len().encode_var_vec());
+    buf.extend_from_slice(bytes);
+}
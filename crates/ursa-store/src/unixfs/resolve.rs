@@ -0,0 +1,320 @@
+//! UnixFS path resolution and file reading.
+
+use anyhow::{anyhow, bail};
+use db::Store;
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{Cid, Result};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{PbNode, UnixFsData, UnixFsType, DAG_PB, MURMUR3_X64_64, RAW};
+use crate::UrsaStore;
+
+#[derive(Debug, Error)]
+pub enum PathError {
+    #[error("No link named {0:?} under {1}")]
+    NotFound(String, Cid),
+    #[error("{0} is not a directory")]
+    NotADirectory(Cid),
+}
+
+/// A block needed to read a UnixFS node is not in the store.
+#[derive(Debug, Error)]
+#[error("The block {0} is missing")]
+pub struct MissingBlock(pub Cid);
+
+/// Type and content size of a UnixFS node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixFsStat {
+    /// Raw leaves are reported as files.
+    pub kind: UnixFsType,
+    /// Size of the file content in bytes, 0 for directories.
+    pub size: u64,
+}
+
+/// An entry of a UnixFS directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub cid: Cid,
+    /// Total size of the blocks under the entry.
+    pub size: u64,
+}
+
+enum Node {
+    Raw(Vec<u8>),
+    Pb(PbNode, UnixFsData),
+}
+
+fn load_node<S: Blockstore>(db: &S, cid: &Cid) -> Result<Node> {
+    let data = db.get(cid)?.ok_or(MissingBlock(*cid))?;
+    match cid.codec() {
+        RAW => Ok(Node::Raw(data)),
+        DAG_PB => {
+            let node = PbNode::decode(&data)?;
+            let unixfs = UnixFsData::decode(&node.data)?;
+            Ok(Node::Pb(node, unixfs))
+        }
+        codec => bail!("{cid} with the codec {codec:#x} is not a UnixFS node"),
+    }
+}
+
+/// Iterator over the content of a UnixFS file, yielding the data of its
+/// nodes in order. The iteration stops after the first error.
+pub struct FileReader<S> {
+    db: Arc<S>,
+    stack: Vec<Cid>,
+}
+
+impl<S> FileReader<S>
+where
+    S: Blockstore,
+{
+    fn read(&mut self, cid: Cid) -> Result<Vec<u8>> {
+        match load_node(self.db.as_ref(), &cid)? {
+            Node::Raw(data) => Ok(data),
+            Node::Pb(node, unixfs) if matches!(unixfs.kind, UnixFsType::File | UnixFsType::Raw) => {
+                // the data of a node comes before the data of its links
+                self.stack
+                    .extend(node.links.into_iter().rev().map(|link| link.cid));
+                Ok(unixfs.data)
+            }
+            Node::Pb(_, unixfs) => bail!("{cid} is a {:?}, not a file", unixfs.kind),
+        }
+    }
+}
+
+impl<S> Iterator for FileReader<S>
+where
+    S: Blockstore,
+{
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(cid) = self.stack.pop() {
+            match self.read(cid) {
+                Ok(data) if data.is_empty() => continue,
+                Ok(data) => return Some(Ok(data)),
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Resolve a `/` separated path of names under a UnixFS directory.
+    pub fn resolve_path(&self, root_cid: &Cid, path: &str) -> Result<Cid> {
+        let mut cid = *root_cid;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let next = match load_node(self.db.as_ref(), &cid)? {
+                Node::Pb(node, unixfs) if unixfs.kind == UnixFsType::Directory => node
+                    .links
+                    .into_iter()
+                    .find(|link| link.name == name)
+                    .map(|link| link.cid),
+                Node::Pb(node, unixfs) if unixfs.kind == UnixFsType::HamtShard => {
+                    self.hamt_find(node, unixfs, name)?
+                }
+                _ => return Err(PathError::NotADirectory(cid).into()),
+            };
+            cid = next.ok_or_else(|| PathError::NotFound(name.to_string(), cid))?;
+        }
+        Ok(cid)
+    }
+
+    /// Type and size of a UnixFS node.
+    pub fn unixfs_stat(&self, cid: &Cid) -> Result<UnixFsStat> {
+        Ok(match load_node(self.db.as_ref(), cid)? {
+            Node::Raw(data) => UnixFsStat {
+                kind: UnixFsType::File,
+                size: data.len() as u64,
+            },
+            Node::Pb(_, unixfs) => UnixFsStat {
+                kind: unixfs.kind,
+                size: unixfs.filesize.unwrap_or(unixfs.data.len() as u64),
+            },
+        })
+    }
+
+    /// List the entries of a UnixFS directory, sharded or not, sorted by name.
+    pub fn list_dir(&self, cid: &Cid) -> Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        match load_node(self.db.as_ref(), cid)? {
+            Node::Pb(node, unixfs) if unixfs.kind == UnixFsType::Directory => {
+                entries.extend(node.links.into_iter().map(|link| DirEntry {
+                    name: link.name,
+                    cid: link.cid,
+                    size: link.tsize,
+                }))
+            }
+            Node::Pb(node, unixfs) if unixfs.kind == UnixFsType::HamtShard => {
+                self.hamt_entries(node, unixfs, &mut entries)?
+            }
+            _ => return Err(PathError::NotADirectory(*cid).into()),
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Lazily read the content of a UnixFS file.
+    pub fn read_file(&self, cid: &Cid) -> FileReader<S> {
+        FileReader {
+            db: Arc::clone(&self.db),
+            stack: vec![*cid],
+        }
+    }
+
+    /// Find an entry of a HAMT sharded directory.
+    ///
+    /// Each shard consumes `log2(fanout)` bits of the murmur3 hash of the name
+    /// to pick a slot. Slot links are named by the slot index in upper case
+    /// hex, followed by the entry name for entries or nothing for sub-shards.
+    fn hamt_find(
+        &self,
+        mut node: PbNode,
+        mut unixfs: UnixFsData,
+        name: &str,
+    ) -> Result<Option<Cid>> {
+        let hash = murmur3_x64_64(name.as_bytes()).to_be_bytes();
+        let mut offset = 0;
+        loop {
+            let (width, pad) = hamt_params(&unixfs)?;
+            let index = take_bits(&hash, offset, width)
+                .ok_or_else(|| anyhow!("The HAMT shard is deeper than the hash of {name:?}"))?;
+            offset += width;
+            let prefix = format!("{index:0pad$X}");
+
+            let mut shard = None;
+            for link in node.links {
+                if !link.name.starts_with(&prefix) {
+                    continue;
+                }
+                if link.name.len() == pad {
+                    shard = Some(link.cid);
+                } else if link.name[pad..] == *name {
+                    return Ok(Some(link.cid));
+                }
+            }
+            match shard {
+                Some(cid) => (node, unixfs) = load_shard(self.db.as_ref(), &cid)?,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Collect all the entries of a HAMT sharded directory.
+    fn hamt_entries(
+        &self,
+        node: PbNode,
+        unixfs: UnixFsData,
+        entries: &mut Vec<DirEntry>,
+    ) -> Result<()> {
+        let (_, pad) = hamt_params(&unixfs)?;
+        for link in node.links {
+            if link.name.len() == pad {
+                let (node, unixfs) = load_shard(self.db.as_ref(), &link.cid)?;
+                self.hamt_entries(node, unixfs, entries)?;
+            } else {
+                entries.push(DirEntry {
+                    name: link.name.get(pad..).unwrap_or_default().to_string(),
+                    cid: link.cid,
+                    size: link.tsize,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load_shard<S: Blockstore>(db: &S, cid: &Cid) -> Result<(PbNode, UnixFsData)> {
+    match load_node(db, cid)? {
+        Node::Pb(node, unixfs) if unixfs.kind == UnixFsType::HamtShard => Ok((node, unixfs)),
+        _ => bail!("The HAMT sub-shard {cid} is not a shard"),
+    }
+}
+
+/// Bits consumed per level and length of the slot prefix of a HAMT shard.
+fn hamt_params(unixfs: &UnixFsData) -> Result<(usize, usize)> {
+    if unixfs.hash_type != Some(MURMUR3_X64_64) {
+        bail!("Unsupported HAMT hash function {:?}", unixfs.hash_type);
+    }
+    match unixfs.fanout {
+        Some(fanout) if fanout > 1 && fanout <= 1024 && fanout.is_power_of_two() => Ok((
+            fanout.trailing_zeros() as usize,
+            format!("{:X}", fanout - 1).len(),
+        )),
+        fanout => bail!("Invalid HAMT fanout {fanout:?}"),
+    }
+}
+
+/// Read `width` bits of `hash` from a bit offset, most significant bit first.
+fn take_bits(hash: &[u8], offset: usize, width: usize) -> Option<u64> {
+    if offset + width > hash.len() * 8 {
+        return None;
+    }
+    Some((offset..offset + width).fold(0, |value, bit| {
+        (value << 1) | ((hash[bit / 8] >> (7 - bit % 8)) & 1) as u64
+    }))
+}
+
+/// First 64 bits of the x64 128 bit murmur3 hash with a zero seed, as used by go-ipfs.
+pub(crate) fn murmur3_x64_64(data: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+    let (mut h1, mut h2) = (0u64, 0u64);
+
+    let blocks = data.chunks_exact(16);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let (mut k1, mut k2) = (0u64, 0u64);
+    for (i, byte) in tail.iter().enumerate() {
+        if i < 8 {
+            k1 |= (*byte as u64) << (8 * i);
+        } else {
+            k2 |= (*byte as u64) << (8 * (i - 8));
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    fmix64(h1).wrapping_add(fmix64(h2))
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
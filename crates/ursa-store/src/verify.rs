@@ -0,0 +1,281 @@
+//! Block hash verification for the [`UrsaStore`].
+//!
+//! Every block written from the network, the origin or a car file goes through
+//! [`UrsaStore::put_block`], which rejects blocks larger than the maximum block
+//! size and blocks whose data does not hash to their cid. [`UrsaStore::scrub`]
+//! re-hashes every block already in the store.
+//!
+//! The dag of a root can be bounded while it is fetched with
+//! [`UrsaStore::limit_dag`]: the blocks reached from the root are counted as
+//! they are written, and refused past the limit, which ends the fetch.
+
+use db::Store;
+use fnv::{FnvHashMap, FnvHashSet};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    store::DefaultParams,
+    Block, Cid, Result,
+};
+use metrics::{increment_counter, Label};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::{ListKeys, UrsaStore};
+
+/// Prefix of the keys under which corrupted blocks are quarantined.
+pub const QUARANTINE_PREFIX: &str = "ursa/quarantine/";
+/// Default maximum size of a block in bytes, the largest block bitswap
+/// implementations are expected to exchange.
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BlockError {
+    #[error("Block {0} does not match its hash")]
+    HashMismatch(Cid),
+    #[error("Block {0} uses an unsupported multihash code {1:#x}")]
+    UnsupportedHash(Cid, u64),
+    #[error("Block {0} of {1} bytes exceeds the maximum block size of {2} bytes")]
+    TooLarge(Cid, usize, usize),
+    #[error("Block {0} exceeds the limit of {2} bytes for the dag of {1}")]
+    DagTooLarge(Cid, Cid, u64),
+}
+
+/// Check that the data of a block hashes to its cid.
+pub fn verify_block(cid: &Cid, data: &[u8]) -> Result<(), BlockError> {
+    let code = cid.hash().code();
+    let hasher = Code::try_from(code).map_err(|_| BlockError::UnsupportedHash(*cid, code))?;
+    if hasher.digest(data).digest() != cid.hash().digest() {
+        return Err(BlockError::HashMismatch(*cid));
+    }
+    Ok(())
+}
+
+/// Bytes written for the dag of a root, see [`UrsaStore::limit_dag`].
+#[derive(Debug)]
+struct DagLimit {
+    root: Cid,
+    max_bytes: u64,
+    written: u64,
+    /// Blocks linked from the blocks written, and not written yet.
+    expected: FnvHashSet<Cid>,
+    /// Blocks counted, which are not counted again.
+    received: FnvHashSet<Cid>,
+}
+
+/// The dag limits of a store, shared with their guards.
+#[derive(Debug, Default)]
+pub(crate) struct DagLimits {
+    next_id: u64,
+    limits: FnvHashMap<u64, DagLimit>,
+}
+
+/// Keeps a limit on the bytes written for a dag until dropped.
+#[derive(Debug)]
+pub struct DagLimitGuard {
+    id: u64,
+    limits: Arc<Mutex<DagLimits>>,
+}
+
+impl Drop for DagLimitGuard {
+    fn drop(&mut self) {
+        self.limits.lock().unwrap().limits.remove(&self.id);
+    }
+}
+
+/// Summary of a scrub run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubReport {
+    /// Number of blocks re-hashed.
+    pub blocks_checked: usize,
+    /// Total size of the checked blocks in bytes.
+    pub bytes_checked: u64,
+    /// Number of blocks linked from a sound block or recorded as a root, but
+    /// missing from the store.
+    pub blocks_missing: usize,
+    /// Blocks whose data does not match their cid.
+    pub corrupted: Vec<Cid>,
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    /// Limit the size of the blocks written to the store.
+    pub fn with_max_block_size(mut self, max_block_size: usize) -> Self {
+        self.max_block_size = max_block_size;
+        self
+    }
+
+    /// Maximum size of a block in bytes.
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    /// Check that a block fits in the maximum block size.
+    pub fn check_block_size(&self, cid: &Cid, size: usize) -> Result<(), BlockError> {
+        if size > self.max_block_size {
+            return Err(BlockError::TooLarge(*cid, size, self.max_block_size));
+        }
+        Ok(())
+    }
+
+    /// Write a block after checking its size and verifying its hash. `source`
+    /// labels the rejected blocks metric, e.g. `bitswap` or `origin`.
+    pub fn put_block(&self, cid: &Cid, data: &[u8], source: &'static str) -> Result<()> {
+        let checked = self
+            .check_block_size(cid, data.len())
+            .and_then(|_| verify_block(cid, data));
+        let checked = checked
+            .map_err(Into::into)
+            .and_then(|_| self.check_dag_limits(cid, data));
+        if let Err(e) = checked {
+            warn!("Rejected a block from {source}: {e}");
+            increment_counter!("store_rejected_blocks", vec![Label::new("source", source)]);
+            return Err(e);
+        }
+        self.record_write(cid);
+        self.db.put_keyed(cid, data)
+    }
+
+    /// Refuse the blocks of the dag under `root` once `max_bytes` of them were
+    /// written, until the guard is dropped. Every block of the dag must be
+    /// written for it to be counted, a block already in the store included.
+    pub fn limit_dag(&self, root: Cid, max_bytes: u64) -> DagLimitGuard {
+        let mut limits = self.dag_limits.lock().unwrap();
+        let id = limits.next_id;
+        limits.next_id += 1;
+        limits.limits.insert(
+            id,
+            DagLimit {
+                root,
+                max_bytes,
+                written: 0,
+                expected: FnvHashSet::from_iter([root]),
+                received: FnvHashSet::default(),
+            },
+        );
+        DagLimitGuard {
+            id,
+            limits: Arc::clone(&self.dag_limits),
+        }
+    }
+
+    /// Count a block against the limits of the dags expecting it.
+    fn check_dag_limits(&self, cid: &Cid, data: &[u8]) -> Result<()> {
+        let mut limits = self.dag_limits.lock().unwrap();
+        let mut links = None;
+        for limit in limits.limits.values_mut() {
+            if !limit.expected.remove(cid) {
+                continue;
+            }
+            limit.written += data.len() as u64;
+            if limit.written > limit.max_bytes {
+                return Err(BlockError::DagTooLarge(*cid, limit.root, limit.max_bytes).into());
+            }
+            limit.received.insert(*cid);
+            if links.is_none() {
+                let mut found = FnvHashSet::default();
+                Block::<DefaultParams>::new_unchecked(*cid, data.to_vec())
+                    .references(&mut found)?;
+                links = Some(found);
+            }
+            for link in links.iter().flatten() {
+                if !limit.received.contains(link) {
+                    limit.expected.insert(*link);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-hash every block of the dag under a root cid.
+    pub fn verify_dag(&self, root_cid: &Cid) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let mut current = vec![*root_cid];
+        let mut seen = FnvHashSet::default();
+
+        while let Some(cid) = current.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            match self.db.get(&cid)? {
+                Some(data) => {
+                    if let Some(block) = self.check_block(cid, data, false, &mut report)? {
+                        block.references(&mut current)?;
+                    }
+                }
+                None => report.blocks_missing += 1,
+            }
+        }
+        Ok(report)
+    }
+
+    /// Re-hash a block, returning it if it matches its cid.
+    fn check_block(
+        &self,
+        cid: Cid,
+        data: Vec<u8>,
+        quarantine: bool,
+        report: &mut ScrubReport,
+    ) -> Result<Option<Block<DefaultParams>>> {
+        report.blocks_checked += 1;
+        report.bytes_checked += data.len() as u64;
+
+        if let Err(e) = verify_block(&cid, &data) {
+            warn!("Scrub found a corrupted block: {e}");
+            increment_counter!("store_corrupted_blocks");
+            if quarantine {
+                self.db.write(format!("{QUARANTINE_PREFIX}{cid}"), &data)?;
+                self.db.delete(cid.to_bytes())?;
+            }
+            report.corrupted.push(cid);
+            return Ok(None);
+        }
+        Ok(Some(Block::new_unchecked(cid, data)))
+    }
+}
+
+impl<S> UrsaStore<S>
+where
+    S: Blockstore + Store + ListKeys + Send + Sync + 'static,
+{
+    /// Re-hash every block of the store, reachable from a known root or not.
+    ///
+    /// Corrupted blocks are reported, and if `quarantine` is set they are moved
+    /// out of the blockstore under [`QUARANTINE_PREFIX`] so they can be fetched again.
+    pub fn scrub(&self, quarantine: bool) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let mut links = FnvHashSet::default();
+        info!("Scrubbing every block of the store");
+
+        self.for_each_block(|cid| {
+            let data = match self.db.get(&cid)? {
+                Some(data) => data,
+                // removed since the keys were listed
+                None => return Ok(()),
+            };
+            // the links of a corrupted block can not be trusted
+            if let Some(block) = self.check_block(cid, data, quarantine, &mut report)? {
+                block.references(&mut links)?;
+            }
+            Ok(())
+        })?;
+
+        let roots = self.roots();
+        for cid in links.iter().chain(&roots) {
+            if !self.db.has(cid)? {
+                report.blocks_missing += 1;
+            }
+        }
+        if quarantine && !report.corrupted.is_empty() {
+            for root_cid in roots {
+                self.refresh_root(&root_cid)?;
+            }
+        }
+        info!("Scrub done: {report:?}");
+        Ok(report)
+    }
+}
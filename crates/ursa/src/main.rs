@@ -7,20 +7,24 @@ use libp2p::Multiaddr;
 use resolve_path::PathResolveExt;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
-use tokio::task;
-use tracing::{error, info};
+use tokio::{sync::oneshot, task, time::timeout};
+use tracing::{error, info, warn};
 use ursa::{cli_error_and_die, wait_until_ctrlc, Cli, Subcommand};
 use ursa_index_provider::engine::ProviderEngine;
-use ursa_network::UrsaService;
+use ursa_network::{NetworkCommand, PeerStore, UrsaService};
 use ursa_rpc_service::{api::NodeNetworkInterface, server::Server};
-use ursa_store::UrsaStore;
+use ursa_store::{Backend, BackendKind, UrsaStore};
 use ursa_telemetry::TelemetryConfig;
 use ursa_tracker::TrackerRegistration;
 
 pub mod config;
 mod ursa;
 
+/// Time given to the network service to save its state on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -40,6 +44,16 @@ async fn main() -> Result<()> {
                     Subcommand::Rpc(cmd) => {
                         cmd.run().await;
                     }
+                    Subcommand::Snapshot(cmd) => {
+                        if let Err(e) = cmd.run(config).await {
+                            cli_error_and_die(&format!("Snapshot error: {e:?}"), 1);
+                        }
+                    }
+                    Subcommand::Store(cmd) => {
+                        if let Err(e) = cmd.run(config).await {
+                            cli_error_and_die(&format!("Store error: {e:?}"), 1);
+                        }
+                    }
                 }
             } else {
                 let UrsaConfig {
@@ -80,13 +94,37 @@ async fn main() -> Result<()> {
                 };
 
                 let db_path = network_config.database_path.resolve().to_path_buf();
-                info!("Opening blockstore database at {:?}", db_path);
+                info!(
+                    "Opening {:?} blockstore database at {:?}",
+                    network_config.database_backend, db_path
+                );
 
-                let db = RocksDb::open(db_path, &RocksDbConfig::default())
-                    .expect("Opening blockstore RocksDB must succeed");
-                let store = Arc::new(UrsaStore::new(Arc::clone(&Arc::new(db))));
+                let db = Backend::open(network_config.database_backend, db_path)
+                    .expect("Opening blockstore database must succeed");
+                let store = Arc::new(
+                    UrsaStore::new(Arc::clone(&Arc::new(db)))
+                        .with_quota(
+                            network_config.max_storage_bytes,
+                            network_config.eviction_policy,
+                        )
+                        .with_max_block_size(network_config.max_block_size),
+                );
+                let access_flush_task = store.flush_accesses_every(Duration::from_secs(
+                    network_config.access_flush_interval.max(1),
+                ));
+
+                let peer_store_path = network_config.peer_store_path.resolve().to_path_buf();
+                info!("Opening peer store database at {:?}", peer_store_path);
+                if network_config.database_backend == BackendKind::Memory {
+                    warn!(
+                        "The memory database backend does not keep the known peers across restarts"
+                    );
+                }
+                let peer_db = Backend::open(network_config.database_backend, peer_store_path)
+                    .expect("Opening peer store database must succeed");
                 let service =
-                    UrsaService::new(keypair.clone(), &network_config, Arc::clone(&store))?;
+                    UrsaService::new(keypair.clone(), &network_config, Arc::clone(&store))?
+                        .with_peer_store(PeerStore::new(Arc::new(peer_db)));
 
                 let provider_db = RocksDb::open(
                     provider_config.database_path.resolve(),
@@ -111,10 +149,11 @@ async fn main() -> Result<()> {
                     server_config.domain.clone(),
                 );
                 let index_provider_router = index_provider_engine.router();
+                let network_sender = service.command_sender();
 
                 // server setup
                 let interface = Arc::new(NodeNetworkInterface::new(
-                    store,
+                    Arc::clone(&store),
                     service.command_sender(),
                     index_provider_engine.command_sender(),
                     server_config.origin.clone(),
@@ -162,8 +201,24 @@ async fn main() -> Result<()> {
 
                 // Gracefully shutdown node & rpc
                 rpc_task.abort();
-                service_task.abort();
                 provider_task.abort();
+                // the service saves the known peers before it stops
+                let (sender, receiver) = oneshot::channel();
+                if network_sender
+                    .send(NetworkCommand::Shutdown { sender })
+                    .is_err()
+                    || timeout(SHUTDOWN_TIMEOUT, receiver).await.is_err()
+                {
+                    warn!("The network service did not shut down in time, aborting it");
+                    service_task.abort();
+                }
+                let _ = service_task.await;
+                access_flush_task.abort();
+                match task::spawn_blocking(move || store.flush_accesses()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Failed to save the content accesses: {e:?}"),
+                    Err(e) => warn!("Failed to save the content accesses: {e:?}"),
+                }
             }
         }
         Err(e) => {
@@ -3,6 +3,7 @@ use anyhow::Result;
 use dirs::home_dir;
 use resolve_path::PathResolveExt;
 use rpc_commands::RpcCommands;
+use snapshot_commands::SnapshotCommands;
 use std::{
     cell::RefCell,
     path::PathBuf,
@@ -14,11 +15,14 @@ use std::{
     thread,
     time::Duration,
 };
+use store_commands::StoreCommands;
 use structopt::StructOpt;
 use tracing::{error, warn};
 
 pub mod identity;
 mod rpc_commands;
+mod snapshot_commands;
+mod store_commands;
 
 /// CLI structure generated when interacting with URSA binary
 #[derive(StructOpt)]
@@ -39,6 +43,16 @@ pub struct Cli {
 pub enum Subcommand {
     #[structopt(name = "rpc", about = "run rpc commands from cli")]
     Rpc(RpcCommands),
+    #[structopt(
+        name = "snapshot",
+        about = "export or import a snapshot of a stopped node"
+    )]
+    Snapshot(SnapshotCommands),
+    #[structopt(
+        name = "store",
+        about = "inspect and maintain the blockstore of a stopped node"
+    )]
+    Store(StoreCommands),
 }
 
 /// CLI options
@@ -1,8 +1,13 @@
 use structopt::StructOpt;
 use tracing::{error, info};
 use ursa_rpc_service::{
-    api::{NetworkGetFileParams, NetworkPutFileParams},
-    client::functions::{get_file, put_file},
+    api::{
+        NetworkGetFileParams, NetworkGetReplicasParams, NetworkImportFileParams,
+        NetworkPutFileParams, StorePinParams,
+    },
+    client::functions::{
+        gc, get_file, get_replicas, import_file, list_content, pin, put_file, unpin,
+    },
 };
 
 #[derive(Debug, StructOpt)]
@@ -12,6 +17,13 @@ pub enum RpcCommands {
         #[structopt(about = "The path to the file")]
         path: String,
     },
+    #[structopt(about = "chunk a file or a directory into a UnixFS dag and put it on the node")]
+    Import {
+        #[structopt(about = "The path to the file or directory")]
+        path: String,
+        #[structopt(long, about = "Chunker to split files with, size-{size} or rabin")]
+        chunker: Option<String>,
+    },
     #[structopt(
         about = "get the file from network for a given root cid and store it on given path"
     )]
@@ -20,7 +32,30 @@ pub enum RpcCommands {
         cid: String,
         #[structopt(about = "The path to store the file")]
         path: String,
+        #[structopt(long, about = "Version of the car file to write, 1 or 2")]
+        car_version: Option<u64>,
+    },
+    #[structopt(
+        about = "list the peers that confirmed they store a replica of a root put on the node"
+    )]
+    Replicas {
+        #[structopt(about = "root cid put on the node")]
+        cid: String,
     },
+    #[structopt(about = "pin a root cid so it is never garbage collected")]
+    Pin {
+        #[structopt(about = "root cid to pin")]
+        cid: String,
+    },
+    #[structopt(about = "unpin a root cid so it can be garbage collected")]
+    Unpin {
+        #[structopt(about = "root cid to unpin")]
+        cid: String,
+    },
+    #[structopt(about = "remove all the blocks that are not reachable from a pinned root")]
+    Gc,
+    #[structopt(about = "list the content stored on the node")]
+    Ls,
 }
 
 impl RpcCommands {
@@ -31,18 +66,42 @@ impl RpcCommands {
                     path: path.to_string(),
                 };
                 match put_file(params).await {
-                    Ok(file) => {
-                        info!("Put car file done: {:?}", file);
+                    Ok(results) => {
+                        for root in results {
+                            match root.error {
+                                None => info!("Put root {} of {} bytes", root.cid, root.size),
+                                Some(e) => error!("Failed to put root {}: {e}", root.cid),
+                            }
+                        }
                     }
                     Err(_e) => {
                         error!("There was an error while calling the rpc server. Please Check Server Logs")
                     }
                 };
             }
-            Self::Get { cid, path } => {
+            Self::Import { path, chunker } => {
+                let params = NetworkImportFileParams {
+                    path: path.to_string(),
+                    chunker: chunker.clone(),
+                };
+                match import_file(params).await {
+                    Ok(root_cid) => {
+                        info!("Import done, the root cid is: {root_cid}");
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
+            Self::Get {
+                cid,
+                path,
+                car_version,
+            } => {
                 let params = NetworkGetFileParams {
                     path: path.to_string(),
                     cid: cid.to_string(),
+                    car_version: *car_version,
                 };
                 match get_file(params).await {
                     Ok(_result) => {
@@ -53,6 +112,81 @@ impl RpcCommands {
                     }
                 };
             }
+            Self::Replicas { cid } => {
+                let params = NetworkGetReplicasParams {
+                    cid: cid.to_string(),
+                };
+                match get_replicas(params).await {
+                    Ok(replicas) => {
+                        info!("{} peers store a replica of {cid}", replicas.len());
+                        for peer in replicas {
+                            info!("{peer}");
+                        }
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
+            Self::Pin { cid } => {
+                let params = StorePinParams {
+                    cid: cid.to_string(),
+                };
+                match pin(params).await {
+                    Ok(_result) => {
+                        info!("pinned {cid}");
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
+            Self::Unpin { cid } => {
+                let params = StorePinParams {
+                    cid: cid.to_string(),
+                };
+                match unpin(params).await {
+                    Ok(true) => {
+                        info!("unpinned {cid}");
+                    }
+                    Ok(false) => {
+                        info!("{cid} was not pinned");
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
+            Self::Gc => {
+                match gc().await {
+                    Ok(stats) => {
+                        info!("Garbage collection done: {:?}", stats);
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
+            Self::Ls => {
+                match list_content().await {
+                    Ok(content) => {
+                        for content in content {
+                            info!(
+                                "{} size: {} blocks: {} source: {:?} hits: {} pinned: {}",
+                                content.cid,
+                                content.meta.size,
+                                content.meta.blocks,
+                                content.meta.source,
+                                content.meta.hits,
+                                content.pinned
+                            );
+                        }
+                    }
+                    Err(_e) => {
+                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    }
+                };
+            }
         }
     }
 }
@@ -0,0 +1,78 @@
+use crate::config::UrsaConfig;
+use anyhow::{bail, Result};
+use db::{rocks::RocksDb, rocks_config::RocksDbConfig};
+use futures::io::AllowStdIo;
+use resolve_path::PathResolveExt;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
+use structopt::StructOpt;
+use tracing::info;
+use ursa_index_provider::provider::{read_head, write_head};
+use ursa_store::{export_snapshot, import_snapshot, Backend, BackendKind, UrsaStore};
+
+/// Snapshots are taken from the databases directly, the node must be stopped.
+#[derive(Debug, StructOpt)]
+pub enum SnapshotCommands {
+    #[structopt(about = "export the blockstore and the index provider ads of a stopped node")]
+    Export {
+        #[structopt(about = "The path of the snapshot file to write")]
+        path: PathBuf,
+    },
+    #[structopt(about = "import a snapshot into the empty databases of a stopped node")]
+    Import {
+        #[structopt(about = "The path of the snapshot file")]
+        path: PathBuf,
+    },
+}
+
+impl SnapshotCommands {
+    pub async fn run(&self, config: UrsaConfig) -> Result<()> {
+        if config.network_config.database_backend == BackendKind::Memory {
+            bail!("The memory backend has no database to work on");
+        }
+        let db = Backend::open(
+            config.network_config.database_backend,
+            config.network_config.database_path.resolve(),
+        )?;
+        let store =
+            UrsaStore::new(Arc::new(db)).with_max_block_size(config.network_config.max_block_size);
+        let provider_db = RocksDb::open(
+            config.provider_config.database_path.resolve(),
+            &RocksDbConfig::default(),
+        )?;
+        let provider_store = UrsaStore::new(Arc::new(provider_db));
+
+        match self {
+            Self::Export { path } => {
+                let head = read_head(provider_store.blockstore())?;
+                let mut writer = AllowStdIo::new(BufWriter::new(File::create(path)?));
+                let manifest = export_snapshot(&store, &provider_store, head, &mut writer).await?;
+                info!(
+                    "Exported {} roots and {} blocks to {path:?}",
+                    manifest.roots.len(),
+                    manifest.content_blocks + manifest.provider_blocks
+                );
+            }
+            Self::Import { path } => {
+                if read_head(provider_store.blockstore())?.is_some() {
+                    bail!("Snapshots can only be imported into an empty provider store");
+                }
+                let reader = AllowStdIo::new(BufReader::new(File::open(path)?));
+                let manifest = import_snapshot(&store, &provider_store, reader).await?;
+                if let Some(head) = manifest.provider_head {
+                    write_head(provider_store.blockstore(), &head)?;
+                }
+                info!(
+                    "Imported {} roots and {} blocks from {path:?}",
+                    manifest.roots.len(),
+                    manifest.content_blocks + manifest.provider_blocks
+                );
+            }
+        }
+        Ok(())
+    }
+}
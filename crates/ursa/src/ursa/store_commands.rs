@@ -0,0 +1,183 @@
+use crate::config::UrsaConfig;
+use anyhow::{bail, Result};
+use futures::io::AllowStdIo;
+use libipld::Cid;
+use resolve_path::PathResolveExt;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
+use structopt::StructOpt;
+use tracing::{info, warn};
+use ursa_store::{Backend, BackendKind, CarVersion, UrsaStore};
+
+/// Store maintenance works on the blockstore database directly, the node must be stopped.
+#[derive(Debug, StructOpt)]
+pub enum StoreCommands {
+    #[structopt(about = "import a car file and pin its complete roots")]
+    Import {
+        #[structopt(about = "The path of the car file")]
+        path: PathBuf,
+    },
+    #[structopt(about = "export the dag of a root cid as a car file")]
+    Export {
+        #[structopt(about = "root cid of the dag to export")]
+        cid: Cid,
+        #[structopt(about = "The path of the car file to write")]
+        path: PathBuf,
+        #[structopt(long, about = "Version of the car file to write, 1 or 2")]
+        car_version: Option<u64>,
+    },
+    #[structopt(about = "list the roots in the store with their sizes")]
+    Ls,
+    #[structopt(about = "show the number of roots and the bytes used by the store")]
+    Stat,
+    #[structopt(about = "verify the hash of every block of a dag")]
+    Verify {
+        #[structopt(about = "root cid of the dag to verify")]
+        cid: Cid,
+    },
+    #[structopt(about = "verify the hash of every block in the store")]
+    Scrub {
+        #[structopt(long, about = "Move the corrupted blocks out of the blockstore")]
+        quarantine: bool,
+    },
+    #[structopt(about = "remove a root and the blocks of its dag not shared with other roots")]
+    Rm {
+        #[structopt(about = "root cid to remove")]
+        cid: Cid,
+    },
+    #[structopt(about = "remove all the blocks that are not reachable from a pinned root")]
+    Gc,
+}
+
+impl StoreCommands {
+    pub async fn run(&self, config: UrsaConfig) -> Result<()> {
+        let network_config = config.network_config;
+        if network_config.database_backend == BackendKind::Memory {
+            bail!("The memory backend has no database to work on");
+        }
+        let db = Backend::open(
+            network_config.database_backend,
+            network_config.database_path.resolve(),
+        )?;
+        let store = UrsaStore::new(Arc::new(db))
+            .with_quota(
+                network_config.max_storage_bytes,
+                network_config.eviction_policy,
+            )
+            .with_max_block_size(network_config.max_block_size);
+
+        match self {
+            Self::Import { path } => {
+                let reader = AllowStdIo::new(BufReader::new(File::open(path)?));
+                for root_cid in store.import_car(reader).await? {
+                    let status = store.dag_status(&root_cid)?;
+                    if status.is_complete() {
+                        store.pin(&root_cid)?;
+                        info!("Imported and pinned {root_cid}");
+                    } else {
+                        warn!(
+                            "Not pinning {root_cid}, its dag is missing {} blocks",
+                            status.missing.len()
+                        );
+                    }
+                }
+                let evicted = store.evict()?;
+                if !evicted.is_empty() {
+                    info!(
+                        "Evicted {} roots to fit in the storage quota",
+                        evicted.len()
+                    );
+                }
+            }
+            Self::Export {
+                cid,
+                path,
+                car_version,
+            } => {
+                let version = match car_version {
+                    Some(version) => CarVersion::try_from(*version)?,
+                    None => CarVersion::default(),
+                };
+                let mut writer = AllowStdIo::new(BufWriter::new(File::create(path)?));
+                store.export_car(cid, version, &mut writer).await?;
+                info!("Exported {cid} to {path:?}");
+            }
+            Self::Ls => {
+                let mut roots = store.roots_meta();
+                roots.sort_by_key(|(_, meta)| meta.inserted_at);
+                for (cid, meta) in roots {
+                    info!(
+                        "{cid} size: {} car size: {} blocks: {} complete: {} source: {:?} hits: {} pinned: {}",
+                        meta.size,
+                        meta.car_size,
+                        meta.blocks,
+                        meta.complete,
+                        meta.source,
+                        meta.hits,
+                        store.is_pinned(&cid)
+                    );
+                }
+            }
+            Self::Stat => {
+                let roots = store.roots_meta();
+                let incomplete = roots.iter().filter(|(_, meta)| !meta.complete).count();
+                info!(
+                    "{:?} store at {:?}",
+                    network_config.database_backend, network_config.database_path
+                );
+                info!(
+                    "roots: {} pinned: {} incomplete: {incomplete} used bytes: {} quota: {}",
+                    roots.len(),
+                    store.pins().len(),
+                    store.used_bytes(),
+                    network_config.max_storage_bytes
+                );
+            }
+            Self::Verify { cid } => {
+                let report = store.verify_dag(cid)?;
+                if !report.corrupted.is_empty() {
+                    bail!("Corrupted blocks under {cid}: {:?}", report.corrupted);
+                }
+                if report.blocks_missing > 0 {
+                    bail!("{} blocks are missing under {cid}", report.blocks_missing);
+                }
+                info!(
+                    "Verified {} blocks ({} bytes) under {cid}",
+                    report.blocks_checked, report.bytes_checked
+                );
+            }
+            Self::Scrub { quarantine } => {
+                let report = store.scrub(*quarantine)?;
+                info!(
+                    "Scrubbed {} blocks ({} bytes), {} missing blocks, {} corrupted blocks",
+                    report.blocks_checked,
+                    report.bytes_checked,
+                    report.blocks_missing,
+                    report.corrupted.len()
+                );
+                for cid in &report.corrupted {
+                    warn!(
+                        "Corrupted block {cid}{}",
+                        if *quarantine { ", quarantined" } else { "" }
+                    );
+                }
+            }
+            Self::Rm { cid } => {
+                let stats = store.remove(cid)?;
+                info!(
+                    "Removed {cid}: {} blocks, {} bytes",
+                    stats.blocks_removed, stats.bytes_freed
+                );
+            }
+            Self::Gc => {
+                let stats = store.gc()?;
+                info!("Garbage collection done: {stats:?}");
+            }
+        }
+        Ok(())
+    }
+}